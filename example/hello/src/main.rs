@@ -5,7 +5,8 @@ fn main() {
     kroute(vec![Box::new(HelloKontroller {
         address: "/hello".to_string(),
         method: Method::Get,
-    })]);
+    })])
+    .expect("kong node failed");
 }
 
 /// Hello API endpoint controller
@@ -0,0 +1,267 @@
+//! 🗒️ `kong` access log
+//!
+//! Every request handled by a node is recorded in the access log, a
+//! file of the working directory kept apart from the application
+//! `LOG`. Entries are written in the
+//! [Common Log Format](https://httpd.apache.org/docs/current/logs.html#common),
+//! the Combined Log Format or as JSON lines:
+//!
+//! ```toml
+//! [access_log]
+//! format = "combined"
+//! file = "ACCESS_LOG"
+//! ```
+//!
+//! ```text
+//! 127.0.0.1 - firephoenix [19/Oct/2026:13:55:36 +0000] "GET /hello HTTP/1.1" 200 12 "-" "curl/8.0" 1534 4f1c2a9e0b7d43c58e6f1a2b3c4d5e6f
+//! ```
+//!
+//! The client IP address, kpassport username (`-` for anonymous
+//! requests), time, request line, status and response size in bytes are
+//! followed by the referer and user agent in the combined format. The
+//! last fields are the time spent handling the request, in microseconds,
+//! and the request ID (see [`crate::request_id`]).
+
+use crate::defaults;
+use crate::konfig::Konfig;
+use crate::rotation::RotatingFile;
+use crate::KError;
+use chrono::{DateTime, SecondsFormat, Utc};
+use serde::Deserialize;
+use serde_json::json;
+use std::net::IpAddr;
+use std::time::Duration;
+
+/// 🗒️ Format of access log entries
+#[derive(Deserialize, Clone, Copy, PartialEq, Eq, Debug, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum AccessLogFormat {
+    /// Common Log Format
+    Common,
+    /// Combined Log Format, the common format with the referer and
+    /// user agent
+    #[default]
+    Combined,
+    /// One JSON object per line
+    Json,
+}
+
+/// 🗒️ A handled request
+#[derive(Clone, Debug)]
+pub struct AccessEntry {
+    /// Time the request was received
+    pub time: DateTime<Utc>,
+    /// IP address of the client
+    pub client_ip: IpAddr,
+    /// Kpassport username of authenticated requests
+    pub username: Option<String>,
+    /// HTTP method
+    pub method: String,
+    /// Requested URL, with the query string
+    pub url: String,
+    /// Response status code
+    pub status: u16,
+    /// Size of the response body in bytes, if known
+    pub size: Option<usize>,
+    /// `Referer` request header
+    pub referer: Option<String>,
+    /// `User-Agent` request header
+    pub user_agent: Option<String>,
+    /// Time spent handling the request
+    pub latency: Duration,
+    /// ID of the request, see [`crate::request_id`]
+    pub request_id: Option<String>,
+}
+
+impl AccessEntry {
+    /// Format the entry
+    pub fn format(&self, format: AccessLogFormat) -> String {
+        let latency = self.latency.as_micros();
+        let request_id = self.request_id.as_deref().unwrap_or("-");
+
+        match format {
+            AccessLogFormat::Common => format!("{} {latency} {request_id}", self.common()),
+            AccessLogFormat::Combined => format!(
+                "{} \"{}\" \"{}\" {latency} {request_id}",
+                self.common(),
+                quoted(self.referer.as_deref()),
+                quoted(self.user_agent.as_deref()),
+            ),
+            AccessLogFormat::Json => json!({
+                "time": self.time.to_rfc3339_opts(SecondsFormat::Millis, true),
+                "client_ip": self.client_ip.to_string(),
+                "username": self.username,
+                "method": self.method,
+                "url": self.url,
+                "status": self.status,
+                "size": self.size,
+                "referer": self.referer,
+                "user_agent": self.user_agent,
+                "latency_us": latency as u64,
+                "request_id": self.request_id,
+            })
+            .to_string(),
+        }
+    }
+
+    /// Fields of the Common Log Format
+    fn common(&self) -> String {
+        let size = match self.size {
+            Some(size) => size.to_string(),
+            None => "-".to_string(),
+        };
+
+        format!(
+            "{} - {} [{}] \"{} {} HTTP/1.1\" {} {size}",
+            self.client_ip,
+            self.username.as_deref().unwrap_or("-"),
+            self.time.format("%d/%b/%Y:%H:%M:%S %z"),
+            self.method,
+            quoted(Some(&self.url)),
+            self.status,
+        )
+    }
+}
+
+/// Value of a quoted field, `-` if there is no value
+fn quoted(value: Option<&str>) -> String {
+    match value {
+        Some(value) if !value.is_empty() => value
+            .chars()
+            .flat_map(|c| match c {
+                '"' => vec!['\\', '"'],
+                '\\' => vec!['\\', '\\'],
+                c if c.is_control() => vec![' '],
+                c => vec![c],
+            })
+            .collect(),
+        _ => "-".to_string(),
+    }
+}
+
+/// 🗒️ Access log of a node
+pub struct AccessLog {
+    format: AccessLogFormat,
+    file: Option<RotatingFile>,
+}
+
+impl AccessLog {
+    /// Open the access log of a configuration, the file is created if
+    /// it does not exist. Nothing is recorded if the configuration has
+    /// no `access_log` section.
+    pub fn new(config: &Konfig) -> Result<Self, KError> {
+        let Some(access_log) = &config.access_log else {
+            return Ok(AccessLog {
+                format: AccessLogFormat::default(),
+                file: None,
+            });
+        };
+
+        let file_name = access_log
+            .file
+            .as_deref()
+            .unwrap_or(defaults::ACCESS_LOG_FILE);
+        let path = std::path::Path::new(config.working_dir()).join(file_name);
+        let file = RotatingFile::open(&path, config.log_rotation.as_ref()).map_err(|source| {
+            KError::LogFile {
+                path: Some(path.clone()),
+                source,
+            }
+        })?;
+
+        Ok(AccessLog {
+            format: access_log.format.unwrap_or_default(),
+            file: Some(file),
+        })
+    }
+
+    /// Whether requests are recorded
+    pub fn is_enabled(&self) -> bool {
+        self.file.is_some()
+    }
+
+    /// Record a handled request
+    pub fn record(&mut self, entry: &AccessEntry) -> Result<(), KError> {
+        if let Some(file) = &mut self.file {
+            file.write_line(&entry.format(self.format))
+                .map_err(|source| KError::LogFile {
+                    path: Some(file.path().to_path_buf()),
+                    source,
+                })?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use chrono::TimeZone;
+
+    #[test]
+    fn format_entries() {
+        let entry = AccessEntry {
+            time: Utc.with_ymd_and_hms(2026, 10, 19, 13, 55, 36).unwrap(),
+            client_ip: IpAddr::from([127, 0, 0, 1]),
+            username: Some("firephoenix".to_string()),
+            method: "GET".to_string(),
+            url: "/hello?name=\"kong\"".to_string(),
+            status: 200,
+            size: Some(12),
+            referer: None,
+            user_agent: Some("curl/8.0".to_string()),
+            latency: Duration::from_micros(1534),
+            request_id: Some("abc".to_string()),
+        };
+
+        assert_eq!(
+            entry.format(AccessLogFormat::Common),
+            r#"127.0.0.1 - firephoenix [19/Oct/2026:13:55:36 +0000] "GET /hello?name=\"kong\" HTTP/1.1" 200 12 1534 abc"#
+        );
+        assert_eq!(
+            entry.format(AccessLogFormat::Combined),
+            r#"127.0.0.1 - firephoenix [19/Oct/2026:13:55:36 +0000] "GET /hello?name=\"kong\" HTTP/1.1" 200 12 "-" "curl/8.0" 1534 abc"#
+        );
+
+        let json: serde_json::Value =
+            serde_json::from_str(&entry.format(AccessLogFormat::Json)).unwrap();
+        assert_eq!(json["client_ip"], "127.0.0.1");
+        assert_eq!(json["username"], "firephoenix");
+        assert_eq!(json["status"], 200);
+        assert_eq!(json["referer"], serde_json::Value::Null);
+        assert_eq!(json["latency_us"], 1534);
+    }
+
+    #[test]
+    fn record_requests() {
+        use crate::testing::{konfig, TestNode, TestRequest};
+
+        let working_dir = std::env::temp_dir().join(format!("kong-access-{}/", std::process::id()));
+        let _ = std::fs::remove_dir_all(&working_dir);
+        let node = TestNode::new(
+            konfig(&format!(
+                r#"
+                working_directory = "{}"
+
+                [access_log]
+                format = "json"
+                "#,
+                working_dir.display()
+            )),
+            vec![],
+        )
+        .unwrap();
+
+        node.send(TestRequest::get("/nowhere?q=1").header("User-Agent", "kong-test"))
+            .assert_status(404);
+
+        let log = std::fs::read_to_string(working_dir.join(defaults::ACCESS_LOG_FILE)).unwrap();
+        let entry: serde_json::Value = serde_json::from_str(log.lines().last().unwrap()).unwrap();
+        assert_eq!(entry["url"], "/nowhere?q=1");
+        assert_eq!(entry["status"], 404);
+        assert_eq!(entry["user_agent"], "kong-test");
+        assert!(entry["size"].as_u64().unwrap() > 0);
+        assert!(entry["username"].is_null());
+        assert_eq!(entry["request_id"].as_str().unwrap().len(), 32);
+    }
+}
@@ -0,0 +1,779 @@
+//! 🔌 `kong` HTTP/1.1 connections
+//!
+//! Listeners hand every connection they accept to [`serve`], which
+//! reads the requests off the socket, buffers their bodies and writes
+//! the responses back. A request is received in full, before the
+//! deadline set by the request timeout, before it is handled: a slow
+//! client only ever holds up its own connection, never the node.
+//! Handlers are given the length of the buffered body in its
+//! `Content-Length` header, chunked bodies included.
+//!
+//! Connections are kept alive between requests, until the client
+//! closes them, they are idle for the request timeout or the node
+//! stops.
+
+use crate::problem::status_title;
+use crate::Shutdown;
+use chrono::Utc;
+use std::cell::Cell;
+use std::io::{self, BufWriter, Read, Write};
+use std::net::{SocketAddr, TcpStream};
+use std::time::{Duration, Instant};
+
+/// Size of the chunks requests are read and response bodies written in
+const CHUNK_SIZE: usize = 8 * 1024;
+
+/// Room for the request line and the separators of the header fields,
+/// on top of the maximum size of the headers
+const HEAD_OVERHEAD: usize = 8 * 1024;
+
+/// How often idle connections check whether the node is stopping
+const POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+thread_local! {
+    /// Client of the connection served by the thread
+    static PEER: Cell<Option<Peer>> = const { Cell::new(None) };
+}
+
+/// Client on the other end of a connection
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub(crate) enum Peer {
+    /// TCP client and its address
+    Tcp(SocketAddr),
+    /// Unix domain socket client, and the user ID of its process if the
+    /// platform tells it
+    Unix(Option<u32>),
+}
+
+/// Client of the connection served by the current thread, `None`
+/// outside of connections (in test nodes)
+pub(crate) fn peer() -> Option<Peer> {
+    PEER.with(Cell::get)
+}
+
+/// Limits of a listener and of the requests received on its
+/// connections
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct Limits {
+    /// Number of connections served at once
+    pub(crate) max_connections: usize,
+    /// Largest request body, longer bodies are not received and the
+    /// request is handed over with its announced `Content-Length` (or
+    /// its body cut one byte past the limit, and that length) for
+    /// kroute to reject it
+    pub(crate) max_body_size: u64,
+    /// Largest size of all the header fields
+    pub(crate) max_header_size: usize,
+    /// Time a client has to send a request, also the longest a
+    /// connection is kept idle
+    pub(crate) timeout: Duration,
+    /// Time the connections open when the listener stops are given to
+    /// finish
+    pub(crate) shutdown_timeout: Duration,
+}
+
+/// Socket a connection is served on
+pub(crate) trait Socket: Read + Write {
+    /// Set the read and write timeouts of the socket
+    fn set_timeout(&self, timeout: Duration) -> io::Result<()>;
+
+    /// Close the connection, once its last response is sent
+    fn close(&mut self) {}
+}
+
+impl Socket for TcpStream {
+    fn set_timeout(&self, timeout: Duration) -> io::Result<()> {
+        self.set_read_timeout(Some(timeout))?;
+        self.set_write_timeout(Some(timeout))
+    }
+}
+
+#[cfg(unix)]
+impl Socket for std::os::unix::net::UnixStream {
+    fn set_timeout(&self, timeout: Duration) -> io::Result<()> {
+        self.set_read_timeout(Some(timeout))?;
+        self.set_write_timeout(Some(timeout))
+    }
+}
+
+/// Serve the requests of a connection with `handler`, until the
+/// connection is closed. `peer` is the client and `secure` whether the
+/// connection is encrypted.
+pub(crate) fn serve<S, F>(
+    socket: S,
+    peer: Peer,
+    secure: bool,
+    handler: &F,
+    limits: Limits,
+    shutdown: &Shutdown,
+) where
+    S: Socket,
+    F: Fn(&rouille::Request) -> rouille::Response,
+{
+    let from = match peer {
+        Peer::Tcp(address) => address,
+        // Unix domain socket clients have no IP address
+        Peer::Unix(_) => SocketAddr::from(([0, 0, 0, 0], 0)),
+    };
+    let mut connection = Connection {
+        socket,
+        buffer: Vec::new(),
+    };
+
+    PEER.with(|current| current.set(Some(peer)));
+    connection.serve(from, secure, handler, limits, shutdown);
+    PEER.with(|current| current.set(None));
+    connection.socket.close();
+}
+
+/// Why a request could not be received
+enum Failure {
+    /// The connection is closed, or broken
+    Closed,
+    /// The request is answered with this status, and the connection
+    /// closed
+    Respond(u16),
+}
+
+/// Request received on a connection
+struct Received {
+    request: rouille::Request,
+    /// Whether the client uses HTTP/1.1
+    http11: bool,
+    /// Whether the connection can be kept alive after the response
+    keep_alive: bool,
+}
+
+/// Request line and header fields of a request
+struct Head {
+    method: String,
+    url: String,
+    http11: bool,
+    headers: Vec<(String, String)>,
+}
+
+impl Head {
+    /// Values of the header field `name`
+    fn header<'a>(&'a self, name: &'a str) -> impl Iterator<Item = &'a str> {
+        self.headers
+            .iter()
+            .filter(move |(field, _)| field.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
+
+    /// Whether a comma separated header field contains `token`
+    fn has_token(&self, name: &str, token: &str) -> bool {
+        self.header(name)
+            .flat_map(|value| value.split(','))
+            .any(|value| value.trim().eq_ignore_ascii_case(token))
+    }
+}
+
+/// Whether `bytes` contain a line feed that is not preceded by a
+/// carriage return, lines of requests have to end with CRLF
+fn bare_lf(bytes: &[u8]) -> bool {
+    bytes
+        .iter()
+        .enumerate()
+        .any(|(i, &byte)| byte == b'\n' && (i == 0 || bytes[i - 1] != b'\r'))
+}
+
+/// Value of a `Content-Length` header field, `None` unless it only has
+/// digits (RFC 9110)
+fn content_length(value: &str) -> Option<u64> {
+    if value.is_empty() || !value.bytes().all(|byte| byte.is_ascii_digit()) {
+        return None;
+    }
+    value.parse().ok()
+}
+
+/// Size of a chunk from its chunk line, `None` unless the size only has
+/// hexadecimal digits (RFC 9112). Chunk extensions are ignored.
+fn chunk_size(line: &[u8]) -> Option<u64> {
+    let size = match line.iter().position(|&byte| byte == b';') {
+        // whitespace is allowed before the extensions
+        Some(end) => line[..end].trim_ascii_end(),
+        None => line,
+    };
+    if size.is_empty() || !size.iter().all(u8::is_ascii_hexdigit) {
+        return None;
+    }
+    u64::from_str_radix(std::str::from_utf8(size).ok()?, 16).ok()
+}
+
+/// Parse the head of a request, `None` if it is malformed
+fn parse_head(head: &[u8]) -> Option<Head> {
+    let head = std::str::from_utf8(head).ok()?;
+    let mut lines = head.split("\r\n");
+    // bare line feeds and carriage returns are rejected (RFC 9112)
+    if lines.clone().any(|line| line.contains(['\r', '\n'])) {
+        return None;
+    }
+
+    let mut request_line = lines.next()?.split(' ');
+    let method = request_line.next().filter(|method| !method.is_empty())?;
+    let url = request_line.next().filter(|url| !url.is_empty())?;
+    let http11 = match request_line.next()? {
+        "HTTP/1.1" => true,
+        "HTTP/1.0" => false,
+        _ => return None,
+    };
+    if request_line.next().is_some() {
+        return None;
+    }
+
+    let mut headers = Vec::new();
+    for line in lines.filter(|line| !line.is_empty()) {
+        let (name, value) = line.split_once(':')?;
+        // obsolete line folding and whitespace before the colon are
+        // rejected (RFC 9112)
+        if name.is_empty() || name.contains([' ', '\t']) || line.starts_with([' ', '\t']) {
+            return None;
+        }
+        headers.push((name.to_string(), value.trim().to_string()));
+    }
+
+    Some(Head {
+        method: method.to_string(),
+        url: url.to_string(),
+        http11,
+        headers,
+    })
+}
+
+/// Whether a socket error is a timeout
+fn is_timeout(error: &io::Error) -> bool {
+    matches!(
+        error.kind(),
+        io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut
+    )
+}
+
+/// Reason phrase of a response status
+fn reason(status: u16) -> &'static str {
+    match status {
+        100 => "Continue",
+        101 => "Switching Protocols",
+        200 => "OK",
+        201 => "Created",
+        202 => "Accepted",
+        204 => "No Content",
+        206 => "Partial Content",
+        301 => "Moved Permanently",
+        302 => "Found",
+        303 => "See Other",
+        304 => "Not Modified",
+        307 => "Temporary Redirect",
+        308 => "Permanent Redirect",
+        _ if status < 400 => "",
+        _ => status_title(status),
+    }
+}
+
+/// Connection to a client
+struct Connection<S> {
+    socket: S,
+    /// Bytes received, that are not part of a request yet
+    buffer: Vec<u8>,
+}
+
+impl<S: Socket> Connection<S> {
+    /// Serve the requests of the connection, until it has to be closed
+    fn serve<F>(
+        &mut self,
+        from: SocketAddr,
+        secure: bool,
+        handler: &F,
+        limits: Limits,
+        shutdown: &Shutdown,
+    ) where
+        F: Fn(&rouille::Request) -> rouille::Response,
+    {
+        while self.wait(limits.timeout, shutdown) {
+            let deadline = Instant::now() + limits.timeout;
+            let received = match self.receive(from, secure, limits, deadline) {
+                Ok(received) => received,
+                Err(Failure::Closed) => return,
+                Err(Failure::Respond(status)) => {
+                    let response = rouille::Response::text(status_title(status))
+                        .with_status_code(status)
+                        .with_additional_header("Connection", "close");
+                    let _ = self.respond(response, false, true, false, limits.timeout);
+                    return;
+                }
+            };
+
+            let response = handler(&received.request);
+            let keep_alive = received.keep_alive
+                && !shutdown.is_stopped()
+                && !response.headers.iter().any(|(name, value)| {
+                    name.eq_ignore_ascii_case("Connection") && value.eq_ignore_ascii_case("close")
+                });
+
+            let head = received.request.method() == "HEAD";
+            let sent = self.respond(response, head, received.http11, keep_alive, limits.timeout);
+            if sent.is_err() || !keep_alive {
+                return;
+            }
+        }
+    }
+
+    /// Receive more bytes before `deadline`, `false` if the client
+    /// closed the connection
+    fn fill(&mut self, deadline: Instant) -> io::Result<bool> {
+        let timeout = deadline.saturating_duration_since(Instant::now());
+        if timeout.is_zero() {
+            return Err(io::ErrorKind::TimedOut.into());
+        }
+        self.socket.set_timeout(timeout)?;
+
+        let mut chunk = [0u8; CHUNK_SIZE];
+        loop {
+            match self.socket.read(&mut chunk) {
+                Ok(0) => return Ok(false),
+                Ok(n) => {
+                    self.buffer.extend_from_slice(&chunk[..n]);
+                    return Ok(true);
+                }
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+                Err(e) => return Err(e),
+            }
+        }
+    }
+
+    /// Receive more bytes before `deadline`, as part of a request
+    fn fill_request(&mut self, deadline: Instant) -> Result<(), Failure> {
+        match self.fill(deadline) {
+            Ok(true) => Ok(()),
+            Ok(false) => Err(Failure::Closed),
+            Err(e) if is_timeout(&e) => Err(Failure::Respond(408)),
+            Err(_) => Err(Failure::Closed),
+        }
+    }
+
+    /// Wait for the next request, for at most `timeout`. Returns
+    /// whether there is one.
+    fn wait(&mut self, timeout: Duration, shutdown: &Shutdown) -> bool {
+        let idle = Instant::now() + timeout;
+
+        // empty lines before a request are ignored (RFC 9112)
+        loop {
+            while self.buffer.starts_with(b"\r\n") {
+                self.buffer.drain(..2);
+            }
+            if !self.buffer.is_empty() && self.buffer != b"\r" {
+                return true;
+            }
+            if shutdown.is_stopped() {
+                return false;
+            }
+
+            match self.fill((Instant::now() + POLL_INTERVAL).min(idle)) {
+                Ok(true) => {}
+                Ok(false) => return false,
+                Err(e) if is_timeout(&e) && Instant::now() < idle => {}
+                Err(_) => return false,
+            }
+        }
+    }
+
+    /// Receive the next request
+    fn receive(
+        &mut self,
+        from: SocketAddr,
+        secure: bool,
+        limits: Limits,
+        deadline: Instant,
+    ) -> Result<Received, Failure> {
+        // request line and header fields
+        let max_head_size = limits.max_header_size + HEAD_OVERHEAD;
+        let end = loop {
+            if let Some(end) = self.buffer.windows(4).position(|w| w == b"\r\n\r\n") {
+                break end;
+            }
+            // the end of the head is never found if the client ends
+            // its lines with bare line feeds
+            if bare_lf(&self.buffer) {
+                return Err(Failure::Respond(400));
+            }
+            if self.buffer.len() > max_head_size {
+                return Err(Failure::Respond(431));
+            }
+            self.fill_request(deadline)?;
+        };
+        let head: Vec<u8> = self.buffer.drain(..end + 4).collect();
+        let mut head = parse_head(&head[..end]).ok_or(Failure::Respond(400))?;
+
+        let (body, complete) = self.body(&mut head, limits.max_body_size, deadline)?;
+        let keep_alive = complete
+            && if head.http11 {
+                !head.has_token("Connection", "close")
+            } else {
+                head.has_token("Connection", "keep-alive")
+            };
+
+        let request = if secure {
+            rouille::Request::fake_https_from(from, head.method, head.url, head.headers, body)
+        } else {
+            rouille::Request::fake_http_from(from, head.method, head.url, head.headers, body)
+        };
+        Ok(Received {
+            request,
+            http11: head.http11,
+            keep_alive,
+        })
+    }
+
+    /// Receive the body of a request, at most one byte more than
+    /// `max_body_size`. Returns the body and whether all of it was
+    /// received. The transfer coding of a chunked body is replaced by
+    /// the length received.
+    fn body(
+        &mut self,
+        head: &mut Head,
+        max_body_size: u64,
+        deadline: Instant,
+    ) -> Result<(Vec<u8>, bool), Failure> {
+        if head.header("Transfer-Encoding").next().is_some() {
+            // chunked has to be the last transfer coding (RFC 9112)
+            let chunked = head
+                .header("Transfer-Encoding")
+                .flat_map(|value| value.split(','))
+                .last()
+                .is_some_and(|coding| coding.trim().eq_ignore_ascii_case("chunked"));
+            if !chunked || head.header("Content-Length").next().is_some() {
+                return Err(Failure::Respond(400));
+            }
+            self.continue_100(head, deadline)?;
+            let (body, complete) = self.chunked(max_body_size, deadline)?;
+            head.headers
+                .retain(|(name, _)| !name.eq_ignore_ascii_case("Transfer-Encoding"));
+            head.headers
+                .push(("Content-Length".to_string(), body.len().to_string()));
+            return Ok((body, complete));
+        }
+
+        let mut lengths = head.header("Content-Length");
+        let length = match lengths.next() {
+            Some(length) => content_length(length).ok_or(Failure::Respond(400))?,
+            None => return Ok((Vec::new(), true)),
+        };
+        if lengths.any(|other| content_length(other) != Some(length)) {
+            return Err(Failure::Respond(400));
+        }
+
+        // kroute rejects the request, from its announced length
+        if length > max_body_size {
+            return Ok((Vec::new(), false));
+        }
+        self.continue_100(head, deadline)?;
+        Ok((self.take(length as usize, deadline)?, true))
+    }
+
+    /// Receive a chunked body, at most one byte more than
+    /// `max_body_size`
+    fn chunked(
+        &mut self,
+        max_body_size: u64,
+        deadline: Instant,
+    ) -> Result<(Vec<u8>, bool), Failure> {
+        let mut body = Vec::new();
+
+        loop {
+            let size = chunk_size(&self.line(deadline)?).ok_or(Failure::Respond(400))?;
+
+            if size == 0 {
+                // trailer fields are ignored
+                while !self.line(deadline)?.is_empty() {}
+                return Ok((body, true));
+            }
+
+            let room = max_body_size.saturating_add(1) - body.len() as u64;
+            if size >= room {
+                body.extend(self.take(room as usize, deadline)?);
+                return Ok((body, false));
+            }
+            body.extend(self.take(size as usize, deadline)?);
+            if self.take(2, deadline)? != b"\r\n" {
+                return Err(Failure::Respond(400));
+            }
+        }
+    }
+
+    /// Tell a client that waits for it to send the body of its request
+    fn continue_100(&mut self, head: &Head, deadline: Instant) -> Result<(), Failure> {
+        if !head.http11 || !head.has_token("Expect", "100-continue") {
+            return Ok(());
+        }
+        let timeout = deadline.saturating_duration_since(Instant::now());
+        self.socket
+            .set_timeout(timeout.max(Duration::from_millis(1)))
+            .and_then(|_| self.socket.write_all(b"HTTP/1.1 100 Continue\r\n\r\n"))
+            .map_err(|_| Failure::Closed)
+    }
+
+    /// Receive the next `n` bytes
+    fn take(&mut self, n: usize, deadline: Instant) -> Result<Vec<u8>, Failure> {
+        while self.buffer.len() < n {
+            self.fill_request(deadline)?;
+        }
+        Ok(self.buffer.drain(..n).collect())
+    }
+
+    /// Receive the next line, without its line ending
+    fn line(&mut self, deadline: Instant) -> Result<Vec<u8>, Failure> {
+        loop {
+            if let Some(end) = self.buffer.windows(2).position(|w| w == b"\r\n") {
+                let line: Vec<u8> = self.buffer.drain(..end + 2).take(end).collect();
+                if line.contains(&b'\r') || line.contains(&b'\n') {
+                    return Err(Failure::Respond(400));
+                }
+                return Ok(line);
+            }
+            if self.buffer.len() > HEAD_OVERHEAD {
+                return Err(Failure::Respond(400));
+            }
+            self.fill_request(deadline)?;
+        }
+    }
+
+    /// Send a response, without its body if it answers a `HEAD`
+    /// request. The `Connection: close` header is sent unless the
+    /// connection is kept alive.
+    fn respond(
+        &mut self,
+        response: rouille::Response,
+        head: bool,
+        http11: bool,
+        keep_alive: bool,
+        timeout: Duration,
+    ) -> io::Result<()> {
+        self.socket.set_timeout(timeout)?;
+
+        let status = response.status_code;
+        let (mut data, size) = response.data.into_reader_and_size();
+        let has_body = status >= 200 && status != 204 && status != 304;
+        // HTTP/1.0 clients do not know the chunked transfer coding, the
+        // end of the connection marks the end of the body
+        let chunked = has_body && size.is_none() && http11;
+        let keep_alive = keep_alive && (!has_body || size.is_some() || chunked);
+
+        let mut writer = BufWriter::new(&mut self.socket);
+        write!(writer, "HTTP/1.1 {status} {}\r\n", reason(status))?;
+        for (name, value) in &response.headers {
+            // the framing of the response is kong's, not the handler's
+            if ["Content-Length", "Transfer-Encoding", "Connection"]
+                .iter()
+                .any(|field| name.eq_ignore_ascii_case(field))
+            {
+                continue;
+            }
+            write!(writer, "{name}: {value}\r\n")?;
+        }
+        write!(
+            writer,
+            "Date: {}\r\n",
+            Utc::now().format("%a, %d %b %Y %H:%M:%S GMT")
+        )?;
+        match size {
+            Some(size) if has_body => write!(writer, "Content-Length: {size}\r\n")?,
+            _ if chunked => write!(writer, "Transfer-Encoding: chunked\r\n")?,
+            _ => {}
+        }
+        if !keep_alive {
+            write!(writer, "Connection: close\r\n")?;
+        }
+        write!(writer, "\r\n")?;
+
+        if has_body && !head {
+            if chunked {
+                let mut chunk = [0u8; CHUNK_SIZE];
+                loop {
+                    let n = match data.read(&mut chunk) {
+                        Ok(n) => n,
+                        Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                        Err(e) => return Err(e),
+                    };
+                    if n == 0 {
+                        break;
+                    }
+                    write!(writer, "{n:x}\r\n")?;
+                    writer.write_all(&chunk[..n])?;
+                    write!(writer, "\r\n")?;
+                }
+                write!(writer, "0\r\n\r\n")?;
+            } else {
+                io::copy(&mut data, &mut writer)?;
+            }
+        }
+        writer.flush()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::net::TcpListener;
+    use std::thread;
+
+    /// Serve one connection with a handler echoing the requests, returns
+    /// the client side of the connection
+    fn connect(timeout: Duration) -> TcpStream {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+
+        thread::spawn(move || {
+            let (socket, from) = listener.accept().unwrap();
+            let limits = Limits {
+                max_connections: 1,
+                max_body_size: 16,
+                max_header_size: 1024,
+                timeout,
+                shutdown_timeout: timeout,
+            };
+            serve(
+                socket,
+                Peer::Tcp(from),
+                false,
+                &|request: &rouille::Request| {
+                    let mut body = String::new();
+                    request.data().unwrap().read_to_string(&mut body).unwrap();
+                    let length = request.header("Content-Length").unwrap_or("-");
+                    rouille::Response::text(format!(
+                        "{} {} {length} {body}",
+                        request.method(),
+                        request.url()
+                    ))
+                },
+                limits,
+                &Shutdown::new(),
+            )
+        });
+
+        let stream = TcpStream::connect(address).unwrap();
+        stream
+            .set_read_timeout(Some(Duration::from_secs(5)))
+            .unwrap();
+        stream
+    }
+
+    /// Read a response with a `Content-Length`
+    fn response(stream: &mut TcpStream) -> String {
+        let mut response = Vec::new();
+        let mut byte = [0u8];
+        while !response.ends_with(b"\r\n\r\n") {
+            stream.read_exact(&mut byte).unwrap();
+            response.push(byte[0]);
+        }
+
+        let head = String::from_utf8(response).unwrap();
+        let length: usize = head
+            .lines()
+            .find_map(|line| line.strip_prefix("Content-Length: "))
+            .unwrap()
+            .parse()
+            .unwrap();
+        let mut body = vec![0u8; length];
+        stream.read_exact(&mut body).unwrap();
+        head + &String::from_utf8(body).unwrap()
+    }
+
+    #[test]
+    fn keep_alive() {
+        let mut stream = connect(Duration::from_secs(5));
+
+        stream
+            .write_all(b"POST /a HTTP/1.1\r\nHost: kong\r\nContent-Length: 4\r\n\r\nkong")
+            .unwrap();
+        let first = response(&mut stream);
+        assert!(first.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(first.ends_with("POST /a 4 kong"));
+        assert!(!first.contains("Connection: close"));
+
+        // the same connection serves the next request
+        stream
+            .write_all(
+                b"POST /b HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\n2\r\nko\r\n2;x=y\r\nng\r\n0\r\n\r\n",
+            )
+            .unwrap();
+        assert!(response(&mut stream).ends_with("POST /b 4 kong"));
+
+        stream
+            .write_all(b"GET /c HTTP/1.1\r\nConnection: close\r\n\r\n")
+            .unwrap();
+        let last = response(&mut stream);
+        assert!(last.contains("Connection: close\r\n"));
+        assert!(last.ends_with("GET /c - "));
+        assert_eq!(stream.read(&mut [0u8]).unwrap(), 0);
+    }
+
+    #[test]
+    fn body_too_large() {
+        let mut stream = connect(Duration::from_secs(5));
+
+        // the body is not received, the handler sees its length
+        stream
+            .write_all(b"POST /a HTTP/1.1\r\nContent-Length: 17\r\n\r\n")
+            .unwrap();
+        let announced = response(&mut stream);
+        assert!(announced.contains("Connection: close\r\n"));
+        assert!(announced.ends_with("POST /a 17 "));
+
+        // a chunked body is cut one byte past the limit
+        let mut stream = connect(Duration::from_secs(5));
+        stream
+            .write_all(b"POST /b HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\n14\r\n")
+            .unwrap();
+        stream.write_all(&[b'k'; 20]).unwrap();
+        let cut = response(&mut stream);
+        assert!(cut.contains("Connection: close\r\n"));
+        assert!(cut.ends_with(&format!("POST /b 17 {}", "k".repeat(17))));
+    }
+
+    #[test]
+    fn slow_client() {
+        let mut stream = connect(Duration::from_millis(300));
+
+        // the client stops sending in the middle of the body
+        stream
+            .write_all(b"POST /a HTTP/1.1\r\nContent-Length: 4\r\n\r\nko")
+            .unwrap();
+        let started = Instant::now();
+        let response = response(&mut stream);
+        assert!(response.starts_with("HTTP/1.1 408 Request Timeout\r\n"));
+        assert!(started.elapsed() < Duration::from_secs(2));
+        assert_eq!(stream.read(&mut [0u8]).unwrap(), 0);
+    }
+
+    #[test]
+    fn malformed_request() {
+        let mut stream = connect(Duration::from_secs(5));
+
+        stream.write_all(b"GET /a SPDY/3\r\n\r\n").unwrap();
+        assert!(response(&mut stream).starts_with("HTTP/1.1 400 Bad Request\r\n"));
+    }
+
+    #[test]
+    fn strict_framing() {
+        let requests: [&[u8]; 7] = [
+            b"POST /a HTTP/1.1\r\nContent-Length: +2\r\n\r\nko",
+            b"POST /a HTTP/1.1\r\nContent-Length: 2\r\nContent-Length: 02x\r\n\r\nko",
+            b"POST /a HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\n+2\r\nko\r\n0\r\n\r\n",
+            b"POST /a HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\n 2\r\nko\r\n0\r\n\r\n",
+            b"POST /a HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\n2\nko\r\n0\r\n\r\n",
+            b"POST /a HTTP/1.1\r\nContent-Length: 2\r\nTransfer-Encoding: chunked\r\n\r\n2\r\nko\r\n0\r\n\r\n",
+            b"GET /a HTTP/1.1\nHost: kong\n\n",
+        ];
+
+        for request in requests {
+            let mut stream = connect(Duration::from_secs(5));
+            stream.write_all(request).unwrap();
+            let response = response(&mut stream);
+            assert!(
+                response.starts_with("HTTP/1.1 400 Bad Request\r\n"),
+                "{}",
+                String::from_utf8_lossy(request)
+            );
+        }
+    }
+}
@@ -0,0 +1,365 @@
+//! 🌍 `kong` cross-origin resource sharing (CORS)
+//!
+//! Browsers only let web applications served from another origin read
+//! kong responses if the node allows it. The CORS policy is configured
+//! globally with the `[cors]` table and can be overridden per route
+//! with `[routes."/address".cors]`:
+//!
+//! ```toml
+//! [cors]
+//! origins = ["https://app.example.com", "https://*.example.com"]
+//! methods = ["GET", "POST"]
+//! headers = ["Content-Type", "Authorization"]
+//! expose_headers = ["RateLimit-Remaining"]
+//! credentials = true
+//! max_age = 600
+//!
+//! [routes."/public".cors]
+//! origins = ["*"]
+//! ```
+//!
+//! Preflight requests (`OPTIONS` requests with an `Origin` and an
+//! `Access-Control-Request-Method` header) are answered automatically,
+//! unless a kontroller handles `OPTIONS` for the address itself.
+//! Responses of kontrollers, as well as `404`, `405` and `429`
+//! responses of kroute, get the `Access-Control-*` headers if the
+//! request origin is allowed. Requests from origins that are not
+//! allowed are still handled, their responses just lack the CORS
+//! headers, so the browser does not expose them. Unless the policy
+//! allows any origin, all these responses depend on the origin and
+//! carry `Vary: Origin`, so caches do not serve one origin's response
+//! to another.
+//!
+//! Policies allowing credentials have to list the allowed origins, the
+//! configuration is rejected if they are empty or contain `*`.
+
+use crate::konfig::CorsKonfig;
+use crate::KError;
+
+/// Whether a request is a CORS preflight request
+pub(crate) fn is_preflight(request: &rouille::Request) -> bool {
+    request.method() == "OPTIONS"
+        && request.header("Origin").is_some()
+        && request.header("Access-Control-Request-Method").is_some()
+}
+
+/// Answer a preflight request, `allow` lists the methods of the route
+/// and is used when the policy does not list methods itself.
+pub(crate) fn preflight(
+    policy: &CorsKonfig,
+    request: &rouille::Request,
+    allow: &str,
+) -> rouille::Response {
+    let response = vary(
+        policy,
+        rouille::Response::empty_204().with_additional_header("Allow", allow.to_string()),
+    );
+
+    let origin = match request.header("Origin") {
+        Some(origin) if policy.allows_origin(origin) => origin,
+        _ => return response,
+    };
+
+    let methods = match &policy.methods {
+        Some(methods) => methods.join(", "),
+        None => allow.to_string(),
+    };
+    let requested_method = request
+        .header("Access-Control-Request-Method")
+        .unwrap_or_default();
+    if !methods.split(", ").any(|m| m == requested_method) {
+        return response;
+    }
+
+    let headers = match &policy.headers {
+        Some(headers) => headers.join(", "),
+        // without a list of allowed headers, the requested ones are allowed
+        None => request
+            .header("Access-Control-Request-Headers")
+            .unwrap_or_default()
+            .to_string(),
+    };
+
+    let mut response = allow_origin(policy, origin, response)
+        .with_additional_header("Access-Control-Allow-Methods", methods);
+    if !headers.is_empty() {
+        response = response.with_additional_header("Access-Control-Allow-Headers", headers);
+    }
+    if let Some(max_age) = policy.max_age {
+        response = response.with_additional_header("Access-Control-Max-Age", max_age.to_string());
+    }
+    response
+}
+
+/// Add CORS headers to the response of a kontroller
+pub(crate) fn headers(
+    policy: &CorsKonfig,
+    request: &rouille::Request,
+    response: rouille::Response,
+) -> rouille::Response {
+    let response = vary(policy, response);
+    let origin = match request.header("Origin") {
+        Some(origin) if policy.allows_origin(origin) => origin,
+        _ => return response,
+    };
+
+    let response = allow_origin(policy, origin, response);
+    match &policy.expose_headers {
+        Some(headers) if !headers.is_empty() => {
+            response.with_additional_header("Access-Control-Expose-Headers", headers.join(", "))
+        }
+        _ => response,
+    }
+}
+
+/// Whether the policy allows any origin with `*`, responses then do not
+/// depend on the origin of the request
+fn any_origin(policy: &CorsKonfig) -> bool {
+    // the wildcard can not be used with credentials, the origin is
+    // echoed instead
+    policy.origins.iter().any(|o| o == "*") && !policy.credentials.unwrap_or(false)
+}
+
+/// Add `Vary: Origin` to a response of the policy's routes, unless the
+/// policy allows any origin. Allowed and other origins get different
+/// responses, even without an `Origin` header.
+fn vary(policy: &CorsKonfig, response: rouille::Response) -> rouille::Response {
+    if any_origin(policy) {
+        response
+    } else {
+        response.with_additional_header("Vary", "Origin")
+    }
+}
+
+/// `Access-Control-Allow-Origin` and `Access-Control-Allow-Credentials`
+/// headers of an allowed origin
+fn allow_origin(
+    policy: &CorsKonfig,
+    origin: &str,
+    response: rouille::Response,
+) -> rouille::Response {
+    if any_origin(policy) {
+        return response.with_additional_header("Access-Control-Allow-Origin", "*");
+    }
+
+    let response =
+        response.with_additional_header("Access-Control-Allow-Origin", origin.to_string());
+    if policy.credentials.unwrap_or(false) {
+        response.with_additional_header("Access-Control-Allow-Credentials", "true")
+    } else {
+        response
+    }
+}
+
+impl CorsKonfig {
+    /// Check the policy configured at `key`: credentials are only
+    /// allowed for an explicit list of origins
+    pub(crate) fn validate(&self, key: &str) -> Result<(), KError> {
+        if !self.credentials.unwrap_or(false) {
+            return Ok(());
+        }
+
+        let reason = if self.origins.is_empty() {
+            "credentials require a list of allowed origins"
+        } else if self.origins.iter().any(|o| o == "*") {
+            "credentials can not be allowed for any origin `*`"
+        } else {
+            return Ok(());
+        };
+        Err(KError::InvalidConfig {
+            key: format!("{key}.origins"),
+            reason: reason.to_string(),
+        })
+    }
+
+    /// Whether the policy allows requests from `origin`
+    pub fn allows_origin(&self, origin: &str) -> bool {
+        self.origins
+            .iter()
+            .any(|pattern| pattern == "*" || matches_pattern(pattern, origin))
+    }
+}
+
+/// Match an origin against a pattern, `*` matches any part of a host
+/// name (letters, digits, `-` and `.`)
+fn matches_pattern(pattern: &str, origin: &str) -> bool {
+    match pattern.split_once('*') {
+        None => pattern == origin,
+        Some((prefix, rest)) => {
+            let Some(origin) = origin.strip_prefix(prefix) else {
+                return false;
+            };
+
+            // try every possible length of the wildcard match
+            let host_chars = origin
+                .find(|c: char| !(c.is_ascii_alphanumeric() || c == '-' || c == '.'))
+                .unwrap_or(origin.len());
+            (1..=host_chars).any(|i| matches_pattern(rest, &origin[i..]))
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn policy() -> CorsKonfig {
+        CorsKonfig {
+            origins: vec![
+                "https://app.example.com".to_string(),
+                "https://*.kong.dev".to_string(),
+            ],
+            methods: Some(vec!["GET".to_string(), "POST".to_string()]),
+            headers: Some(vec!["Content-Type".to_string()]),
+            expose_headers: None,
+            credentials: Some(true),
+            max_age: Some(600),
+        }
+    }
+
+    fn fake_request(method: &str, headers: &[(&str, &str)]) -> rouille::Request {
+        let headers = headers
+            .iter()
+            .map(|(n, v)| (n.to_string(), v.to_string()))
+            .collect();
+        rouille::Request::fake_http(method, "/users", headers, Vec::new())
+    }
+
+    fn header<'a>(response: &'a rouille::Response, name: &str) -> Option<&'a str> {
+        response
+            .headers
+            .iter()
+            .find(|(n, _)| n.eq_ignore_ascii_case(name))
+            .map(|(_, v)| v.as_ref())
+    }
+
+    #[test]
+    fn origin_patterns() {
+        let policy = policy();
+
+        assert!(policy.allows_origin("https://app.example.com"));
+        assert!(policy.allows_origin("https://api.kong.dev"));
+        assert!(policy.allows_origin("https://eu.api.kong.dev"));
+        assert!(!policy.allows_origin("https://kong.dev"));
+        assert!(!policy.allows_origin("http://api.kong.dev"));
+        assert!(!policy.allows_origin("https://evil.com/.kong.dev"));
+        assert!(!policy.allows_origin("https://app.example.com.evil.com"));
+    }
+
+    #[test]
+    fn preflight_allowed() {
+        let request = fake_request(
+            "OPTIONS",
+            &[
+                ("Origin", "https://app.example.com"),
+                ("Access-Control-Request-Method", "POST"),
+            ],
+        );
+        assert!(is_preflight(&request));
+
+        let response = preflight(&policy(), &request, "GET, POST, OPTIONS");
+        assert_eq!(response.status_code, 204);
+        assert_eq!(
+            header(&response, "Access-Control-Allow-Origin"),
+            Some("https://app.example.com")
+        );
+        assert_eq!(
+            header(&response, "Access-Control-Allow-Methods"),
+            Some("GET, POST")
+        );
+        assert_eq!(
+            header(&response, "Access-Control-Allow-Headers"),
+            Some("Content-Type")
+        );
+        assert_eq!(
+            header(&response, "Access-Control-Allow-Credentials"),
+            Some("true")
+        );
+        assert_eq!(header(&response, "Access-Control-Max-Age"), Some("600"));
+    }
+
+    #[test]
+    fn preflight_rejected() {
+        let request = fake_request(
+            "OPTIONS",
+            &[
+                ("Origin", "https://app.example.com"),
+                ("Access-Control-Request-Method", "DELETE"),
+            ],
+        );
+        let response = preflight(&policy(), &request, "GET, POST, DELETE, OPTIONS");
+        assert_eq!(header(&response, "Access-Control-Allow-Origin"), None);
+
+        let request = fake_request(
+            "OPTIONS",
+            &[
+                ("Origin", "https://evil.com"),
+                ("Access-Control-Request-Method", "GET"),
+            ],
+        );
+        let response = preflight(&policy(), &request, "GET, OPTIONS");
+        assert_eq!(header(&response, "Access-Control-Allow-Origin"), None);
+        assert_eq!(header(&response, "Vary"), Some("Origin"));
+    }
+
+    #[test]
+    fn vary_on_origin() {
+        let allowed = fake_request("GET", &[("Origin", "https://app.example.com")]);
+        let response = headers(&policy(), &allowed, rouille::Response::text("kong"));
+        assert_eq!(header(&response, "Vary"), Some("Origin"));
+
+        // responses to other origins differ from the allowed ones
+        let disallowed = fake_request("GET", &[("Origin", "https://evil.com")]);
+        let response = headers(&policy(), &disallowed, rouille::Response::text("kong"));
+        assert_eq!(header(&response, "Access-Control-Allow-Origin"), None);
+        assert_eq!(header(&response, "Vary"), Some("Origin"));
+
+        let without_origin = fake_request("GET", &[]);
+        let response = headers(&policy(), &without_origin, rouille::Response::text("kong"));
+        assert_eq!(header(&response, "Vary"), Some("Origin"));
+    }
+
+    #[test]
+    fn wildcard_origin() {
+        let policy = CorsKonfig {
+            origins: vec!["*".to_string()],
+            expose_headers: Some(vec!["RateLimit-Remaining".to_string()]),
+            ..Default::default()
+        };
+        let request = fake_request("GET", &[("Origin", "https://any.where")]);
+
+        let response = headers(&policy, &request, rouille::Response::text("kong"));
+        assert_eq!(header(&response, "Access-Control-Allow-Origin"), Some("*"));
+        assert_eq!(
+            header(&response, "Access-Control-Expose-Headers"),
+            Some("RateLimit-Remaining")
+        );
+        assert_eq!(header(&response, "Vary"), None);
+    }
+
+    #[test]
+    fn credentials_require_origins() {
+        assert!(policy().validate("cors").is_ok());
+
+        let wildcard = CorsKonfig {
+            origins: vec!["https://app.example.com".to_string(), "*".to_string()],
+            ..policy()
+        };
+        match wildcard.validate("cors") {
+            Err(KError::InvalidConfig { key, .. }) => assert_eq!(key, "cors.origins"),
+            _ => panic!("`*` with credentials should be rejected"),
+        }
+
+        let no_origins = CorsKonfig {
+            origins: Vec::new(),
+            ..policy()
+        };
+        assert!(no_origins.validate("routes./users.cors").is_err());
+
+        let without_credentials = CorsKonfig {
+            origins: vec!["*".to_string()],
+            ..Default::default()
+        };
+        assert!(without_credentials.validate("cors").is_ok());
+    }
+}
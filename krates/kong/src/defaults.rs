@@ -3,5 +3,61 @@
 /// Kong working directory
 pub const WORKING_DIRECTORY: &str = "kong/";
 
+/// Address the node listens on
+pub const BIND: &str = "localhost";
+
 /// Kong log file
 pub const LOG_FILE: &str = "LOG";
+
+/// Kong access log file
+pub const ACCESS_LOG_FILE: &str = "ACCESS_LOG";
+
+/// Number of rotated log files retained
+pub const LOG_KEEP: usize = 7;
+
+/// Login throttle counters file
+pub const LOGIN_THROTTLE_FILE: &str = "LOGIN_THROTTLE";
+
+/// Failed logins allowed per username before the account is locked
+pub const LOGIN_MAX_FAILURES: u32 = 5;
+
+/// Failed logins allowed per IP address before the address is locked
+pub const LOGIN_IP_MAX_FAILURES: u32 = 20;
+
+/// Delay in seconds after the first failed login
+pub const LOGIN_BASE_DELAY: u64 = 1;
+
+/// Maximum delay in seconds between failed logins
+pub const LOGIN_MAX_DELAY: u64 = 60;
+
+/// Number of seconds a locked account stays locked
+pub const LOGIN_LOCKOUT: u64 = 900;
+
+/// Number of usernames and IP addresses tracked by the login throttle
+pub const LOGIN_MAX_TRACKED: usize = 100_000;
+
+/// Minimum number of seconds between two writes of the login throttle
+/// counters file
+pub const LOGIN_PERSIST_INTERVAL: u64 = 5;
+
+/// Maximum size of a request body in bytes (1 MiB)
+pub const MAX_BODY_SIZE: u64 = 1024 * 1024;
+
+/// Maximum size of all request headers in bytes (8 KiB)
+pub const MAX_HEADER_SIZE: usize = 8 * 1024;
+
+/// Number of seconds a client has to send a request
+pub const REQUEST_TIMEOUT: u64 = 30;
+
+/// Number of connections each listener serves at once
+pub const MAX_CONNECTIONS: usize = 512;
+
+/// Number of seconds in-flight requests are given to finish when the
+/// node stops
+pub const SHUTDOWN_TIMEOUT: u64 = 30;
+
+/// Address of the liveness endpoint
+pub const LIVENESS_PATH: &str = "/healthz";
+
+/// Address of the readiness endpoint
+pub const READINESS_PATH: &str = "/readyz";
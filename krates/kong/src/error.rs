@@ -1,28 +1,255 @@
 //! 🚨 `kong` error management
-use std::fmt;
+use crate::error_response::ResponseError;
+use crate::validate::ValidationError;
+use krypto::error::KryptoError;
+use std::error::Error;
+use std::path::{Path, PathBuf};
+use std::{fmt, io};
 
 #[derive(Debug)]
 /// 🚨 `kong` error management
 pub enum KError {
-    /// Configuration error
-    Config,
+    /// Configuration file could not be read
+    ConfigRead {
+        /// Path of the configuration file
+        path: PathBuf,
+        /// IO error
+        source: io::Error,
+    },
+    /// Configuration is not valid TOML or does not match the
+    /// configuration format
+    ConfigParse {
+        /// Path of the configuration file, if it was read from a file
+        path: Option<PathBuf>,
+        /// Line of the error, starting at 1
+        line: Option<usize>,
+        /// Column of the error, starting at 1
+        column: Option<usize>,
+        /// TOML error, naming the invalid key if known
+        source: toml::de::Error,
+    },
+    /// Invalid configuration value
+    InvalidConfig {
+        /// Invalid key, `section.key` if it is in a section
+        key: String,
+        /// Why the value is invalid
+        reason: String,
+    },
     /// URL parsing error
-    UrlParsing,
+    UrlParsing {
+        /// URL that could not be parsed
+        url: String,
+        /// Why it could not be parsed
+        reason: String,
+    },
     /// Invalid HTTP Method
-    InvalidHttpMethod,
+    InvalidHttpMethod(String),
     /// Log file error
-    LogFile,
+    LogFile {
+        /// Path of the log file, `None` for the console
+        path: Option<PathBuf>,
+        /// IO error
+        source: io::Error,
+    },
+    /// Could not start a listener
+    Listener {
+        /// Address of the listener
+        address: String,
+        /// Why it could not be started
+        reason: String,
+        /// Underlying error, if any
+        source: Option<Box<dyn Error + Send + Sync>>,
+    },
+    /// TLS certificate error
+    Tls {
+        /// What went wrong
+        reason: String,
+        /// IO error, if the certificate files could not be read
+        source: Option<io::Error>,
+    },
+    /// Could not register signal handlers
+    Signal(io::Error),
+    /// Invalid, duplicate or conflicting kontroller routes, one
+    /// description per problem
+    InvalidRoutes(Vec<String>),
+    /// Kpassport or password error
+    Krypto(KryptoError),
+    /// Input validation error
+    Validation(ValidationError),
 }
 
-impl std::error::Error for KError {}
+impl KError {
+    /// Configuration parsing error, with the position of `source`
+    pub(crate) fn config_parse(path: Option<&Path>, source: toml::de::Error) -> Self {
+        let position = source.line_col();
+        KError::ConfigParse {
+            path: path.map(Path::to_path_buf),
+            line: position.map(|(line, _)| line + 1),
+            column: position.map(|(_, column)| column + 1),
+            source,
+        }
+    }
+}
+
+impl Error for KError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            Self::ConfigRead { source, .. } => Some(source),
+            Self::ConfigParse { source, .. } => Some(source),
+            Self::LogFile { source, .. } => Some(source),
+            Self::Listener { source, .. } => source
+                .as_deref()
+                .map(|source| source as &(dyn Error + 'static)),
+            Self::Tls { source, .. } => source.as_ref().map(|source| source as &dyn Error),
+            Self::Signal(source) => Some(source),
+            Self::Krypto(source) => Some(source),
+            Self::Validation(source) => Some(source),
+            _ => None,
+        }
+    }
+}
 
 impl fmt::Display for KError {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
-            Self::Config => write!(f, "Could not read config file"),
-            Self::UrlParsing => write!(f, "Could not parse error"),
-            Self::InvalidHttpMethod => write!(f, "Invalid HTTP method"),
-            Self::LogFile => write!(f, "Log file error"),
+            Self::ConfigRead { path, source } => {
+                write!(f, "Could not read config file {}: {source}", path.display())
+            }
+            Self::ConfigParse {
+                path: Some(path),
+                source,
+                ..
+            } => write!(f, "Invalid config file {}: {source}", path.display()),
+            Self::ConfigParse { source, .. } => write!(f, "Invalid config: {source}"),
+            Self::InvalidConfig { key, reason } => write!(f, "Invalid config key {key}: {reason}"),
+            Self::UrlParsing { url, reason } => write!(f, "Could not parse URL {url}: {reason}"),
+            Self::InvalidHttpMethod(method) => write!(f, "Invalid HTTP method {method}"),
+            Self::LogFile {
+                path: Some(path),
+                source,
+            } => write!(f, "Log file error {}: {source}", path.display()),
+            Self::LogFile { source, .. } => write!(f, "Console log error: {source}"),
+            Self::Listener {
+                address,
+                reason,
+                source: Some(source),
+            } => write!(f, "Could not start listener {address}, {reason}: {source}"),
+            Self::Listener {
+                address, reason, ..
+            } => write!(f, "Could not start listener {address}, {reason}"),
+            Self::Tls {
+                reason,
+                source: Some(source),
+            } => write!(f, "TLS certificate error, {reason}: {source}"),
+            Self::Tls { reason, .. } => write!(f, "TLS certificate error, {reason}"),
+            Self::Signal(source) => write!(f, "Could not register signal handlers: {source}"),
+            Self::InvalidRoutes(problems) => write!(f, "Invalid routes: {}", problems.join(", ")),
+            Self::Krypto(source) => write!(f, "{source}"),
+            Self::Validation(source) => write!(f, "{source}"),
+        }
+    }
+}
+
+impl From<KryptoError> for KError {
+    fn from(error: KryptoError) -> Self {
+        KError::Krypto(error)
+    }
+}
+
+impl From<ValidationError> for KError {
+    fn from(error: ValidationError) -> Self {
+        KError::Validation(error)
+    }
+}
+
+/// Kontrollers can use `?` on kong errors, krypto and validation errors
+/// keep their status, other errors are internal server errors
+impl ResponseError for KError {
+    fn status(&self) -> u16 {
+        match self {
+            Self::Krypto(error) => error.status(),
+            Self::Validation(error) => error.status(),
+            _ => 500,
         }
     }
+
+    fn message(&self) -> String {
+        match self {
+            Self::Krypto(error) => error.message(),
+            Self::Validation(error) => error.message(),
+            // internal details are logged, not sent to clients
+            _ => crate::problem::status_title(500).to_string(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::Konfig;
+
+    #[test]
+    fn config_errors() {
+        let dir = std::env::temp_dir().join(format!("kong-error-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+
+        let missing = dir.join("missing.toml");
+        let error = Konfig::read_file(&missing).err().unwrap();
+        assert!(matches!(&error, KError::ConfigRead { path, .. } if path == &missing));
+        assert!(error.source().is_some());
+        assert!(error.to_string().contains("missing.toml"));
+
+        let invalid = dir.join("invalid.toml");
+        let config = crate::testing::MINIMAL_CONFIG;
+        std::fs::write(&invalid, format!("{config}log_level = \"loud\"\n")).unwrap();
+        let error = Konfig::read_file(&invalid).err().unwrap();
+        assert!(matches!(&error, KError::ConfigParse { path: Some(path), .. } if path == &invalid));
+        assert!(error.to_string().contains("for key `log_level`"));
+
+        std::fs::write(&invalid, format!("{config}log_level = \n")).unwrap();
+        match Konfig::read_file(&invalid).err().unwrap() {
+            KError::ConfigParse { line, column, .. } => {
+                assert_eq!(line, Some(6));
+                assert_eq!(column, Some(13));
+            }
+            error => panic!("unexpected error {error}"),
+        }
+
+        let cors = "[cors]\norigins = [\"*\"]\ncredentials = true\n";
+        std::fs::write(&invalid, format!("{config}{cors}")).unwrap();
+        match Konfig::read_file(&invalid).err().unwrap() {
+            KError::InvalidConfig { key, .. } => assert_eq!(key, "cors.origins"),
+            error => panic!("unexpected error {error}"),
+        }
+    }
+
+    #[test]
+    fn convert_errors() {
+        fn validate(username: &str) -> Result<(), KError> {
+            if !crate::validate::Validate::username(username) {
+                Err(ValidationError::Username)?;
+            }
+            Err(KryptoError::InvalidKpassport)?
+        }
+
+        let error = validate("").unwrap_err();
+        assert!(matches!(
+            error,
+            KError::Validation(ValidationError::Username)
+        ));
+        assert_eq!(error.status(), 400);
+        assert_eq!(error.message(), "Invalid username");
+
+        let error = validate("firephoenix").unwrap_err();
+        assert!(matches!(
+            error,
+            KError::Krypto(KryptoError::InvalidKpassport)
+        ));
+        assert_eq!(error.status(), 401);
+
+        let error = KError::Signal(io::Error::other("denied"));
+        assert_eq!(error.status(), 500);
+        assert_eq!(error.message(), "Internal Server Error");
+        assert_eq!(error.source().unwrap().to_string(), "denied");
+    }
 }
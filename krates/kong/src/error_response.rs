@@ -1,6 +1,64 @@
 //! 🏴 `kong` error response
+//!
+//! Errors are answered with an [`ErrorResponse`] body, built from the
+//! typed [`HttpError`] vocabulary:
+//!
+//! ```
+//! use kong::{ErrorResponse, HttpError};
+//!
+//! let response = ErrorResponse::unprocessable_entity();
+//! assert_eq!(response.status_code, 422);
+//! assert_eq!(HttpError::UnprocessableEntity.status(), 422);
+//! ```
+//!
+//! Application errors implement [`ResponseError`], so kontrollers can
+//! return them with `?` from [`crate::Kontrol::try_kontrol`]:
+//!
+//! ```
+//! use kong::{server, HttpError, Kong, KontrolError, ResponseError};
+//!
+//! enum UserError {
+//!     Taken,
+//!     Database,
+//! }
+//!
+//! impl ResponseError for UserError {
+//!     fn status(&self) -> u16 {
+//!         match self {
+//!             UserError::Taken => 409,
+//!             UserError::Database => 500,
+//!         }
+//!     }
+//!     fn message(&self) -> String {
+//!         match self {
+//!             UserError::Taken => "Username is taken".to_string(),
+//!             UserError::Database => HttpError::InternalServerError.message().to_string(),
+//!         }
+//!     }
+//! }
+//!
+//! fn create_user(username: &str) -> Result<server::Response, KontrolError> {
+//!     if username == "firephoenix" {
+//!         Err(UserError::Taken)?;
+//!     }
+//!     Ok(server::Response::empty_204())
+//! }
+//!
+//! let response = create_user("firephoenix").unwrap_err().response();
+//! assert_eq!(response.status_code, 409);
+//! ```
 
+use crate::log::{Level, Log};
 use serde::Serialize;
+use serde_json::{json, Map, Value};
+use std::io::Read;
+
+/// Largest error response body [`take_error_body`] reads
+const MAX_ERROR_BODY: usize = 64 * 1024;
+
+/// Header marking the responses built as an [`ErrorResponse`], kroute
+/// removes it before the response is sent
+pub(crate) const ERROR_RESPONSE_HEADER: &str = "X-Kong-Error-Response";
 
 /// 🏴 API error response
 #[derive(Serialize)]
@@ -9,62 +67,476 @@ pub struct ErrorResponse {
     pub error_message: String,
 }
 
+/// 🏴 HTTP errors
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum HttpError {
+    /// 400
+    BadRequest,
+    /// 401
+    Unauthorized,
+    /// 402
+    PaymentRequired,
+    /// 403
+    Forbidden,
+    /// 404
+    NotFound,
+    /// 405
+    MethodNotAllowed,
+    /// 406
+    NotAcceptable,
+    /// 408
+    RequestTimeout,
+    /// 409
+    Conflict,
+    /// 410
+    Gone,
+    /// 411
+    LengthRequired,
+    /// 412
+    PreconditionFailed,
+    /// 413
+    PayloadTooLarge,
+    /// 414
+    UriTooLong,
+    /// 415
+    UnsupportedMediaType,
+    /// 416
+    RangeNotSatisfiable,
+    /// 417
+    ExpectationFailed,
+    /// 422
+    UnprocessableEntity,
+    /// 423
+    Locked,
+    /// 428
+    PreconditionRequired,
+    /// 429, `retry_after` is the number of seconds the client should
+    /// wait before making a new request
+    TooManyRequests {
+        /// Seconds before the client should retry
+        retry_after: u64,
+    },
+    /// 431
+    RequestHeaderFieldsTooLarge,
+    /// 451
+    UnavailableForLegalReasons,
+    /// 500
+    InternalServerError,
+    /// 501
+    NotImplemented,
+    /// 502
+    BadGateway,
+    /// 503
+    ServiceUnavailable,
+    /// 504
+    GatewayTimeout,
+}
+
+impl HttpError {
+    /// HTTP status code
+    pub fn status(&self) -> u16 {
+        match self {
+            HttpError::BadRequest => 400,
+            HttpError::Unauthorized => 401,
+            HttpError::PaymentRequired => 402,
+            HttpError::Forbidden => 403,
+            HttpError::NotFound => 404,
+            HttpError::MethodNotAllowed => 405,
+            HttpError::NotAcceptable => 406,
+            HttpError::RequestTimeout => 408,
+            HttpError::Conflict => 409,
+            HttpError::Gone => 410,
+            HttpError::LengthRequired => 411,
+            HttpError::PreconditionFailed => 412,
+            HttpError::PayloadTooLarge => 413,
+            HttpError::UriTooLong => 414,
+            HttpError::UnsupportedMediaType => 415,
+            HttpError::RangeNotSatisfiable => 416,
+            HttpError::ExpectationFailed => 417,
+            HttpError::UnprocessableEntity => 422,
+            HttpError::Locked => 423,
+            HttpError::PreconditionRequired => 428,
+            HttpError::TooManyRequests { .. } => 429,
+            HttpError::RequestHeaderFieldsTooLarge => 431,
+            HttpError::UnavailableForLegalReasons => 451,
+            HttpError::InternalServerError => 500,
+            HttpError::NotImplemented => 501,
+            HttpError::BadGateway => 502,
+            HttpError::ServiceUnavailable => 503,
+            HttpError::GatewayTimeout => 504,
+        }
+    }
+
+    /// Error message of the [`ErrorResponse`]
+    pub fn message(&self) -> &'static str {
+        match self {
+            HttpError::BadRequest => "Bad request",
+            HttpError::NotFound => "Could not find resource",
+            HttpError::PreconditionFailed => "Pre-Condition failed",
+            error => crate::problem::status_title(error.status()),
+        }
+    }
+
+    /// Response of the error
+    pub fn response(&self) -> rouille::Response {
+        let response = ErrorResponse::with_status(self.status(), self.message());
+        match self {
+            HttpError::TooManyRequests { retry_after } => {
+                response.with_additional_header("Retry-After", retry_after.to_string())
+            }
+            _ => response,
+        }
+    }
+}
+
+/// 🏴 Errors that are answered with an HTTP error response
+pub trait ResponseError {
+    /// HTTP status code
+    fn status(&self) -> u16;
+    /// Error message of the [`ErrorResponse`], __defaults to the
+    /// reason phrase of the status__
+    fn message(&self) -> String {
+        crate::problem::status_title(self.status()).to_string()
+    }
+    /// Response of the error
+    fn response(&self) -> rouille::Response {
+        ErrorResponse::with_status(self.status(), &self.message())
+    }
+}
+
+impl ResponseError for HttpError {
+    fn status(&self) -> u16 {
+        HttpError::status(self)
+    }
+    fn message(&self) -> String {
+        HttpError::message(self).to_string()
+    }
+    fn response(&self) -> rouille::Response {
+        HttpError::response(self)
+    }
+}
+
+impl ResponseError for crate::validate::ValidationError {
+    fn status(&self) -> u16 {
+        400
+    }
+    fn message(&self) -> String {
+        self.to_string()
+    }
+}
+
+impl ResponseError for krypto::error::KryptoError {
+    fn status(&self) -> u16 {
+        match self {
+            krypto::error::KryptoError::PasswordHashing => 500,
+            _ => 401,
+        }
+    }
+}
+
+/// 🏴 Error of a kontroller, any [`ResponseError`] converts into it
+pub struct KontrolError(Box<dyn ResponseError + Send + Sync>);
+
+impl KontrolError {
+    /// HTTP status code
+    pub fn status(&self) -> u16 {
+        self.0.status()
+    }
+    /// Error message
+    pub fn message(&self) -> String {
+        self.0.message()
+    }
+    /// Response of the error
+    pub fn response(&self) -> rouille::Response {
+        self.0.response()
+    }
+}
+
+impl<E: ResponseError + Send + Sync + 'static> From<E> for KontrolError {
+    fn from(error: E) -> Self {
+        KontrolError(Box::new(error))
+    }
+}
+
+impl std::fmt::Debug for KontrolError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "KontrolError({}: {})", self.status(), self.message())
+    }
+}
+
 impl ErrorResponse {
+    /// Error response with any status and message, responses built
+    /// while a request is handled have its `request_id`
+    pub fn with_status(status: u16, message: &str) -> rouille::Response {
+        let mut body = json!(ErrorResponse {
+            error_message: message.to_string(),
+        });
+        if let Some(id) = crate::log::request_id() {
+            body["request_id"] = Value::String(id);
+        }
+
+        rouille::Response::json(&body)
+            .with_status_code(status)
+            .with_additional_header(ERROR_RESPONSE_HEADER, "1")
+    }
     /// HTTP Bad request (400)
     pub fn bad_request() -> rouille::Response {
-        rouille::Response::json(&ErrorResponse {
-            error_message: "Bad request".to_string(),
-        })
-        .with_status_code(400)
+        HttpError::BadRequest.response()
     }
     /// HTTP unauthorized request (401)
     pub fn unauthorized() -> rouille::Response {
-        rouille::Response::json(&ErrorResponse {
-            error_message: "Unauthorized".to_string(),
-        })
-        .with_status_code(401)
+        HttpError::Unauthorized.response()
     }
-    /// HTTP forbidden request (402)
+    /// HTTP payment required (402)
+    pub fn payment_required() -> rouille::Response {
+        HttpError::PaymentRequired.response()
+    }
+    /// HTTP forbidden request (403)
     pub fn forbidden() -> rouille::Response {
-        rouille::Response::json(&ErrorResponse {
-            error_message: "Forbidden".to_string(),
-        })
-        .with_status_code(402)
+        HttpError::Forbidden.response()
     }
     /// HTTP not foud resource (404)
     pub fn not_found() -> rouille::Response {
-        rouille::Response::json(&ErrorResponse {
-            error_message: "Could not find resource".to_string(),
-        })
-        .with_status_code(404)
+        HttpError::NotFound.response()
     }
-
     /// HTTP method not allowed resource (405)
     pub fn not_allowed() -> rouille::Response {
-        rouille::Response::json(&ErrorResponse {
-            error_message: "Method Not Allowed".to_string(),
-        })
-        .with_status_code(405)
+        HttpError::MethodNotAllowed.response()
+    }
+    /// HTTP not acceptable (406)
+    pub fn not_acceptable() -> rouille::Response {
+        HttpError::NotAcceptable.response()
+    }
+    /// HTTP request timeout (408)
+    pub fn request_timeout() -> rouille::Response {
+        HttpError::RequestTimeout.response()
     }
     /// HTTP request conflict (409)
     pub fn conflict() -> rouille::Response {
-        rouille::Response::json(&ErrorResponse {
-            error_message: "Conflict".to_string(),
-        })
-        .with_status_code(409)
+        HttpError::Conflict.response()
+    }
+    /// HTTP gone (410)
+    pub fn gone() -> rouille::Response {
+        HttpError::Gone.response()
+    }
+    /// HTTP length required (411)
+    pub fn length_required() -> rouille::Response {
+        HttpError::LengthRequired.response()
     }
     /// HTTP precondition failed (412)
     pub fn pre_condition() -> rouille::Response {
-        rouille::Response::json(&ErrorResponse {
-            error_message: "Pre-Condition failed".to_string(),
-        })
-        .with_status_code(412)
+        HttpError::PreconditionFailed.response()
+    }
+    /// HTTP payload too large (413)
+    pub fn payload_too_large() -> rouille::Response {
+        HttpError::PayloadTooLarge.response()
+    }
+    /// HTTP URI too long (414)
+    pub fn uri_too_long() -> rouille::Response {
+        HttpError::UriTooLong.response()
+    }
+    /// HTTP unsupported media type (415)
+    pub fn unsupported_media_type() -> rouille::Response {
+        HttpError::UnsupportedMediaType.response()
+    }
+    /// HTTP range not satisfiable (416)
+    pub fn range_not_satisfiable() -> rouille::Response {
+        HttpError::RangeNotSatisfiable.response()
+    }
+    /// HTTP expectation failed (417)
+    pub fn expectation_failed() -> rouille::Response {
+        HttpError::ExpectationFailed.response()
+    }
+    /// HTTP unprocessable entity (422)
+    pub fn unprocessable_entity() -> rouille::Response {
+        HttpError::UnprocessableEntity.response()
+    }
+    /// HTTP locked (423)
+    pub fn locked() -> rouille::Response {
+        HttpError::Locked.response()
+    }
+    /// HTTP precondition required (428)
+    pub fn precondition_required() -> rouille::Response {
+        HttpError::PreconditionRequired.response()
+    }
+    /// HTTP too many requests (429), `retry_after` is the number of
+    /// seconds the client should wait before making a new request.
+    pub fn too_many_requests(retry_after: u64) -> rouille::Response {
+        HttpError::TooManyRequests { retry_after }.response()
+    }
+    /// HTTP request header fields too large (431)
+    pub fn header_fields_too_large() -> rouille::Response {
+        HttpError::RequestHeaderFieldsTooLarge.response()
+    }
+    /// HTTP unavailable for legal reasons (451)
+    pub fn unavailable_for_legal_reasons() -> rouille::Response {
+        HttpError::UnavailableForLegalReasons.response()
     }
     /// HTTP internal server error (500)
     pub fn internal() -> rouille::Response {
-        rouille::Response::json(&ErrorResponse {
-            error_message: "Internal Server Error".to_string(),
-        })
-        .with_status_code(500)
+        HttpError::InternalServerError.response()
+    }
+    /// HTTP not implemented (501)
+    pub fn not_implemented() -> rouille::Response {
+        HttpError::NotImplemented.response()
+    }
+    /// HTTP bad gateway (502)
+    pub fn bad_gateway() -> rouille::Response {
+        HttpError::BadGateway.response()
+    }
+    /// HTTP service unavailable (503)
+    pub fn service_unavailable() -> rouille::Response {
+        HttpError::ServiceUnavailable.response()
+    }
+    /// HTTP gateway timeout (504)
+    pub fn gateway_timeout() -> rouille::Response {
+        HttpError::GatewayTimeout.response()
+    }
+}
+
+/// Response of a kontroller, errors are rendered as error responses.
+/// Server errors are logged, they are bugs or failures of the node.
+pub(crate) fn render(result: Result<rouille::Response, KontrolError>) -> rouille::Response {
+    match result {
+        Ok(response) => response,
+        Err(error) => {
+            let level = if error.status() >= 500 {
+                Level::Error
+            } else {
+                Level::Debug
+            };
+            let _ = Log::event(
+                level,
+                "kontroller error",
+                &[
+                    ("status", json!(error.status())),
+                    ("error", json!(error.message())),
+                ],
+            );
+            error.response()
+        }
+    }
+}
+
+/// Take the JSON object body of an [`ErrorResponse`], the caller sets
+/// the new body of the response. Other responses are left unchanged.
+pub(crate) fn take_error_body(response: &mut rouille::Response) -> Option<Map<String, Value>> {
+    let is_error_response = response
+        .headers
+        .iter()
+        .any(|(name, _)| name.eq_ignore_ascii_case(ERROR_RESPONSE_HEADER));
+    if !is_error_response {
+        return None;
+    }
+
+    let (mut data, size) = std::mem::replace(&mut response.data, rouille::ResponseBody::empty())
+        .into_reader_and_size();
+    let size = match size {
+        Some(size) if size <= MAX_ERROR_BODY => size,
+        Some(size) => {
+            response.data = rouille::ResponseBody::from_reader_and_size(data, size);
+            return None;
+        }
+        None => {
+            response.data = rouille::ResponseBody::from_reader(data);
+            return None;
+        }
+    };
+
+    let mut body = Vec::with_capacity(size);
+    let _ = data.read_to_end(&mut body);
+    match serde_json::from_slice::<Value>(&body) {
+        Ok(Value::Object(body)) => Some(body),
+        // body of a HEAD response, stripped by the router
+        _ if body.len() != size => {
+            response.data = rouille::ResponseBody::from_reader_and_size(std::io::empty(), size);
+            None
+        }
+        _ => {
+            response.data = rouille::ResponseBody::from_data(body);
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn error_vocabulary() {
+        assert_eq!(ErrorResponse::forbidden().status_code, 403);
+        assert_eq!(ErrorResponse::unsupported_media_type().status_code, 415);
+        assert_eq!(ErrorResponse::not_found().status_code, 404);
+        assert_eq!(HttpError::NotFound.message(), "Could not find resource");
+        assert_eq!(HttpError::Forbidden.message(), "Forbidden");
+
+        let response = ErrorResponse::too_many_requests(30);
+        assert_eq!(response.status_code, 429);
+        assert!(response
+            .headers
+            .iter()
+            .any(|(n, v)| n == "Retry-After" && v == "30"));
+
+        struct Teapot;
+        impl ResponseError for Teapot {
+            fn status(&self) -> u16 {
+                418
+            }
+        }
+        let error = KontrolError::from(Teapot);
+        assert_eq!(error.status(), 418);
+        assert_eq!(error.message(), "Client Error");
+        assert_eq!(error.response().status_code, 418);
+    }
+
+    #[test]
+    fn render_kontroller_errors() {
+        use crate::testing::{TestNode, TestRequest};
+        use crate::{Kong, Kontrol, Method};
+
+        struct Upload;
+
+        impl Kontrol for Upload {
+            fn address(&self) -> String {
+                "/upload".to_string()
+            }
+            fn method(&self) -> Method {
+                Method::Post
+            }
+            fn try_kontrol(&self, kong: &Kong) -> Result<rouille::Response, KontrolError> {
+                let query = kong.query.as_ref().unwrap();
+                if query.get("type") != Some("png") {
+                    Err(HttpError::UnsupportedMediaType)?;
+                }
+                let size: u32 = query.extract::<Map<String, Value>>()?["size"]
+                    .as_str()
+                    .unwrap_or_default()
+                    .parse()
+                    .map_err(|_| HttpError::UnprocessableEntity)?;
+                Ok(rouille::Response::text(size.to_string()))
+            }
+            fn kontrol(&self, kong: &Kong) -> rouille::Response {
+                self.try_kontrol(kong)
+                    .unwrap_or_else(|error| error.response())
+            }
+        }
+
+        let node = TestNode::minimal(vec![Box::new(Upload)]).unwrap();
+
+        node.send(TestRequest::post("/upload?type=gif"))
+            .assert_status(415);
+        assert_eq!(
+            node.send(TestRequest::post("/upload?type=png&size=big"))
+                .assert_status(422)
+                .json()["error_message"],
+            "Unprocessable Entity"
+        );
+        assert_eq!(
+            node.send(TestRequest::post("/upload?type=png&size=12"))
+                .assert_status(200)
+                .text(),
+            "12"
+        );
     }
 }
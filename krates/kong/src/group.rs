@@ -0,0 +1,312 @@
+//! 🗂️ `kong` route groups
+//!
+//! A group mounts kontrollers under a shared address prefix, and
+//! applies shared guards, middleware and a CORS policy to them. Groups
+//! can be nested, and mounted side by side to version an API:
+//!
+//! ```no_run
+//! use kong::{group::Group, kroute, ErrorResponse, Kong, KontrollerHandle};
+//!
+//! fn kontrollers() -> Vec<KontrollerHandle> {
+//!     // ...
+//! #   vec![]
+//! }
+//!
+//! let admin = Group::new("/admin")
+//!     .guard(|kong: &Kong| match &kong.kpassport {
+//!         Some(_) => Ok(()),
+//!         None => Err(ErrorResponse::unauthorized()),
+//!     })
+//!     .kontrollers(kontrollers());
+//!
+//! let v1 = Group::new("/api/v1").kontrollers(kontrollers()).group(admin);
+//! let v2 = Group::new("/api/v2").kontrollers(kontrollers());
+//!
+//! let mut kontrollers = v1.into_kontrollers();
+//! kontrollers.extend(v2.into_kontrollers());
+//! kroute(kontrollers).expect("kong node failed");
+//! ```
+//!
+//! The authorization policy of a group (see [`crate::AuthPolicy`])
+//! applies to all of its kontrollers, unless they have a stricter one.
+//! Guards and middleware of a group run around its kontrollers, after
+//! the request has been routed, rate limited and its input validated.
+//! Guards and middleware of outer groups run before those of inner
+//! groups. The CORS policy of the innermost group applies, unless the
+//! route has its own policy in the configuration.
+
+use crate::error_response::render;
+use crate::health::HealthCheck;
+use crate::{AuthPolicy, CorsKonfig, Kong, Kontrol, KontrollerHandle, Method};
+use rouille::{Request, Response};
+use std::sync::Arc;
+
+/// 🛡️ Guard, decides whether a request may reach the kontrollers of a
+/// group. A rejected request is answered with the returned response.
+pub type Guard = Box<dyn Fn(&Kong) -> Result<(), Response> + Send + Sync>;
+
+/// 🧅 Middleware, wraps the kontrollers of a group. It receives the
+/// next handler, that it may call (or not) and whose response it may
+/// change.
+pub type Middleware = Box<dyn Fn(&Kong, &dyn Fn(&Kong) -> Response) -> Response + Send + Sync>;
+
+/// Guards, middleware, authorization and CORS policy shared by the
+/// kontrollers of a group
+#[derive(Default)]
+struct Policy {
+    auth: AuthPolicy,
+    guards: Vec<Guard>,
+    middleware: Vec<Middleware>,
+    cors: Option<CorsKonfig>,
+}
+
+/// 🗂️ Route group
+pub struct Group {
+    prefix: String,
+    kontrollers: Vec<KontrollerHandle>,
+    policy: Policy,
+}
+
+impl Group {
+    /// Create a group mounted at `prefix`
+    pub fn new(prefix: &str) -> Self {
+        Group {
+            prefix: prefix.trim_end_matches('/').to_string(),
+            kontrollers: Vec::new(),
+            policy: Default::default(),
+        }
+    }
+
+    /// Add a kontroller to the group
+    pub fn kontroller(mut self, kontroller: KontrollerHandle) -> Self {
+        self.kontrollers.push(kontroller);
+        self
+    }
+
+    /// Add kontrollers to the group
+    pub fn kontrollers(mut self, kontrollers: Vec<KontrollerHandle>) -> Self {
+        self.kontrollers.extend(kontrollers);
+        self
+    }
+
+    /// Nest a group, its prefix is appended to the prefix of this group
+    pub fn group(self, group: Group) -> Self {
+        self.kontrollers(group.into_kontrollers())
+    }
+
+    /// Add a guard, guards run in the order they are added
+    pub fn guard<G>(mut self, guard: G) -> Self
+    where
+        G: Fn(&Kong) -> Result<(), Response> + Send + Sync + 'static,
+    {
+        self.policy.guards.push(Box::new(guard));
+        self
+    }
+
+    /// Add a middleware, the first middleware added is the outermost
+    pub fn middleware<M>(mut self, middleware: M) -> Self
+    where
+        M: Fn(&Kong, &dyn Fn(&Kong) -> Response) -> Response + Send + Sync + 'static,
+    {
+        self.policy.middleware.push(Box::new(middleware));
+        self
+    }
+
+    /// Set the authorization policy of the group, kontrollers with a
+    /// stricter policy keep theirs
+    pub fn auth(mut self, policy: AuthPolicy) -> Self {
+        self.policy.auth = policy;
+        self
+    }
+
+    /// Set the CORS policy of the group, see [`crate::cors`]
+    pub fn cors(mut self, policy: CorsKonfig) -> Self {
+        self.policy.cors = Some(policy);
+        self
+    }
+
+    /// Kontrollers of the group, ready to be routed by [`crate::kroute`]
+    pub fn into_kontrollers(self) -> Vec<KontrollerHandle> {
+        let policy = Arc::new(self.policy);
+
+        self.kontrollers
+            .into_iter()
+            .map(|kontroller| -> KontrollerHandle {
+                Box::new(GroupKontroller {
+                    address: join(&self.prefix, &kontroller.address()),
+                    kontroller,
+                    policy: policy.clone(),
+                })
+            })
+            .collect()
+    }
+}
+
+/// Kontroller mounted in a group
+struct GroupKontroller {
+    address: String,
+    kontroller: KontrollerHandle,
+    policy: Arc<Policy>,
+}
+
+impl GroupKontroller {
+    /// Call the middleware starting at `index`, the kontroller is
+    /// called after the last middleware
+    fn next(&self, kong: &Kong, index: usize) -> Response {
+        match self.policy.middleware.get(index) {
+            Some(middleware) => middleware(kong, &|kong: &Kong| self.next(kong, index + 1)),
+            None => render(self.kontroller.try_kontrol(kong)),
+        }
+    }
+}
+
+impl Kontrol for GroupKontroller {
+    fn address(&self) -> String {
+        self.address.clone()
+    }
+
+    fn method(&self) -> Method {
+        self.kontroller.method()
+    }
+
+    fn listener(&self) -> Option<String> {
+        self.kontroller.listener()
+    }
+
+    fn auth(&self) -> AuthPolicy {
+        self.kontroller.auth().max(self.policy.auth)
+    }
+
+    fn cors(&self) -> Option<&CorsKonfig> {
+        self.kontroller.cors().or(self.policy.cors.as_ref())
+    }
+
+    fn health_checks(&self) -> Vec<Arc<dyn HealthCheck>> {
+        self.kontroller.health_checks()
+    }
+
+    fn summary(&self) -> Option<String> {
+        self.kontroller.summary()
+    }
+
+    fn input_schema(&self) -> Option<serde_json::Value> {
+        self.kontroller.input_schema()
+    }
+
+    fn output_schema(&self) -> Option<serde_json::Value> {
+        self.kontroller.output_schema()
+    }
+
+    fn get_input(&self, request: &Request) -> Option<serde_json::Value> {
+        self.kontroller.get_input(request)
+    }
+
+    fn validate(&self, input: Option<serde_json::Value>) -> Result<Option<serde_json::Value>, ()> {
+        self.kontroller.validate(input)
+    }
+
+    fn kontrol(&self, kong: &Kong) -> Response {
+        for guard in &self.policy.guards {
+            if let Err(response) = guard(kong) {
+                return response;
+            }
+        }
+
+        self.next(kong, 0)
+    }
+}
+
+/// Join a group prefix and a kontroller address
+fn join(prefix: &str, address: &str) -> String {
+    match address.trim_start_matches('/') {
+        "" if prefix.is_empty() => "/".to_string(),
+        "" => prefix.to_string(),
+        address => format!("{prefix}/{address}"),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::ErrorResponse;
+
+    struct TestKontroller {
+        address: &'static str,
+    }
+
+    impl Kontrol for TestKontroller {
+        fn address(&self) -> String {
+            self.address.to_string()
+        }
+        fn method(&self) -> Method {
+            Method::Get
+        }
+        fn kontrol(&self, _kong: &Kong) -> Response {
+            Response::text("kong")
+        }
+    }
+
+    fn kong() -> Kong {
+        let working_dir = std::env::temp_dir().join(format!("kong-group-{}/", std::process::id()));
+        Kong::new(crate::testing::konfig(&format!(
+            "working_directory = \"{}\"",
+            working_dir.display()
+        )))
+    }
+
+    #[test]
+    fn prefixed_addresses() {
+        let inner = Group::new("/admin/").kontroller(Box::new(TestKontroller { address: "/" }));
+        let kontrollers = Group::new("/api/v1")
+            .kontroller(Box::new(TestKontroller {
+                address: "/users/:id",
+            }))
+            .group(inner)
+            .into_kontrollers();
+
+        let addresses: Vec<String> = kontrollers.iter().map(|k| k.address()).collect();
+        assert_eq!(addresses, vec!["/api/v1/users/:id", "/api/v1/admin"]);
+    }
+
+    #[test]
+    fn guards_and_middleware() {
+        let kontrollers = Group::new("/admin")
+            .guard(|kong: &Kong| match kong.remote_addr {
+                Some(_) => Ok(()),
+                None => Err(ErrorResponse::forbidden()),
+            })
+            .middleware(|kong: &Kong, next: &dyn Fn(&Kong) -> Response| {
+                next(kong).with_additional_header("X-Group", "admin")
+            })
+            .cors(CorsKonfig {
+                origins: vec!["*".to_string()],
+                ..Default::default()
+            })
+            .kontroller(Box::new(TestKontroller { address: "/stats" }))
+            .into_kontrollers();
+        let kontroller = &kontrollers[0];
+        let mut kong = kong();
+
+        // rejected by the guard, the middleware is not called
+        let response = kontroller.kontrol(&kong);
+        assert!(!response.is_success());
+        assert!(!response.headers.iter().any(|(h, _)| h == "X-Group"));
+
+        kong.remote_addr = Some("127.0.0.1:9000".parse().unwrap());
+        let response = kontroller.kontrol(&kong);
+        assert!(response.is_success());
+        assert!(response
+            .headers
+            .iter()
+            .any(|(h, v)| h == "X-Group" && v == "admin"));
+
+        assert!(kontroller.cors().is_some());
+        assert_eq!(kontroller.auth(), AuthPolicy::Public);
+
+        let kontrollers = Group::new("/admin")
+            .auth(AuthPolicy::Admin)
+            .kontroller(Box::new(TestKontroller { address: "/stats" }))
+            .into_kontrollers();
+        assert_eq!(kontrollers[0].auth(), AuthPolicy::Admin);
+    }
+}
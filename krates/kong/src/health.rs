@@ -0,0 +1,473 @@
+//! 🩺 `kong` health endpoints
+//!
+//! A node serves a liveness endpoint, answering `200 OK` as long as it
+//! handles requests, and a readiness endpoint, answering `200 OK` only
+//! if all its readiness checks pass and `503 Service Unavailable`
+//! otherwise:
+//!
+//! ```toml
+//! [health]
+//! liveness = "/healthz"
+//! readiness = "/readyz"
+//! min_free_space = 104857600
+//! ```
+//!
+//! ```json
+//! {
+//!   "status": "unavailable",
+//!   "checks": {
+//!     "working_directory": { "status": "ok" },
+//!     "disk_space": { "status": "fail", "error": "5242880 bytes free" },
+//!     "database": { "status": "ok" }
+//!   }
+//! }
+//! ```
+//!
+//! The working directory is checked to be writable and, if
+//! `min_free_space` is set, to have that many bytes of free disk space.
+//! Checks run with the configuration of the node, without waiting for
+//! the requests it handles. Kontrollers add the checks of the resources
+//! they depend on, for example database reachability:
+//!
+//! ```
+//! use kong::health::{Check, HealthCheck};
+//! use kong::{server, Kong, Kontrol, Method};
+//! use std::sync::Arc;
+//!
+//! struct Users;
+//!
+//! impl Kontrol for Users {
+//!     fn address(&self) -> String {
+//!         "/users".to_string()
+//!     }
+//!     fn method(&self) -> Method {
+//!         Method::Get
+//!     }
+//!     fn health_checks(&self) -> Vec<Arc<dyn HealthCheck>> {
+//!         vec![Arc::new(Check::new("database", |_config| {
+//!             // connect to the database
+//!             Ok(())
+//!         }))]
+//!     }
+//!     fn kontrol(&self, _kong: &Kong) -> server::Response {
+//!         server::Response::text("users")
+//!     }
+//! }
+//! ```
+//!
+//! Every node runs the checks of its own kontrollers, a check named
+//! like one that comes before it is not run again.
+
+use crate::konfig::{HealthKonfig, Konfig};
+use crate::router::{strip_body, Route};
+use crate::{Kong, Kontrol, KontrollerHandle, Method};
+use serde_json::{json, Map, Value};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+
+/// 🩺 Readiness check
+pub trait HealthCheck: Send + Sync {
+    /// Name of the check, in the readiness report
+    fn name(&self) -> String;
+    /// Run the check with the configuration of the node, the error
+    /// describes why it failed
+    fn check(&self, config: &Konfig) -> Result<(), String>;
+}
+
+/// 🩺 Readiness check of a function
+pub struct Check<F> {
+    name: String,
+    check: F,
+}
+
+impl<F> Check<F>
+where
+    F: Fn(&Konfig) -> Result<(), String> + Send + Sync,
+{
+    /// Create a check named `name`
+    pub fn new(name: &str, check: F) -> Self {
+        Check {
+            name: name.to_string(),
+            check,
+        }
+    }
+}
+
+impl<F> HealthCheck for Check<F>
+where
+    F: Fn(&Konfig) -> Result<(), String> + Send + Sync,
+{
+    fn name(&self) -> String {
+        self.name.clone()
+    }
+
+    fn check(&self, config: &Konfig) -> Result<(), String> {
+        (self.check)(config)
+    }
+}
+
+/// Number of working directory probes, names the probe files
+static PROBES: AtomicUsize = AtomicUsize::new(0);
+
+/// 🩺 Working directory is writable
+pub struct WorkingDirWritable;
+
+impl HealthCheck for WorkingDirWritable {
+    fn name(&self) -> String {
+        "working_directory".to_string()
+    }
+
+    fn check(&self, config: &Konfig) -> Result<(), String> {
+        // every probe writes its own file, concurrent probes (of this
+        // process or of other nodes sharing the directory) do not
+        // remove each other's
+        let probe = std::path::Path::new(config.working_dir()).join(format!(
+            ".kong-health-{}-{}",
+            std::process::id(),
+            PROBES.fetch_add(1, Ordering::Relaxed)
+        ));
+        std::fs::write(&probe, b"kong")
+            .and_then(|_| std::fs::remove_file(&probe))
+            .map_err(|e| e.to_string())
+    }
+}
+
+/// 🩺 Working directory disk has enough free space
+pub struct DiskSpace {
+    /// Minimum free space in bytes
+    pub min_free_space: u64,
+}
+
+impl HealthCheck for DiskSpace {
+    fn name(&self) -> String {
+        "disk_space".to_string()
+    }
+
+    fn check(&self, config: &Konfig) -> Result<(), String> {
+        let free = fs2::available_space(config.working_dir()).map_err(|e| e.to_string())?;
+        if free < self.min_free_space {
+            return Err(format!("{free} bytes free"));
+        }
+        Ok(())
+    }
+}
+
+/// Readiness checks of a node: the working directory checks, followed
+/// by the checks of the `kontrollers`. Checks named like one before
+/// them are left out.
+pub(crate) fn node_checks(
+    kontrollers: &[KontrollerHandle],
+    config: &Konfig,
+) -> Vec<Arc<dyn HealthCheck>> {
+    let mut checks: Vec<Arc<dyn HealthCheck>> = vec![Arc::new(WorkingDirWritable)];
+    if let Some(min_free_space) = config.health.as_ref().and_then(|h| h.min_free_space) {
+        checks.push(Arc::new(DiskSpace { min_free_space }));
+    }
+
+    for check in kontrollers.iter().flat_map(|k| k.health_checks()) {
+        if !checks.iter().any(|other| other.name() == check.name()) {
+            checks.push(check);
+        }
+    }
+    checks
+}
+
+/// Run readiness checks, returns whether they all passed and the
+/// report of every check
+pub fn readiness(checks: &[Arc<dyn HealthCheck>], config: &Konfig) -> (bool, Value) {
+    let mut ready = true;
+    let mut report = Map::new();
+
+    for check in checks {
+        let status = match check.check(config) {
+            Ok(()) => json!({ "status": "ok" }),
+            Err(error) => {
+                ready = false;
+                json!({ "status": "fail", "error": error })
+            }
+        };
+        report.insert(check.name(), status);
+    }
+
+    let status = if ready { "ready" } else { "unavailable" };
+    (ready, json!({ "status": status, "checks": report }))
+}
+
+/// Answer a request routed to a health endpoint, `None` for requests
+/// of other endpoints. `checks` are the [`node_checks`]. The endpoints
+/// only need the configuration, so kroute answers them without locking
+/// the node: probes are answered while other requests are handled, and
+/// are not in the access log.
+pub(crate) fn respond(
+    config: &Konfig,
+    checks: &[Arc<dyn HealthCheck>],
+    route: &Route<'_>,
+) -> Option<rouille::Response> {
+    let health = config.health.as_ref()?;
+    let (kontroller, head) = match route {
+        Route::Kontroller(kontroller, _) => (kontroller, false),
+        Route::Head(kontroller, _) => (kontroller, true),
+        _ => return None,
+    };
+    if kontroller.method() != Method::Get || kontroller.listener() != health.listener {
+        return None;
+    }
+
+    let address = kontroller.address();
+    let response = if address == liveness_path(health) {
+        live()
+    } else if address == readiness_path(health) {
+        ready(checks, config)
+    } else {
+        return None;
+    };
+
+    if head {
+        Some(strip_body(response))
+    } else {
+        Some(response)
+    }
+}
+
+/// Address of the liveness endpoint
+fn liveness_path(config: &HealthKonfig) -> String {
+    config
+        .liveness
+        .clone()
+        .unwrap_or_else(|| crate::defaults::LIVENESS_PATH.to_string())
+}
+
+/// Address of the readiness endpoint
+fn readiness_path(config: &HealthKonfig) -> String {
+    config
+        .readiness
+        .clone()
+        .unwrap_or_else(|| crate::defaults::READINESS_PATH.to_string())
+}
+
+/// Response of the liveness endpoint
+fn live() -> rouille::Response {
+    rouille::Response::json(&json!({ "status": "ok" }))
+}
+
+/// Response of the readiness endpoint
+fn ready(checks: &[Arc<dyn HealthCheck>], config: &Konfig) -> rouille::Response {
+    let (ready, report) = readiness(checks, config);
+    let status = if ready { 200 } else { 503 };
+    rouille::Response::json(&report).with_status_code(status)
+}
+
+/// Kontroller of the liveness endpoint, routes requests to
+/// [`respond`]
+pub(crate) struct LivenessKontroller {
+    path: String,
+    listener: Option<String>,
+}
+
+impl LivenessKontroller {
+    pub(crate) fn new(config: &HealthKonfig) -> Self {
+        LivenessKontroller {
+            path: liveness_path(config),
+            listener: config.listener.clone(),
+        }
+    }
+}
+
+impl Kontrol for LivenessKontroller {
+    fn address(&self) -> String {
+        self.path.clone()
+    }
+
+    fn method(&self) -> Method {
+        Method::Get
+    }
+
+    fn listener(&self) -> Option<String> {
+        self.listener.clone()
+    }
+
+    fn summary(&self) -> Option<String> {
+        Some("Liveness of the node".to_string())
+    }
+
+    fn kontrol(&self, _kong: &Kong) -> rouille::Response {
+        live()
+    }
+}
+
+/// Kontroller of the readiness endpoint, routes requests to
+/// [`respond`]
+pub(crate) struct ReadinessKontroller {
+    config: HealthKonfig,
+    checks: Vec<Arc<dyn HealthCheck>>,
+}
+
+impl ReadinessKontroller {
+    pub(crate) fn new(config: &HealthKonfig, checks: Vec<Arc<dyn HealthCheck>>) -> Self {
+        ReadinessKontroller {
+            config: config.clone(),
+            checks,
+        }
+    }
+}
+
+impl Kontrol for ReadinessKontroller {
+    fn address(&self) -> String {
+        readiness_path(&self.config)
+    }
+
+    fn method(&self) -> Method {
+        Method::Get
+    }
+
+    fn listener(&self) -> Option<String> {
+        self.config.listener.clone()
+    }
+
+    fn summary(&self) -> Option<String> {
+        Some("Readiness of the node and the status of its checks".to_string())
+    }
+
+    fn kontrol(&self, kong: &Kong) -> rouille::Response {
+        ready(&self.checks, &kong.config)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::kroute::{builtin_kontrollers, handle};
+    use crate::rate_limit::RateLimiter;
+    use crate::router::Routes;
+    use crate::testing::{konfig, TestNode, TestRequest};
+    use std::sync::{mpsc, Mutex};
+    use std::time::Duration;
+
+    /// Kontroller depending on an unreachable database
+    struct Users;
+
+    impl Kontrol for Users {
+        fn address(&self) -> String {
+            "/users".to_string()
+        }
+        fn method(&self) -> Method {
+            Method::Get
+        }
+        fn health_checks(&self) -> Vec<Arc<dyn HealthCheck>> {
+            vec![Arc::new(Check::new("database", |_config| {
+                Err("unreachable".to_string())
+            }))]
+        }
+        fn kontrol(&self, _kong: &Kong) -> rouille::Response {
+            rouille::Response::text("users")
+        }
+    }
+
+    #[test]
+    fn health_endpoints() {
+        let config = konfig(
+            r#"
+            [health]
+            readiness = "/ready"
+            min_free_space = 1
+            "#,
+        );
+        let node = TestNode::new(config, vec![]).unwrap();
+
+        node.send(TestRequest::get("/healthz"))
+            .assert_status(200)
+            .assert_json(&json!({ "status": "ok" }));
+
+        let response = node.send(TestRequest::get("/ready"));
+        response.assert_status(200);
+        let report = response.json();
+        assert_eq!(report["status"], "ready");
+        assert_eq!(report["checks"]["working_directory"]["status"], "ok");
+        assert_eq!(report["checks"]["disk_space"]["status"], "ok");
+
+        // failing checks, the checks of the kontrollers run on their
+        // node only
+        let config = konfig(&format!(
+            r#"
+            [health]
+            readiness = "/ready"
+            min_free_space = {}
+            "#,
+            i64::MAX
+        ));
+        let mut kontrollers = crate::group::Group::new("/v1")
+            .kontroller(Box::new(Users))
+            .into_kontrollers();
+        kontrollers.push(Box::new(Users));
+        let node = TestNode::new(config, kontrollers).unwrap();
+
+        let response = node.send(TestRequest::get("/ready"));
+        response.assert_status(503);
+        let report = response.json();
+        assert_eq!(report["status"], "unavailable");
+        assert_eq!(report["checks"]["working_directory"]["status"], "ok");
+        assert_eq!(report["checks"]["database"]["error"], "unreachable");
+        assert_eq!(report["checks"]["disk_space"]["status"], "fail");
+        assert_eq!(report["checks"].as_object().unwrap().len(), 3);
+    }
+
+    #[test]
+    fn concurrent_probes() {
+        let working_dir = std::env::temp_dir().join(format!("kong-probes-{}/", std::process::id()));
+        std::fs::create_dir_all(&working_dir).unwrap();
+        let config = konfig(&format!(
+            r#"working_directory = "{}""#,
+            working_dir.display()
+        ));
+        std::thread::scope(|scope| {
+            for _ in 0..8 {
+                scope.spawn(|| {
+                    for _ in 0..50 {
+                        assert_eq!(WorkingDirWritable.check(&config), Ok(()));
+                    }
+                });
+            }
+        });
+    }
+
+    #[test]
+    fn answer_without_the_node() {
+        let working_dir = std::env::temp_dir().join(format!("kong-health-{}/", std::process::id()));
+        let config = konfig(&format!(
+            r#"
+            working_directory = "{}"
+
+            [health]
+            "#,
+            working_dir.display()
+        ));
+        let mut kontrollers = Vec::new();
+        builtin_kontrollers(&mut kontrollers, &config);
+        let checks = node_checks(&kontrollers, &config);
+        let routes = Routes::new(kontrollers).unwrap();
+        let kong = Mutex::new(Kong::new(config.clone()));
+        let rate_limiter = Mutex::new(RateLimiter::new());
+
+        // a long request holds the node
+        let locked = kong.lock().unwrap();
+        let (sender, receiver) = mpsc::channel();
+        let statuses: Vec<_> = std::thread::scope(|scope| {
+            scope.spawn(|| {
+                for url in ["/healthz", "/readyz"] {
+                    let request = rouille::Request::fake_http("GET", url, vec![], vec![]);
+                    let response =
+                        handle(&request, &routes, &config, &checks, &kong, &rate_limiter);
+                    sender.send(response.status_code).unwrap();
+                }
+            });
+
+            let statuses = (0..2)
+                .map(|_| receiver.recv_timeout(Duration::from_secs(5)))
+                .collect();
+            // let the probes finish if they wait for the node after all
+            drop(locked);
+            statuses
+        });
+        assert_eq!(statuses, [Ok(200), Ok(200)]);
+    }
+}
@@ -1,17 +1,34 @@
 //! 🎛️ `kong` server configuration
 
+use crate::access_log::AccessLogFormat;
 use crate::defaults;
 use crate::error::KError;
-use serde::Deserialize;
+use crate::log::{Level, LogFormat};
+use crate::problem::ErrorFormat;
+use crate::AuthPolicy;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::path::Path;
 use std::{env, fs};
 
 /// 🎛️ Server configuration
-#[derive(Deserialize)]
+#[derive(Deserialize, Clone)]
 pub struct Konfig {
     /// Port to access the server
     pub port: u16,
+    /// Address to listen on, a hostname, an IPv4 address (`0.0.0.0`
+    /// for all interfaces), an IPv6 address (`::` for all interfaces)
+    /// or a Unix domain socket (`unix:/path/to/socket`).
+    /// __defaults to localhost__
+    pub bind: Option<String>,
+    /// Additional listeners, keyed by name. Kontrollers are served by
+    /// the listener named by [`crate::Kontrol::listener`]
+    pub listeners: Option<HashMap<String, ListenerKonfig>>,
     /// Admin email address
     pub admin_email: Option<String>,
+    /// Kpassport usernames of the node administrators, allowed to call
+    /// endpoints with the [`crate::AuthPolicy::Admin`] policy
+    pub admins: Option<Vec<String>>,
     /// Kong server working directory, path should end with `/`
     /// __defaults to kong/__
     pub working_directory: Option<String>,
@@ -30,23 +47,284 @@ pub struct Konfig {
     /// stored in the working directory as LOG.
     /// Logging to the LOG file is __disabled__ by default
     pub log_file: Option<bool>,
+    /// Minimum level of logged entries, __defaults to info__
+    pub log_level: Option<Level>,
+    /// Format of log entries, `text` or `json`, __defaults to text__
+    pub log_format: Option<LogFormat>,
+    /// Access log, __if not provided requests are logged to the
+    /// application log__
+    pub access_log: Option<AccessLogKonfig>,
+    /// Rotation of the LOG and access log files, __if not provided log
+    /// files are never rotated__
+    pub log_rotation: Option<LogRotationKonfig>,
+    /// Login brute-force protection, see [`crate::throttle`]
+    pub login_throttle: Option<LoginThrottleKonfig>,
+    /// Rate limit applied to all requests
+    pub rate_limit: Option<RateLimitKonfig>,
+    /// Route specific configuration, keyed by kontroller address
+    pub routes: Option<HashMap<String, RouteKonfig>>,
+    /// Maximum size of a request body in bytes, __defaults to 1 MiB__
+    pub max_body_size: Option<u64>,
+    /// Maximum size of all request headers in bytes, __defaults to 8 KiB__
+    pub max_header_size: Option<usize>,
+    /// Number of seconds a client has to send a request (its headers
+    /// and body), idle connections are closed after as long. Requests
+    /// still waiting for the node when it elapses are answered with
+    /// `503 Service Unavailable`, kontrollers that already run are not
+    /// interrupted. __defaults to 30__
+    pub request_timeout: Option<u64>,
+    /// TLS configuration, __if not provided the node serves plain HTTP__
+    pub tls: Option<TlsKonfig>,
+    /// Number of seconds in-flight requests are given to finish when
+    /// the node stops, __defaults to 30__
+    pub shutdown_timeout: Option<u64>,
+    /// Number of connections each listener serves at once, further
+    /// clients wait to be accepted. __defaults to 512__
+    pub max_connections: Option<usize>,
+    /// CORS policy of all routes, __if not provided cross-origin
+    /// requests are not allowed__
+    pub cors: Option<CorsKonfig>,
+    /// Route table endpoint, __if not provided the route table is not
+    /// served__
+    pub route_table: Option<RouteTableKonfig>,
+    /// OpenAPI document endpoint, __if not provided the document is not
+    /// served__
+    pub openapi: Option<OpenApiKonfig>,
+    /// Metrics endpoint, __if not provided metrics are not served__
+    pub metrics: Option<MetricsKonfig>,
+    /// Health endpoints, __if not provided they are not served__
+    pub health: Option<HealthKonfig>,
+    /// Format of error responses, `message` or `problem` (RFC 7807
+    /// problem details), __defaults to message__
+    pub error_format: Option<ErrorFormat>,
+}
+
+/// 🗒️ Access log configuration, see [`crate::access_log`]
+#[derive(Deserialize, Clone)]
+pub struct AccessLogKonfig {
+    /// Format of the entries, `common`, `combined` or `json`,
+    /// __defaults to combined__
+    pub format: Option<AccessLogFormat>,
+    /// Name of the access log file in the working directory,
+    /// __defaults to ACCESS_LOG__
+    pub file: Option<String>,
+}
+
+/// 🔄 Log file rotation configuration, see [`crate::rotation`]
+#[derive(Deserialize, Clone, Default, Debug)]
+pub struct LogRotationKonfig {
+    /// Size in bytes over which a log file is rotated, __if not
+    /// provided files are not rotated by size__
+    pub max_size: Option<u64>,
+    /// Rotate log files every day, __defaults to false__
+    pub daily: Option<bool>,
+    /// Number of rotated files retained, __defaults to 7__
+    pub keep: Option<usize>,
+    /// Gzip compress rotated files, __defaults to false__
+    pub compress: Option<bool>,
+}
+
+/// 🩺 Health endpoints configuration, see [`crate::health`]
+#[derive(Deserialize, Clone, Default)]
+pub struct HealthKonfig {
+    /// Address of the liveness endpoint, __defaults to /healthz__
+    pub liveness: Option<String>,
+    /// Address of the readiness endpoint, __defaults to /readyz__
+    pub readiness: Option<String>,
+    /// Listener serving the endpoints, __defaults to the main listener__
+    pub listener: Option<String>,
+    /// Minimum free disk space of the working directory in bytes for
+    /// the node to be ready, __if not provided disk space is not
+    /// checked__
+    pub min_free_space: Option<u64>,
+}
+
+/// 📈 Metrics endpoint configuration, see [`crate::metrics`]
+#[derive(Deserialize, Clone)]
+pub struct MetricsKonfig {
+    /// Address of the endpoint, for example `/metrics`
+    pub path: String,
+    /// Listener serving the endpoint, __defaults to the main listener__
+    pub listener: Option<String>,
+    /// Authorization policy of the endpoint, `public`, `kpassport` or
+    /// `admin`, __defaults to admin, or to public if the endpoint is
+    /// served by another `listener`__
+    pub auth: Option<AuthPolicy>,
+}
+
+/// 📜 OpenAPI document endpoint configuration, see [`crate::openapi`]
+#[derive(Deserialize, Clone)]
+pub struct OpenApiKonfig {
+    /// Address of the endpoint, for example `/openapi.json`
+    pub path: String,
+    /// Listener serving the endpoint, the document only describes the
+    /// routes of this listener, __defaults to the main listener__
+    pub listener: Option<String>,
+    /// Version of the API, __defaults to 0.0.0__
+    pub version: Option<String>,
+}
+
+/// 🧭 Route table endpoint configuration, see [`crate::route_table`]
+#[derive(Deserialize, Clone)]
+pub struct RouteTableKonfig {
+    /// Address of the endpoint, for example `/routes`
+    pub path: String,
+    /// Listener serving the endpoint, __defaults to the main listener__
+    pub listener: Option<String>,
+}
+
+/// 📡 Additional listener configuration
+#[derive(Deserialize, Clone)]
+pub struct ListenerKonfig {
+    /// Address to listen on, see [`Konfig::bind`]
+    pub bind: Option<String>,
+    /// Port of the listener, not needed for Unix domain sockets
+    pub port: Option<u16>,
+    /// TLS configuration of the listener
+    pub tls: Option<TlsKonfig>,
+}
+
+/// 🔒 TLS configuration
+#[derive(Deserialize, Clone)]
+pub struct TlsKonfig {
+    /// Path to the PEM encoded certificate chain
+    pub certificate: String,
+    /// Path to the PEM encoded private key
+    pub private_key: String,
+    /// Port of a plain HTTP listener that redirects clients to HTTPS,
+    /// __if not provided there is no redirect listener__
+    pub redirect_port: Option<u16>,
+}
+
+/// 🛤️ Route specific configuration
+#[derive(Deserialize, Clone, Default)]
+pub struct RouteKonfig {
+    /// Rate limit of the route, applied in addition to the global
+    /// rate limit
+    pub rate_limit: Option<RateLimitKonfig>,
+    /// Maximum size of a request body in bytes, overrides the global
+    /// `max_body_size`
+    pub max_body_size: Option<u64>,
+    /// CORS policy of the route, overrides the global `cors` policy
+    pub cors: Option<CorsKonfig>,
+}
+
+/// 🌍 CORS policy, see [`crate::cors`]
+#[derive(Deserialize, Clone, Default, Debug)]
+pub struct CorsKonfig {
+    /// Allowed origins, exact (`https://app.example.com`), patterns
+    /// (`https://*.example.com`) or `*` for any origin
+    pub origins: Vec<String>,
+    /// Allowed methods, __defaults to the methods of the route__
+    pub methods: Option<Vec<String>>,
+    /// Allowed request headers, __defaults to the headers requested by
+    /// the browser__
+    pub headers: Option<Vec<String>>,
+    /// Response headers exposed to the web application
+    pub expose_headers: Option<Vec<String>>,
+    /// Whether requests with credentials (cookies) are allowed, only
+    /// together with an explicit list of `origins` (no `*`),
+    /// __defaults to false__
+    pub credentials: Option<bool>,
+    /// Number of seconds browsers may cache a preflight response
+    pub max_age: Option<u64>,
+}
+
+/// What requests are counted together
+#[derive(Deserialize, Serialize, Clone, Copy, PartialEq, Eq, Debug, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum RateLimitKey {
+    /// Each client IP address has its own limit, Unix domain socket
+    /// clients are limited by the user ID of their process
+    #[default]
+    Ip,
+    /// Each kpassport username has its own limit, requests without a
+    /// valid kpassport are limited by IP address
+    Kpassport,
+    /// All requests to a route share a single limit
+    Route,
+}
+
+/// 🎡 Rate limit configuration, see [`crate::rate_limit`]
+#[derive(Deserialize, Serialize, Clone, Debug)]
+pub struct RateLimitKonfig {
+    /// Number of requests allowed per period (bucket size)
+    pub requests: u32,
+    /// Period in seconds
+    pub period: u64,
+    /// How requests are counted together, __defaults to `ip`__
+    pub key: Option<RateLimitKey>,
+}
+
+/// 🧯 Login brute-force protection configuration
+#[derive(Deserialize, Clone, Default)]
+pub struct LoginThrottleKonfig {
+    /// Failed logins allowed per username before the account is
+    /// locked, __defaults to 5__
+    pub max_failures: Option<u32>,
+    /// Failed logins allowed per client IP address before the address
+    /// is locked, __defaults to 20__
+    pub ip_max_failures: Option<u32>,
+    /// Delay in seconds after the first failed login, the delay doubles
+    /// with every failure. __defaults to 1__
+    pub base_delay: Option<u64>,
+    /// Maximum delay in seconds between failed logins, __defaults to 60__
+    pub max_delay: Option<u64>,
+    /// Number of seconds a locked account stays locked, __defaults to 900__
+    pub lockout: Option<u64>,
+    /// Number of usernames and IP addresses tracked, the least recently
+    /// failed are forgotten first. __defaults to 100000__
+    pub max_tracked: Option<usize>,
 }
 
 impl Konfig {
+    /// Working directory of the node
+    pub fn working_dir(&self) -> &str {
+        self.working_directory
+            .as_deref()
+            .unwrap_or(defaults::WORKING_DIRECTORY)
+    }
+
+    /// Get the configuration of a route
+    pub fn route(&self, address: &str) -> Option<&RouteKonfig> {
+        self.routes.as_ref().and_then(|routes| routes.get(address))
+    }
+
     /// Read server config file from path provided as an argument when
     /// the program was started.
     pub fn read() -> Result<Konfig, KError> {
         let arg = env::args().nth(1);
         match arg {
-            Some(a) => {
-                let toml_str = fs::read_to_string(a).map_err(|_| KError::Config)?;
-                let config: Konfig = toml::from_str(&toml_str).map_err(|_| KError::Config)?;
-                Ok(config)
-            }
+            Some(a) => Konfig::read_file(Path::new(&a)),
             None => panic!("Path to config file is not provided!"),
         }
     }
 
+    /// Read server config file from `path`
+    pub fn read_file(path: &Path) -> Result<Konfig, KError> {
+        let toml_str = fs::read_to_string(path).map_err(|source| KError::ConfigRead {
+            path: path.to_path_buf(),
+            source,
+        })?;
+        let config: Konfig =
+            toml::from_str(&toml_str).map_err(|source| KError::config_parse(Some(path), source))?;
+        config.validate()?;
+        Ok(config)
+    }
+
+    /// Check the settings that can be parsed but not used together
+    pub(crate) fn validate(&self) -> Result<(), KError> {
+        if let Some(cors) = &self.cors {
+            cors.validate("cors")?;
+        }
+        for (address, route) in self.routes.iter().flatten() {
+            if let Some(cors) = &route.cors {
+                cors.validate(&format!("routes.{address}.cors"))?;
+            }
+        }
+        Ok(())
+    }
+
     /// read port from config file
     pub fn read_port() -> u16 {
         let arg = env::args().nth(1);
@@ -78,18 +356,23 @@ impl Konfig {
         }
     }
 
-    /// read loggin
+    /// Read server config file from path provided as an argument when
+    /// the program was started, `None` if it can not be read (for
+    /// example when kong is used outside of a node in tests)
+    pub fn try_read() -> Option<Konfig> {
+        env::args()
+            .nth(1)
+            .and_then(|a| fs::read_to_string(a).ok())
+            .and_then(|toml_str| toml::from_str::<Konfig>(&toml_str).ok())
+    }
+
+    /// read loggin, nothing is logged if the config file cannot be
+    /// read (for example when kong is used outside of a node in tests)
+    #[deprecated(note = "logging is configured by `kroute` when the node starts, see `kong::log`")]
     pub fn read_logging() -> (Option<bool>, Option<bool>) {
-        let arg = env::args().nth(1);
-        match arg {
-            Some(a) => {
-                let toml_str = fs::read_to_string(a).unwrap();
-                let config: Konfig = toml::from_str(&toml_str).unwrap();
-                let console = config.console_log;
-                let file = config.log_file;
-                (console, file)
-            }
-            None => panic!("Path to config file is not provided!"),
+        match Konfig::try_read() {
+            Some(config) => (config.console_log, config.log_file),
+            None => (None, None),
         }
     }
 
@@ -100,8 +383,7 @@ impl Konfig {
             Some(a) => {
                 let toml_str = fs::read_to_string(a).unwrap();
                 let config: Konfig = toml::from_str(&toml_str).unwrap();
-                let hostname = config.hostname;
-                hostname
+                config.hostname
             }
             None => panic!("Path to config file is not provided!"),
         }
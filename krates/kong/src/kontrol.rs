@@ -1,8 +1,27 @@
 //! 🎮 Kong request endpoint kontroller
 
-use crate::{KError, Kong, Method};
+use crate::error_response::KontrolError;
+use crate::health::HealthCheck;
+use crate::{konfig::CorsKonfig, KError, Kong, Method};
 use rouille::{Request, Response};
 use route_recognizer::{Params, Router};
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+
+/// 🔑 Authorization policy of an endpoint, enforced by kroute before
+/// the kontroller is called
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Debug, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum AuthPolicy {
+    /// Anyone can call the endpoint
+    #[default]
+    Public,
+    /// Requests need a valid kpassport, others are answered with 401
+    Kpassport,
+    /// Requests need a valid kpassport of one of the node
+    /// [`crate::Konfig::admins`], others are answered with 401 or 403
+    Admin,
+}
 
 /// 🎮 API Enpoint kontrollers
 pub trait Kontrol {
@@ -10,18 +29,60 @@ pub trait Kontrol {
     fn address(&self) -> String;
     /// Enpoint method
     fn method(&self) -> Method;
+    /// Name of the listener that serves the endpoint, as configured in
+    /// [`crate::Konfig::listeners`]. `None` for the main listener.
+    fn listener(&self) -> Option<String> {
+        None
+    }
+    /// Authorization policy of the endpoint
+    fn auth(&self) -> AuthPolicy {
+        AuthPolicy::Public
+    }
+    /// Short description of the endpoint, used in the OpenAPI document
+    fn summary(&self) -> Option<String> {
+        None
+    }
+    /// JSON Schema of the endpoint input (the request body), used in
+    /// the OpenAPI document. See [`crate::openapi`]
+    fn input_schema(&self) -> Option<serde_json::Value> {
+        None
+    }
+    /// JSON Schema of the endpoint output, used in the OpenAPI document
+    fn output_schema(&self) -> Option<serde_json::Value> {
+        None
+    }
+    /// CORS policy of the endpoint, used when the route has no policy
+    /// in the configuration. See [`crate::cors`]
+    fn cors(&self) -> Option<&CorsKonfig> {
+        None
+    }
+    /// Readiness checks of the resources the endpoint depends on, run
+    /// by the readiness endpoint of the node. See [`crate::health`]
+    fn health_checks(&self) -> Vec<Arc<dyn HealthCheck>> {
+        Vec::new()
+    }
 
     /// Get user input
     fn get_input(&self, _request: &Request) -> Option<serde_json::Value> {
         None
     }
     /// Validate user input
+    #[allow(clippy::result_unit_err)]
     fn validate(&self, input: Option<serde_json::Value>) -> Result<Option<serde_json::Value>, ()> {
         Ok(input)
     }
 
-    /// Handle endpoint (business logic)
+    /// Handle endpoint (business logic), every kontroller implements it.
+    /// Kontrollers returning errors implement [`Kontrol::try_kontrol`]
+    /// as well and forward to it:
+    /// `self.try_kontrol(kong).unwrap_or_else(|error| error.response())`
     fn kontrol(&self, kong: &Kong) -> Response;
+    /// Handle endpoint (business logic), errors are rendered as
+    /// [`crate::ErrorResponse`]s by kroute, which calls this method rather
+    /// than [`Kontrol::kontrol`]. __defaults to [`Kontrol::kontrol`]__
+    fn try_kontrol(&self, kong: &Kong) -> Result<Response, KontrolError> {
+        Ok(self.kontrol(kong))
+    }
 
     /// url parameters extractor
     fn url_params(
@@ -34,7 +95,10 @@ pub trait Kontrol {
 
         match m {
             Ok(mtch) => Ok(mtch.params().clone()),
-            Err(_) => Err(KError::UrlParsing),
+            Err(reason) => Err(KError::UrlParsing {
+                url: url.to_string(),
+                reason,
+            }),
         }
     }
 }
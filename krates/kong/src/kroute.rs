@@ -1,98 +1,710 @@
 //! 🌀 `kong` request router
 
-use crate::{error_response::ErrorResponse, konfig::Konfig, Kong, Kontrol};
+use crate::error_response::{render, ErrorResponse};
+use crate::{konfig::Konfig, Kong};
 
-use crate::log::Log;
-use crate::{read_kpassport::get_kpassport, KError};
+use crate::access_log::AccessEntry;
+use crate::connection::{self, Limits, Peer};
+use crate::cors;
+use crate::health::{self, HealthCheck, LivenessKontroller, ReadinessKontroller};
+use crate::konfig::{CorsKonfig, RateLimitKey, RateLimitKonfig};
+use crate::limits::{check_body_size, header_size};
+use crate::listener::{self, Address};
+use crate::log::{Log, RequestScope};
+use crate::metrics::{self, MetricsKontroller};
+use crate::openapi::{self, OpenApiKontroller};
+use crate::problem::{self, ErrorFormat};
+use crate::query::Query;
+use crate::rate_limit::{RateLimitStatus, RateLimiter};
+use crate::recover;
+use crate::request_id;
+use crate::rotation;
+use crate::route_table::{RouteTable, RouteTableKontroller};
+use crate::router::{strip_body, Route, Routes};
+use crate::{defaults, read_kpassport::get_kpassport, KError, Shutdown};
+use crate::{AuthPolicy, KontrollerHandle};
+use chrono::{DateTime, Utc};
 use core::fmt;
-use route_recognizer::Router;
+use krypto::error::KryptoError;
+use rouille::ResponseBody;
+use route_recognizer::Params;
+use serde::Serialize;
+use serde_json::json;
+use std::collections::{HashMap, HashSet};
 use std::str::FromStr;
-use std::sync::Mutex;
+use std::sync::mpsc::{self, RecvTimeoutError};
+use std::sync::{Arc, Mutex, OnceLock};
+use std::thread;
+use std::time::{Duration, Instant};
 
-/// Kontoller Handle
-type KontrollerHandle = Box<dyn Kontrol + std::marker::Sync + std::marker::Send + 'static>;
+/// How often kroute checks whether the node is stopping
+const POLL_INTERVAL: Duration = Duration::from_millis(100);
 
-/// 🌀 `kong` request routing
-pub fn kroute(kontrollers: Vec<KontrollerHandle>) -> rouille::Response {
-    let port = Konfig::read_port();
-    let hostname = Konfig::read_hostname();
-    let address = format!("localhost:{port}");
+/// 🌀 `kong` request routing, until the node receives `SIGTERM` or
+/// `SIGINT`
+pub fn kroute(kontrollers: Vec<KontrollerHandle>) -> Result<(), KError> {
+    rotation::reopen_on_sighup()?;
+    kroute_until(kontrollers, Shutdown::on_signals()?)
+}
+
+/// 🌀 `kong` request routing, until `shutdown` is stopped. See
+/// [`crate::shutdown`] for what happens when the node stops.
+///
+/// The process should exit once it returns: requests that did not
+/// finish before the shutdown timeout are left running on their
+/// threads, which still own the kontrollers and the node.
+pub fn kroute_until(
+    mut kontrollers: Vec<KontrollerHandle>,
+    shutdown: Shutdown,
+) -> Result<(), KError> {
     let kong: Kong = Default::default();
-    let kong: Mutex<Kong> = Mutex::new(kong);
-    let mut router = Router::new();
+    Log::init(&kong.config)?;
+    let hostname = kong.config.hostname.clone();
+    let shutdown_timeout = kong
+        .config
+        .shutdown_timeout
+        .unwrap_or(defaults::SHUTDOWN_TIMEOUT);
+    let limits = Limits {
+        max_connections: kong
+            .config
+            .max_connections
+            .unwrap_or(defaults::MAX_CONNECTIONS),
+        max_body_size: largest_body_size(&kong.config),
+        max_header_size: kong
+            .config
+            .max_header_size
+            .unwrap_or(defaults::MAX_HEADER_SIZE),
+        timeout: Duration::from_secs(
+            kong.config
+                .request_timeout
+                .unwrap_or(defaults::REQUEST_TIMEOUT),
+        ),
+        shutdown_timeout: Duration::from_secs(shutdown_timeout),
+    };
+
+    // main listener, followed by the additional listeners
+    let bind = kong.config.bind.as_deref().unwrap_or(defaults::BIND);
+    let address = Address::new(bind, Some(kong.config.port))?;
+    let mut listeners = vec![(None, address, kong.config.tls.clone())];
+    for (name, listener) in kong.config.listeners.iter().flatten() {
+        let bind = listener.bind.as_deref().unwrap_or(defaults::BIND);
+        let address = Address::new(bind, listener.port).map_err(|error| match error {
+            KError::InvalidConfig { key, reason } => KError::InvalidConfig {
+                key: format!("listeners.{name}.{key}"),
+                reason,
+            },
+            error => error,
+        })?;
+        listeners.push((Some(name.clone()), address, listener.tls.clone()));
+    }
 
-    // prepare kontrollers for routing
+    // nodes created without reading a config file are checked as well
+    kong.config.validate()?;
+    for kontroller in &kontrollers {
+        if let Some(cors) = kontroller.cors() {
+            cors.validate(&format!("cors of {}", kontroller.address()))?;
+        }
+    }
+
+    let route_table = builtin_kontrollers(&mut kontrollers, &kong.config);
+    let checks: Arc<[Arc<dyn HealthCheck>]> =
+        health::node_checks(&kontrollers, &kong.config).into();
+
+    // prepare kontrollers for routing, each listener has its own routes
+    let mut listener_kontrollers: HashMap<Option<String>, Vec<KontrollerHandle>> = HashMap::new();
+    for (name, _, _) in &listeners {
+        listener_kontrollers.insert(name.clone(), Vec::new());
+    }
     for kontroller in kontrollers {
-        let kontroller_id = format!("{}{}", kontroller.method(), kontroller.address());
-        router.add(&kontroller_id, kontroller);
+        let Some(listener) = listener_kontrollers.get_mut(&kontroller.listener()) else {
+            return Err(KError::InvalidConfig {
+                key: format!("listeners.{}", kontroller.listener().unwrap_or_default()),
+                reason: format!("not configured, used by {}", kontroller.address()),
+            });
+        };
+        listener.push(kontroller);
+    }
+
+    // validate all routes before any listener starts
+    let mut listener_routes = HashMap::new();
+    for (name, kontrollers) in listener_kontrollers {
+        listener_routes.insert(name, Routes::new(kontrollers)?);
     }
 
-    Log::log(&format!("{hostname} node started @ {address}")).expect("Error while logging");
+    for route in &route_table.routes {
+        Log::log(&format!("route {route}"))?;
+    }
+
+    // the configuration does not change while the node runs, requests
+    // read it without locking the node
+    let config = Arc::new(kong.config.clone());
+    let kong: Arc<Mutex<Kong>> = Arc::new(Mutex::new(kong));
+    let rate_limiter: Arc<Mutex<RateLimiter>> = Arc::new(Mutex::new(RateLimiter::new()));
+    let (stopped, listener_stopped) = mpsc::channel();
+
+    for (name, address, tls) in listeners {
+        let routes = listener_routes.remove(&name).unwrap_or_default();
+        let handler = handler(
+            routes,
+            config.clone(),
+            checks.clone(),
+            kong.clone(),
+            rate_limiter.clone(),
+            shutdown.clone(),
+        );
+        let shutdown = shutdown.clone();
+        let stopped = stopped.clone();
 
-    rouille::start_server(address, move |request| {
-        let mut kong = kong.lock().unwrap();
+        match name {
+            Some(name) => Log::log(&format!("{hostname} {name} listener started @ {address}"))?,
+            None => Log::log(&format!("{hostname} node started @ {address}"))?,
+        }
+
+        thread::spawn(move || {
+            let result = listener::serve(&address, handler, tls.as_ref(), limits, &shutdown);
+            let _ = stopped.send(result);
+        });
+    }
+    drop(stopped);
 
-        // Handle static files
-        if let Some(path) = &kong.config.static_files_path {
-            let response = rouille::match_assets(request, &path);
-            if response.is_success() {
-                log_request(request, response.status_code);
-                return response;
+    // serve until the node is stopped, or a listener fails
+    let mut result = Ok(());
+    while !shutdown.is_stopped() {
+        match listener_stopped.recv_timeout(POLL_INTERVAL) {
+            Ok(Err(e)) => {
+                result = Err(e);
+                shutdown.stop();
             }
+            Ok(Ok(())) | Err(RecvTimeoutError::Timeout) => {}
+            Err(RecvTimeoutError::Disconnected) => break,
         }
+    }
+    // signals only set the flag, the listeners are woken here
+    shutdown.stop();
 
-        let response = filter(request, &router, &mut kong);
-        log_request(request, response.status_code);
-        response
-    })
+    // drain in-flight requests
+    Log::log(&format!("{hostname} node stopping"))?;
+    let deadline = Instant::now() + Duration::from_secs(shutdown_timeout);
+    loop {
+        let timeout = deadline.saturating_duration_since(Instant::now());
+        match listener_stopped.recv_timeout(timeout) {
+            Ok(_) => {}
+            Err(RecvTimeoutError::Disconnected) => break,
+            Err(RecvTimeoutError::Timeout) => {
+                Log::warn(
+                    "in-flight requests did not finish before the shutdown timeout",
+                    &[],
+                )?;
+                break;
+            }
+        }
+    }
+
+    // a request that did not finish may still hold the node
+    if let Ok(kong) = kong.try_lock() {
+        kong.login_throttle.flush();
+    }
+    Log::log(&format!("{hostname} node stopped"))?;
+    Log::flush()?;
+    result
+}
+
+/// Add the route table and OpenAPI document kontrollers, if they are
+/// configured. Returns the route table of all kontrollers.
+pub(crate) fn builtin_kontrollers(
+    kontrollers: &mut Vec<KontrollerHandle>,
+    config: &Konfig,
+) -> RouteTable {
+    let mut route_table_handle = None;
+    if let Some(route_table) = &config.route_table {
+        let (kontroller, handle) = RouteTableKontroller::new(route_table);
+        kontrollers.push(Box::new(kontroller));
+        route_table_handle = Some(handle);
+    }
+
+    let mut openapi_handle = None;
+    if let Some(openapi) = &config.openapi {
+        let (kontroller, handle) = OpenApiKontroller::new(openapi);
+        kontrollers.push(Box::new(kontroller));
+        openapi_handle = Some(handle);
+    }
+
+    if let Some(health) = &config.health {
+        kontrollers.push(Box::new(LivenessKontroller::new(health)));
+        let checks = health::node_checks(kontrollers, config);
+        kontrollers.push(Box::new(ReadinessKontroller::new(health, checks)));
+    }
+
+    if let Some(metrics) = &config.metrics {
+        kontrollers.push(Box::new(MetricsKontroller::new(metrics)));
+    }
+
+    let route_table = RouteTable::new(kontrollers, config);
+    if let Some(handle) = route_table_handle {
+        let _ = handle.set(route_table.clone());
+    }
+    if let Some(handle) = openapi_handle {
+        let _ = handle.set(openapi::document(kontrollers, config));
+    }
+    route_table
+}
+
+/// Request handler of a listener
+fn handler(
+    routes: Routes,
+    config: Arc<Konfig>,
+    checks: Arc<[Arc<dyn HealthCheck>]>,
+    kong: Arc<Mutex<Kong>>,
+    rate_limiter: Arc<Mutex<RateLimiter>>,
+    shutdown: Shutdown,
+) -> impl Fn(&rouille::Request) -> rouille::Response + Send + Sync + 'static {
+    move |request| {
+        // requests received while the node is stopping are refused
+        if shutdown.is_stopped() {
+            return ErrorResponse::service_unavailable()
+                .with_additional_header("Connection", "close");
+        }
+        handle(request, &routes, &config, &checks, &kong, &rate_limiter)
+    }
+}
+
+/// Handle a request, `config` is the configuration of the node and
+/// `checks` its readiness checks
+pub(crate) fn handle(
+    request: &rouille::Request,
+    routes: &Routes,
+    config: &Konfig,
+    checks: &[Arc<dyn HealthCheck>],
+    kong: &Mutex<Kong>,
+    rate_limiter: &Mutex<RateLimiter>,
+) -> rouille::Response {
+    let received = Instant::now();
+    let time = Utc::now();
+    let metrics = metrics::metrics();
+    let _in_flight = metrics.in_flight();
+    let id = request_id::from_request(request);
+    let _scope = RequestScope::enter(&id);
+
+    // Request handling deadline
+    let timeout = config.request_timeout.unwrap_or(defaults::REQUEST_TIMEOUT);
+    let deadline = received + Duration::from_secs(timeout);
+
+    // The connection received the body before the request is handled,
+    // it is checked against the size limit of its route
+    let route = routes.recognize(request.method(), &request.url());
+    let body_size = check_body_size(request, max_body_size(config, &route));
+    let (method, address) = metrics_labels(request, &route);
+
+    // health endpoints are answered without the node, so that they
+    // respond while a long request holds it
+    if let Some(response) = health::respond(config, checks, &route) {
+        let response = request_id::tag(response, &id);
+        metrics.request(&method, &address, response.status_code, received.elapsed());
+        return response;
+    }
+
+    // a panicking request poisons the locks, the state stays usable
+    let mut kong = kong.lock().unwrap_or_else(|e| e.into_inner());
+    let mut rate_limiter = rate_limiter.lock().unwrap_or_else(|e| e.into_inner());
+
+    // the kpassport of the previous request is not this request's
+    kong.kpassport = None;
+    kong.request_id = Some(id.clone());
+
+    let mut response = match body_size {
+        Ok(()) => {
+            recover::catch_panic(|| respond(request, route, &mut kong, &mut rate_limiter, deadline))
+        }
+        Err(response) => response,
+    };
+    if kong.config.error_format == Some(ErrorFormat::Problem) {
+        response = problem::convert(response, &request.url());
+    }
+    let response = request_id::tag(response, &id);
+    metrics.request(&method, &address, response.status_code, received.elapsed());
+    log_request(request, response, &mut kong, time, received)
+}
+
+/// Respond to a request routed to `route`, the steps of [`handle`]
+fn respond(
+    request: &rouille::Request,
+    route: Route<'_>,
+    kong: &mut Kong,
+    rate_limiter: &mut RateLimiter,
+    deadline: Instant,
+) -> rouille::Response {
+    // requests that waited too long for their turn are not handled at
+    // all, the deadline does not interrupt kontrollers
+    if Instant::now() > deadline {
+        return ErrorResponse::service_unavailable();
+    }
+
+    // Header size limit
+    let max_header_size = kong
+        .config
+        .max_header_size
+        .unwrap_or(defaults::MAX_HEADER_SIZE);
+    if header_size(request) > max_header_size {
+        return ErrorResponse::header_fields_too_large();
+    }
+
+    // Global rate limit
+    let mut rate_limit_status = None;
+    if let Some(limit) = &kong.config.rate_limit {
+        let key = rate_limit_key("global", limit, request, kong);
+        let status = rate_limiter.check(&key, limit, Instant::now());
+
+        if !status.allowed {
+            metrics::metrics().rate_limit_rejection("global");
+            let policy = route_cors_policy(&kong.config, &route);
+            return with_cors(policy, request, rate_limited(&status));
+        }
+        rate_limit_status = Some(status);
+    }
+
+    // Handle static files
+    if let Some(path) = &kong.config.static_files_path {
+        let response = rouille::match_assets(request, &path);
+        if response.is_success() {
+            metrics::metrics().static_file_hit();
+            return response;
+        }
+    }
+
+    let mut response = filter(request, route, kong, rate_limiter);
+    if let Some(status) = rate_limit_status {
+        // route specific rate limit headers take precedence
+        if !response.headers.iter().any(|(h, _)| h == "RateLimit-Limit") {
+            response = status.headers(response);
+        }
+    }
+    response
+}
+
+/// Method and route labels of the metrics of a request routed to
+/// `route`, requests no kontroller matched share the same labels
+fn metrics_labels(request: &rouille::Request, route: &Route<'_>) -> (String, String) {
+    match route {
+        Route::Kontroller(kontroller, _) | Route::Head(kontroller, _) => {
+            (request.method().to_string(), kontroller.address())
+        }
+        Route::Options { address, .. } => ("OPTIONS".to_string(), address.clone()),
+        Route::NotAllowed { .. } | Route::NotFound => (
+            metrics::UNMATCHED_ROUTE.to_string(),
+            metrics::UNMATCHED_ROUTE.to_string(),
+        ),
+    }
+}
+
+/// Largest request body the route of a request accepts
+fn max_body_size(config: &Konfig, route: &Route<'_>) -> u64 {
+    let route_limit = match route {
+        Route::Kontroller(kontroller, _) | Route::Head(kontroller, _) => config
+            .route(&kontroller.address())
+            .and_then(|route| route.max_body_size),
+        _ => None,
+    };
+
+    route_limit
+        .or(config.max_body_size)
+        .unwrap_or(defaults::MAX_BODY_SIZE)
+}
+
+/// Largest request body any route accepts
+fn largest_body_size(config: &Konfig) -> u64 {
+    let global = config.max_body_size.unwrap_or(defaults::MAX_BODY_SIZE);
+
+    config
+        .routes
+        .iter()
+        .flat_map(|routes| routes.values())
+        .filter_map(|route| route.max_body_size)
+        .fold(global, u64::max)
 }
 
 // filter route
 fn filter(
     request: &rouille::Request,
-    router: &Router<KontrollerHandle>,
+    route: Route<'_>,
     kong: &mut Kong,
+    rate_limiter: &mut RateLimiter,
 ) -> rouille::Response {
-    // check request url
-    let kontroller_id = format!("{}{}", request.method(), request.url());
-    let recognized_route = router.recognize(&kontroller_id);
-
-    match recognized_route {
-        Ok(route) => {
-            // get url parameters
-            kong.url_parameters = Some(route.params().clone());
-
-            // get a valid kpassport token
-            if let Ok(kpassport) = get_kpassport(kong, request) {
-                kong.kpassport = Some(kpassport);
-            } else {
-                kong.kpassport = None
-            };
+    // check request method and url
+    let (kontroller, params, head) = match route {
+        Route::Kontroller(kontroller, params) => (kontroller, params, false),
+        Route::Head(kontroller, params) => (kontroller, params, true),
+        Route::Options {
+            address,
+            allow,
+            cors,
+        } => {
+            return match cors_policy(&kong.config, &address, cors) {
+                Some(policy) if cors::is_preflight(request) => {
+                    cors::preflight(policy, request, &allow)
+                }
+                _ => rouille::Response::empty_204().with_additional_header("Allow", allow),
+            }
+        }
+        Route::NotAllowed {
+            address,
+            allow,
+            cors,
+        } => {
+            let response = ErrorResponse::not_allowed().with_additional_header("Allow", allow);
+            return with_cors(cors_policy(&kong.config, &address, cors), request, response);
+        }
+        Route::NotFound => {
+            return with_cors(
+                kong.config.cors.as_ref(),
+                request,
+                ErrorResponse::not_found(),
+            )
+        }
+    };
+
+    let response = kontrol(request, kontroller, params, kong, rate_limiter);
+
+    // CORS headers of the route
+    let policy = cors_policy(&kong.config, &kontroller.address(), kontroller.cors());
+    let response = with_cors(policy, request, response);
+
+    // HEAD requests are served by the GET kontroller, without the body
+    if head {
+        strip_body(response)
+    } else {
+        response
+    }
+}
+
+/// CORS policy of a route, the policy configured for the route takes
+/// precedence over the policy of the kontroller (or its group), which
+/// takes precedence over the global policy
+fn cors_policy<'a>(
+    config: &'a Konfig,
+    address: &str,
+    kontroller_policy: Option<&'a CorsKonfig>,
+) -> Option<&'a CorsKonfig> {
+    config
+        .route(address)
+        .and_then(|route| route.cors.as_ref())
+        .or(kontroller_policy)
+        .or(config.cors.as_ref())
+}
+
+/// CORS policy of a routed request, requests no kontroller matched
+/// only have the global policy
+fn route_cors_policy<'a>(config: &'a Konfig, route: &Route<'a>) -> Option<&'a CorsKonfig> {
+    match route {
+        Route::Kontroller(kontroller, _) | Route::Head(kontroller, _) => {
+            cors_policy(config, &kontroller.address(), kontroller.cors())
+        }
+        Route::Options { address, cors, .. } | Route::NotAllowed { address, cors, .. } => {
+            cors_policy(config, address, *cors)
+        }
+        Route::NotFound => config.cors.as_ref(),
+    }
+}
 
-            // Get input
-            let input_json_str = route.handler().get_input(request);
+/// Add the CORS headers of `policy`, if any, to a response
+fn with_cors(
+    policy: Option<&CorsKonfig>,
+    request: &rouille::Request,
+    response: rouille::Response,
+) -> rouille::Response {
+    match policy {
+        Some(policy) => cors::headers(policy, request, response),
+        None => response,
+    }
+}
+
+/// Let the kontroller handle the request
+fn kontrol(
+    request: &rouille::Request,
+    kontroller: &KontrollerHandle,
+    params: Params,
+    kong: &mut Kong,
+    rate_limiter: &mut RateLimiter,
+) -> rouille::Response {
+    // get url parameters
+    kong.url_parameters = Some(params);
 
-            // validate input_json_str
-            if let Ok(input) = route.handler().validate(input_json_str) {
-                kong.input = input;
+    // get query string parameters
+    kong.query = Some(Query::from_request(request));
 
-                // kontrol
-                route.handler().kontrol(kong)
+    // get client address, Unix domain socket clients have none
+    kong.remote_addr = match connection::peer() {
+        Some(Peer::Unix(_)) => None,
+        _ => Some(*request.remote_addr()),
+    };
+
+    // get a valid kpassport token
+    match get_kpassport(kong, request) {
+        Ok(kpassport) => kong.kpassport = Some(kpassport),
+        Err(e) => {
+            if !matches!(e, KryptoError::MissingAuthenticationCredentials) {
+                metrics::metrics().kpassport_failure(&e);
+            }
+            kong.kpassport = None
+        }
+    };
+
+    // Route rate limit
+    let address = kontroller.address();
+    let limit = kong
+        .config
+        .route(&address)
+        .and_then(|r| r.rate_limit.as_ref());
+    let rate_limit_status = limit.map(|limit| {
+        let key = rate_limit_key(&address, limit, request, kong);
+        rate_limiter.check(&key, limit, Instant::now())
+    });
+
+    if let Some(status) = &rate_limit_status {
+        if !status.allowed {
+            metrics::metrics().rate_limit_rejection("route");
+            return rate_limited(status);
+        }
+    }
+
+    // Authorization policy
+    if let Err(response) = authorize(kontroller.auth(), kong) {
+        return response;
+    }
+
+    // Get input
+    let input_json_str = kontroller.get_input(request);
+
+    // validate input_json_str
+    let response = if let Ok(input) = kontroller.validate(input_json_str) {
+        kong.input = input;
+
+        // kontrol
+        render(kontroller.try_kontrol(kong))
+    } else {
+        ErrorResponse::bad_request()
+    };
+
+    match rate_limit_status {
+        Some(status) => status.headers(response),
+        None => response,
+    }
+}
+
+/// Enforce the authorization policy of an endpoint, kong.kpassport
+/// holds the valid kpassport of the request if there is one
+fn authorize(policy: AuthPolicy, kong: &Kong) -> Result<(), rouille::Response> {
+    match (policy, &kong.kpassport) {
+        (AuthPolicy::Public, _) => Ok(()),
+        (_, None) => Err(ErrorResponse::unauthorized()),
+        (AuthPolicy::Kpassport, Some(_)) => Ok(()),
+        (AuthPolicy::Admin, Some(kpassport)) => {
+            let admins = kong.config.admins.as_deref().unwrap_or_default();
+            if admins.contains(&kpassport.content.username) {
+                Ok(())
             } else {
-                ErrorResponse::bad_request()
+                Err(ErrorResponse::forbidden())
             }
         }
-        Err(_) => ErrorResponse::not_found(),
     }
 }
 
-/// Log request
-fn log_request(request: &rouille::Request, status_code: u16) {
-    let log = format!("{} {} = {}", request.method(), request.url(), status_code);
-    Log::log(&log).expect("Error while logging");
+/// Identify the token bucket of a request, `scope` is either `global`
+/// or the address of the route
+fn rate_limit_key(
+    scope: &str,
+    limit: &RateLimitKonfig,
+    request: &rouille::Request,
+    kong: &Kong,
+) -> String {
+    let client = client_key(request);
+
+    match limit.key.unwrap_or_default() {
+        RateLimitKey::Ip => format!("{scope} {client}"),
+        RateLimitKey::Kpassport => match get_kpassport(kong, request) {
+            Ok(kpassport) => format!("{scope} user:{}", kpassport.content.username),
+            Err(_) => format!("{scope} {client}"),
+        },
+        RateLimitKey::Route => format!("{scope} route"),
+    }
 }
 
-#[derive(Clone, PartialEq)]
+/// Identify the client of a request: TCP clients by their IP address,
+/// Unix domain socket clients by the user ID of their process
+fn client_key(request: &rouille::Request) -> String {
+    match connection::peer() {
+        Some(Peer::Unix(Some(uid))) => format!("uid:{uid}"),
+        // the platform does not tell who the client is
+        Some(Peer::Unix(None)) => "unix".to_string(),
+        _ => format!("ip:{}", request.remote_addr().ip()),
+    }
+}
+
+/// Response to a request that is over the rate limit
+fn rate_limited(status: &RateLimitStatus) -> rouille::Response {
+    status.headers(ErrorResponse::too_many_requests(status.reset))
+}
+
+/// Log request, in the access log if there is one or else in the
+/// application log. Logging failures are reported, the response is
+/// still sent.
+fn log_request(
+    request: &rouille::Request,
+    mut response: rouille::Response,
+    kong: &mut Kong,
+    time: DateTime<Utc>,
+    received: Instant,
+) -> rouille::Response {
+    if !kong.access_log.is_enabled() {
+        let logged = Log::info(
+            "request",
+            &[
+                ("method", json!(request.method())),
+                ("url", json!(request.url())),
+                ("status", json!(response.status_code)),
+            ],
+        );
+        if let Err(error) = logged {
+            eprintln!("Could not log request: {error}");
+        }
+        return response;
+    }
+
+    let (data, size) =
+        std::mem::replace(&mut response.data, ResponseBody::empty()).into_reader_and_size();
+    response.data = match size {
+        Some(size) => ResponseBody::from_reader_and_size(data, size),
+        None => ResponseBody::from_reader(data),
+    };
+
+    let entry = AccessEntry {
+        time,
+        client_ip: request.remote_addr().ip(),
+        username: kong
+            .kpassport
+            .as_ref()
+            .map(|kpassport| kpassport.content.username.clone()),
+        method: request.method().to_string(),
+        url: request.raw_url().to_string(),
+        status: response.status_code,
+        size,
+        referer: request.header("Referer").map(str::to_string),
+        user_agent: request.header("User-Agent").map(str::to_string),
+        latency: received.elapsed(),
+        request_id: kong.request_id.clone(),
+    };
+    if let Err(error) = kong.access_log.record(&entry) {
+        let logged = Log::error(
+            "Could not write the access log",
+            &[("error", json!(error.to_string()))],
+        );
+        if logged.is_err() {
+            eprintln!("Could not write the access log: {error}");
+        }
+    }
+    response
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
 /// 🚥 HTTP methods
 pub enum Method {
     /// HTTP GET method
@@ -101,28 +713,103 @@ pub enum Method {
     Post,
     /// HTTP PUT method
     Put,
+    /// HTTP PATCH method
+    Patch,
     /// HTTP HEAD method
     Head,
     /// HTTP DELETE method
     Delete,
     /// HTTP OPTIONS method
     Options,
+    /// Any other HTTP method, like `QUERY` or the WebDAV methods.
+    /// Extension methods are created by parsing their name, so a
+    /// standard method is never an extension method:
+    /// `"QUERY".parse::<Method>()`. Methods are case-sensitive, `QUERY`
+    /// only matches `QUERY` requests.
+    Extension(ExtensionMethod),
+}
+
+/// Name of an extension HTTP method, see [`Method::Extension`]
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub struct ExtensionMethod(&'static str);
+
+impl ExtensionMethod {
+    /// Name of the method
+    pub fn as_str(&self) -> &'static str {
+        self.0
+    }
+}
+
+/// Names of the extension methods, interned so [`Method`] stays `Copy`
+static EXTENSION_METHODS: OnceLock<Mutex<HashSet<&'static str>>> = OnceLock::new();
+
+/// Number of extension method names interned at most, names are never
+/// freed
+const MAX_EXTENSION_METHODS: usize = 64;
+
+impl Method {
+    /// Standard method named `s`, or the extension method named `s` if
+    /// it was parsed before. Unlike parsing, it never interns a name,
+    /// so it is safe to use on the methods of requests.
+    pub(crate) fn lookup(s: &str) -> Option<Method> {
+        if let Some(method) = Method::standard(s) {
+            return Some(method);
+        }
+        let names = EXTENSION_METHODS
+            .get()?
+            .lock()
+            .unwrap_or_else(|e| e.into_inner());
+        names
+            .get(s)
+            .map(|name| Method::Extension(ExtensionMethod(name)))
+    }
+
+    /// Standard method named `s`
+    fn standard(s: &str) -> Option<Method> {
+        match s {
+            "GET" => Some(Method::Get),
+            "POST" => Some(Method::Post),
+            "PUT" => Some(Method::Put),
+            "PATCH" => Some(Method::Patch),
+            "HEAD" => Some(Method::Head),
+            "DELETE" => Some(Method::Delete),
+            "OPTIONS" => Some(Method::Options),
+            _ => None,
+        }
+    }
 }
 
-impl Copy for Method {}
 impl FromStr for Method {
     type Err = KError;
 
+    /// Parse a method name, extension method names are interned for
+    /// the lifetime of the process (at most 64 of them)
     fn from_str(s: &str) -> Result<Self, Self::Err> {
-        match s {
-            "GET" => Ok(Method::Get),
-            "POST" => Ok(Method::Post),
-            "PUT" => Ok(Method::Put),
-            "HEAD" => Ok(Method::Head),
-            "DELETE" => Ok(Method::Delete),
-            "OPTIONS" => Ok(Method::Options),
-            _ => Err(KError::InvalidHttpMethod),
+        if let Some(method) = Method::standard(s) {
+            return Ok(method);
+        }
+        if !is_token(s) {
+            return Err(KError::InvalidHttpMethod(s.to_string()));
+        }
+
+        let mut names = EXTENSION_METHODS
+            .get_or_init(Default::default)
+            .lock()
+            .unwrap_or_else(|e| e.into_inner());
+        if let Some(name) = names.get(s) {
+            return Ok(Method::Extension(ExtensionMethod(name)));
         }
+        if names.len() >= MAX_EXTENSION_METHODS {
+            return Err(KError::InvalidHttpMethod(s.to_string()));
+        }
+        let name: &'static str = Box::leak(s.to_string().into_boxed_str());
+        names.insert(name);
+        Ok(Method::Extension(ExtensionMethod(name)))
+    }
+}
+impl Serialize for Method {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
     }
 }
 impl fmt::Display for Method {
@@ -131,9 +818,47 @@ impl fmt::Display for Method {
             Self::Get => write!(f, "GET"),
             Self::Post => write!(f, "POST"),
             Self::Put => write!(f, "PUT"),
+            Self::Patch => write!(f, "PATCH"),
             Self::Head => write!(f, "HEAD"),
             Self::Delete => write!(f, "DELETE"),
             Self::Options => write!(f, "OPTIONS"),
+            Self::Extension(method) => write!(f, "{}", method.as_str()),
         }
     }
 }
+
+/// Whether `s` is a valid HTTP method name (an RFC 9110 token)
+fn is_token(s: &str) -> bool {
+    !s.is_empty()
+        && s.chars()
+            .all(|c| c.is_ascii_alphanumeric() || "!#$%&'*+-.^_`|~".contains(c))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn method_from_str() {
+        assert_eq!(Method::from_str("PATCH").unwrap(), Method::Patch);
+        assert_eq!(Method::from_str("GET").unwrap(), Method::Get);
+        let query = Method::from_str("QUERY").unwrap();
+        assert!(matches!(query, Method::Extension(name) if name.as_str() == "QUERY"));
+        assert_eq!(Method::from_str("QUERY").unwrap(), query);
+        assert_eq!(
+            Method::from_str("PROPFIND").unwrap().to_string(),
+            "PROPFIND"
+        );
+        assert!(Method::from_str("").is_err());
+        assert!(Method::from_str("GET /").is_err());
+
+        // request methods are never interned
+        assert_eq!(Method::lookup("QUERY"), Some(query));
+        assert_eq!(Method::lookup("GET"), Some(Method::Get));
+        assert_eq!(Method::lookup("UNPARSED"), None);
+        assert_eq!(
+            Method::from_str("UNPARSED").unwrap().to_string(),
+            "UNPARSED"
+        );
+    }
+}
@@ -12,31 +12,63 @@
 #![doc(html_logo_url = "https://kwatafana.org/logo.jpeg")]
 #![warn(missing_docs, unreachable_pub, future_incompatible, rust_2018_idioms)]
 
+pub mod access_log;
+mod connection;
+pub mod cors;
 pub mod defaults;
 mod error;
 mod error_response;
+pub mod group;
+pub mod health;
 pub mod inputs;
 mod konfig;
 mod kontrol;
 mod kroute;
+mod limits;
+mod listener;
 pub mod log;
+mod lru;
+pub mod metrics;
+pub mod openapi;
+pub mod problem;
+pub mod query;
+pub mod rate_limit;
 mod read_kpassport;
+mod recover;
+pub mod request_id;
+pub mod rotation;
+pub mod route_table;
+mod router;
+pub mod shutdown;
+pub mod testing;
+pub mod throttle;
+#[cfg(feature = "tls")]
+mod tls;
 pub mod validate;
 
 pub use error::KError;
-pub use error_response::ErrorResponse;
-pub use konfig::Konfig;
-pub use kontrol::Kontrol;
-pub use kroute::{kroute, Method};
+pub use error_response::{ErrorResponse, HttpError, KontrolError, ResponseError};
+pub use konfig::{
+    AccessLogKonfig, CorsKonfig, HealthKonfig, Konfig, ListenerKonfig, LogRotationKonfig,
+    LoginThrottleKonfig, OpenApiKonfig, RateLimitKey, RateLimitKonfig, RouteKonfig,
+    RouteTableKonfig, TlsKonfig,
+};
+pub use kontrol::{AuthPolicy, Kontrol};
+pub use kroute::{kroute, kroute_until, ExtensionMethod, Method};
 pub use krypto;
 pub use rouille as server;
+pub use router::KontrollerHandle;
 pub use serde_json::{
     error::Error as JsonError, from_str as json_from_str, json, Value as JsonValue,
 };
+pub use shutdown::Shutdown;
 
+use access_log::AccessLog;
 use krypto::kpassport::Kpassport;
+use query::Query;
 use route_recognizer::Params;
-use std::fs::File;
+use std::net::SocketAddr;
+use throttle::LoginThrottle;
 
 /// 🔥 Kong object
 pub struct Kong {
@@ -48,22 +80,57 @@ pub struct Kong {
     pub input: Option<serde_json::Value>,
     /// Url parameters
     pub url_parameters: Option<Params>,
+    /// Query string parameters
+    pub query: Option<Query>,
+    /// Address of the client that made the request
+    pub remote_addr: Option<SocketAddr>,
+    /// ID of the request, see [`request_id`]
+    pub request_id: Option<String>,
+    /// Login brute-force protection
+    pub login_throttle: LoginThrottle,
+    /// Access log of the node
+    pub(crate) access_log: AccessLog,
 }
 
 impl Kong {
+    /// Create a kong instance from a configuration, the working
+    /// directory is created if it does not exist
+    pub fn new(config: Konfig) -> Self {
+        Kong::init(&config);
+
+        let throttle_file =
+            std::path::Path::new(config.working_dir()).join(defaults::LOGIN_THROTTLE_FILE);
+        let login_throttle = match &config.login_throttle {
+            Some(throttle_config) => LoginThrottle::new(&throttle_file, throttle_config),
+            None => LoginThrottle::new(&throttle_file, &Default::default()),
+        };
+
+        // XXX: Note that using expect() here is safe, because kong
+        // instances are created at start up.
+        let access_log = AccessLog::new(&config).expect("Could not open access log file");
+
+        Kong {
+            config,
+            kpassport: None,
+            input: None,
+            url_parameters: None,
+            query: None,
+            remote_addr: None,
+            request_id: None,
+            login_throttle,
+            access_log,
+        }
+    }
+
     /// Initialize kong, by creating the working directory if it does
-    /// not exist and it content if it does not exist (for example the
-    /// LOG file)
+    /// not exist. Logging is configured once per process, by kroute.
     fn init(config: &Konfig) {
-        Kong::create_working_directory();
-        Kong::create_log_file(config);
+        Kong::create_working_directory(config);
     }
 
     /// Create working dirctory if it does not already exist
-    fn create_working_directory() {
-        // Get path to working directory
-        let working_dir = Konfig::read_working_dir();
-        let working_dir = std::path::Path::new(&working_dir);
+    fn create_working_directory(config: &Konfig) {
+        let working_dir = std::path::Path::new(config.working_dir());
 
         if !std::path::Path::exists(working_dir) {
             // create working directory
@@ -73,42 +140,13 @@ impl Kong {
             std::fs::create_dir(working_dir).unwrap()
         }
     }
-
-    /// Create log file if file logging is enabled and the LOG
-    /// file does not yet exist.
-    fn create_log_file(config: &Konfig) {
-        //let logging = config.log_file
-        if let Some(file_logging) = config.log_file {
-            if file_logging {
-                let working_dir = Konfig::read_working_dir();
-                let working_dir_path = std::path::Path::new(&working_dir);
-                let log_file = std::path::Path::new(defaults::LOG_FILE);
-                let log_file_path = working_dir_path.join(log_file);
-
-                if !std::path::Path::exists(&log_file_path) {
-                    // create log file in the working directory
-                    // XXX: Note that using unwrap() here is safe, because
-                    // this function is a called a start up during the
-                    // initialization phase of kong.
-                    File::create(log_file_path).unwrap();
-                }
-            }
-        }
-    }
 }
 
 impl Default for Kong {
-    /// Create new kong instance
+    /// Create new kong instance, from the configuration file provided
+    /// as an argument when the program was started
     fn default() -> Self {
         let config = Konfig::read().expect("Could not read configuration file.");
-
-        Kong::init(&config);
-
-        Kong {
-            config,
-            kpassport: None,
-            input: None,
-            url_parameters: None,
-        }
+        Kong::new(config)
     }
 }
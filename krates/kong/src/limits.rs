@@ -0,0 +1,60 @@
+//! 📏 `kong` request size limits
+
+use crate::error_response::ErrorResponse;
+
+/// Size of all request headers (names and values) in bytes
+pub(crate) fn header_size(request: &rouille::Request) -> usize {
+    request
+        .headers()
+        .map(|(name, value)| name.len() + value.len())
+        .sum()
+}
+
+/// Check the size of the request body against `max_body_size`, bodies
+/// that are too large are rejected with 413. Connections receive the
+/// body within the request timeout and the largest body size of their
+/// listener, and give its length in the `Content-Length` header: the
+/// body is not read again here.
+pub(crate) fn check_body_size(
+    request: &rouille::Request,
+    max_body_size: u64,
+) -> Result<(), rouille::Response> {
+    match request.header("Content-Length").map(str::parse::<u64>) {
+        Some(Ok(length)) if length > max_body_size => Err(ErrorResponse::payload_too_large()),
+        Some(Ok(_)) | None => Ok(()),
+        Some(Err(_)) => Err(ErrorResponse::bad_request()),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn request(length: &str) -> rouille::Request {
+        rouille::Request::fake_http(
+            "POST",
+            "/upload",
+            vec![("Content-Length".to_string(), length.to_string())],
+            Vec::new(),
+        )
+    }
+
+    #[test]
+    fn body_within_limit() {
+        assert!(check_body_size(&request("10"), 10).is_ok());
+        let without_body = rouille::Request::fake_http("GET", "/", Vec::new(), Vec::new());
+        assert!(check_body_size(&without_body, 10).is_ok());
+    }
+
+    #[test]
+    fn body_too_large() {
+        let response = check_body_size(&request("11"), 10).unwrap_err();
+        assert_eq!(response.status_code, 413);
+    }
+
+    #[test]
+    fn invalid_content_length() {
+        let response = check_body_size(&request("ten"), 10).unwrap_err();
+        assert_eq!(response.status_code, 400);
+    }
+}
@@ -0,0 +1,885 @@
+//! 📡 `kong` node listeners
+//!
+//! Plain HTTP and HTTPS listeners. Plain HTTP listeners serve their
+//! connections with [`crate::connection`], so every socket has a read
+//! timeout. HTTPS listeners terminate TLS in front of it, with a
+//! certificate that is swapped without restarting the listener when its
+//! files change (see [`crate::tls`]). An optional plain HTTP listener
+//! redirects clients to HTTPS.
+//!
+//! Listeners bind to a hostname, an IPv4 or IPv6 address or, on unix
+//! systems, to a Unix domain socket (`unix:/path/to/socket`).
+
+use crate::connection::{self, Limits, Peer};
+use crate::konfig::TlsKonfig;
+use crate::log::Log;
+use crate::shutdown::Waker;
+use crate::{KError, Shutdown};
+use std::fmt;
+use std::io;
+use std::net::{TcpListener, TcpStream};
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+#[cfg(feature = "tls")]
+use crate::tls::Certificate;
+
+/// How long listeners wait before accepting again, when the process is
+/// out of resources (file descriptors)
+const ACCEPT_RETRY_DELAY: Duration = Duration::from_millis(100);
+
+/// How often listeners of platforms without `poll` check for
+/// connections and wake-ups
+#[cfg(not(unix))]
+const ACCEPT_INTERVAL: Duration = Duration::from_millis(5);
+
+/// Prefix of Unix domain socket addresses
+const UNIX_PREFIX: &str = "unix:";
+
+/// 📡 Address a listener binds to
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub(crate) enum Address {
+    /// TCP `host:port` address
+    Tcp(String),
+    /// Path of a Unix domain socket
+    Unix(PathBuf),
+}
+
+impl Address {
+    /// Listener address from a `bind` setting and a port, `bind` can be
+    /// a hostname, an IPv4 or IPv6 address or `unix:/path/to/socket`.
+    pub(crate) fn new(bind: &str, port: Option<u16>) -> Result<Address, KError> {
+        if let Some(path) = bind.strip_prefix(UNIX_PREFIX) {
+            return Ok(Address::Unix(PathBuf::from(path)));
+        }
+
+        let port = port.ok_or_else(|| KError::InvalidConfig {
+            key: "port".to_string(),
+            reason: format!("required to listen on {bind}"),
+        })?;
+        if bind.contains(':') && !bind.starts_with('[') {
+            // IPv6 address
+            Ok(Address::Tcp(format!("[{bind}]:{port}")))
+        } else {
+            Ok(Address::Tcp(format!("{bind}:{port}")))
+        }
+    }
+}
+
+impl fmt::Display for Address {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Tcp(address) => write!(f, "{address}"),
+            Self::Unix(path) => write!(f, "{UNIX_PREFIX}{}", path.display()),
+        }
+    }
+}
+
+/// Listen on `address`, handling requests with `handler` until
+/// `shutdown` is stopped. Returns once the requests that were in-flight
+/// when the listener stopped are handled.
+pub(crate) fn serve<F>(
+    address: &Address,
+    handler: F,
+    tls: Option<&TlsKonfig>,
+    limits: Limits,
+    shutdown: &Shutdown,
+) -> Result<(), KError>
+where
+    F: Fn(&rouille::Request) -> rouille::Response + Send + Sync + 'static,
+{
+    let handler = Arc::new(handler);
+
+    match (address, tls) {
+        (Address::Tcp(address), None) => listen(bind(address)?, handler, limits, shutdown),
+        (Address::Tcp(address), Some(tls)) => {
+            if let Some(port) = tls.redirect_port {
+                redirect_to_https(address, port, limits, shutdown)?;
+            }
+            listen_tls(address, handler, tls, limits, shutdown)
+        }
+        (Address::Unix(path), None) => listen_unix(path, handler, limits, shutdown),
+        // TLS is not supported on Unix domain socket listeners
+        (Address::Unix(path), Some(_)) => Err(KError::Listener {
+            address: path.display().to_string(),
+            reason: "TLS is not supported on Unix domain sockets".to_string(),
+            source: None,
+        }),
+    }
+}
+
+/// Listening socket a listener waits on
+#[cfg(unix)]
+trait Listening: std::os::unix::io::AsRawFd {}
+
+#[cfg(unix)]
+impl<L: std::os::unix::io::AsRawFd> Listening for L {}
+
+#[cfg(not(unix))]
+trait Listening {}
+
+#[cfg(not(unix))]
+impl<L> Listening for L {}
+
+/// Wait until `waker` is woken, a connection is waiting on `listener`
+/// (if given) or `timeout` elapses
+#[cfg(unix)]
+fn wait(waker: &Waker, listener: Option<&impl Listening>, timeout: Option<Duration>) {
+    let mut fds = vec![libc::pollfd {
+        fd: waker.fd(),
+        events: libc::POLLIN,
+        revents: 0,
+    }];
+    if let Some(listener) = listener {
+        fds.push(libc::pollfd {
+            fd: listener.as_raw_fd(),
+            events: libc::POLLIN,
+            revents: 0,
+        });
+    }
+    let timeout = timeout.map_or(-1, |timeout| {
+        // rounded up, so the wait does not end just before the timeout
+        timeout.as_micros().div_ceil(1000).min(i32::MAX as u128) as i32
+    });
+
+    // SAFETY: `fds` is a valid array of `fds.len()` pollfd structs, the
+    // file descriptors stay open while polling
+    unsafe { libc::poll(fds.as_mut_ptr(), fds.len() as libc::nfds_t, timeout) };
+    waker.clear();
+}
+
+#[cfg(not(unix))]
+fn wait(_waker: &Waker, _listener: Option<&impl Listening>, timeout: Option<Duration>) {
+    thread::sleep(timeout.map_or(ACCEPT_INTERVAL, |t| t.min(ACCEPT_INTERVAL)));
+}
+
+/// Counts a connection while it is served, the listener is woken once
+/// it finishes (or panics)
+struct Served {
+    connections: Arc<AtomicUsize>,
+    waker: Waker,
+}
+
+impl Served {
+    fn new(connections: &Arc<AtomicUsize>, waker: &Waker) -> Self {
+        connections.fetch_add(1, Ordering::SeqCst);
+        Served {
+            connections: connections.clone(),
+            waker: waker.clone(),
+        }
+    }
+}
+
+impl Drop for Served {
+    fn drop(&mut self) {
+        self.connections.fetch_sub(1, Ordering::SeqCst);
+        self.waker.wake();
+    }
+}
+
+/// Accept connections until `shutdown` is stopped, each connection is
+/// served by `serve` on its own thread. At most `max_connections` are
+/// served at once, further clients wait in the listening socket's
+/// backlog. Once stopped the listening socket is closed, and the
+/// connections that are still open are given `limits.shutdown_timeout`
+/// to finish. Connections that do not are left running.
+///
+/// The listener sleeps until a connection is waiting, a connection
+/// finishes or the node stops, all of which wake it through `waker`.
+fn accept<L, S>(
+    listener: L,
+    mut accept: impl FnMut(&L) -> io::Result<(S, Peer)>,
+    serve: impl Fn(S, Peer) + Send + Sync + 'static,
+    waker: Waker,
+    limits: Limits,
+    shutdown: &Shutdown,
+) where
+    L: Listening,
+    S: Send + 'static,
+{
+    shutdown.wake_on_stop(waker.clone());
+    let serve = Arc::new(serve);
+    // connections being served
+    let connections = Arc::new(AtomicUsize::new(0));
+
+    while !shutdown.is_stopped() {
+        // full listeners only wait for a connection to finish
+        let accepting = connections.load(Ordering::SeqCst) < limits.max_connections;
+        wait(&waker, accepting.then_some(&listener), None);
+        if !accepting || shutdown.is_stopped() {
+            continue;
+        }
+
+        match accept(&listener) {
+            Ok((socket, from)) => {
+                let serve = serve.clone();
+                let served = Served::new(&connections, &waker);
+                thread::spawn(move || {
+                    serve(socket, from);
+                    drop(served);
+                });
+            }
+            // the connection went away before it was accepted
+            Err(e) if e.kind() == io::ErrorKind::WouldBlock => {}
+            // the process is out of resources for a moment
+            Err(_) => thread::sleep(ACCEPT_RETRY_DELAY),
+        }
+    }
+
+    drop(listener);
+    let deadline = Instant::now() + limits.shutdown_timeout;
+    loop {
+        let timeout = deadline.saturating_duration_since(Instant::now());
+        if connections.load(Ordering::SeqCst) == 0 || timeout.is_zero() {
+            return;
+        }
+        wait(&waker, None::<&L>, Some(timeout));
+    }
+}
+
+/// Waker of the listener on `address`
+fn waker(address: &str) -> Result<Waker, KError> {
+    Waker::new().map_err(|source| KError::Listener {
+        address: address.to_string(),
+        reason: "could not create the waker of the listener".to_string(),
+        source: Some(Box::new(source)),
+    })
+}
+
+/// Bind a TCP listener to `address`
+fn bind(address: &str) -> Result<TcpListener, KError> {
+    TcpListener::bind(address)
+        .and_then(|listener| {
+            // listeners wait for connections before accepting them,
+            // accepting does not block if the client left meanwhile
+            listener.set_nonblocking(true)?;
+            Ok(listener)
+        })
+        .map_err(|source| KError::Listener {
+            address: address.to_string(),
+            reason: "could not bind".to_string(),
+            source: Some(Box::new(source)),
+        })
+}
+
+/// Accept a TCP connection
+fn accept_tcp(listener: &TcpListener) -> io::Result<(TcpStream, Peer)> {
+    let (socket, from) = listener.accept()?;
+    socket.set_nonblocking(false)?;
+    socket.set_nodelay(true)?;
+    Ok((socket, Peer::Tcp(from)))
+}
+
+/// Listen on a TCP socket
+fn listen<F>(
+    listener: TcpListener,
+    handler: Arc<F>,
+    limits: Limits,
+    shutdown: &Shutdown,
+) -> Result<(), KError>
+where
+    F: Fn(&rouille::Request) -> rouille::Response + Send + Sync + 'static,
+{
+    let address = listener
+        .local_addr()
+        .map_or_else(|_| "tcp".to_string(), |address| address.to_string());
+    let waker = waker(&address)?;
+    let connection_shutdown = shutdown.clone();
+    accept(
+        listener,
+        accept_tcp,
+        move |socket, peer| {
+            connection::serve(socket, peer, false, &*handler, limits, &connection_shutdown)
+        },
+        waker,
+        limits,
+        shutdown,
+    );
+    Ok(())
+}
+
+/// Listen on a Unix domain socket
+#[cfg(unix)]
+fn listen_unix<F>(
+    path: &std::path::Path,
+    handler: Arc<F>,
+    limits: Limits,
+    shutdown: &Shutdown,
+) -> Result<(), KError>
+where
+    F: Fn(&rouille::Request) -> rouille::Response + Send + Sync + 'static,
+{
+    use std::os::unix::fs::FileTypeExt;
+    use std::os::unix::net::UnixListener;
+
+    // remove the socket left behind by a previous run, any other file
+    // at the path is left alone
+    match std::fs::symlink_metadata(path) {
+        Ok(metadata) if metadata.file_type().is_socket() => {
+            let _ = std::fs::remove_file(path);
+        }
+        Ok(_) => {
+            return Err(KError::Listener {
+                address: path.display().to_string(),
+                reason: "exists and is not a socket".to_string(),
+                source: None,
+            })
+        }
+        Err(_) => {}
+    }
+
+    let listener = UnixListener::bind(path)
+        .and_then(|listener| {
+            listener.set_nonblocking(true)?;
+            Ok(listener)
+        })
+        .map_err(|source| KError::Listener {
+            address: path.display().to_string(),
+            reason: "could not bind".to_string(),
+            source: Some(Box::new(source)),
+        })?;
+
+    let waker = waker(&path.display().to_string())?;
+    let connection_shutdown = shutdown.clone();
+    accept(
+        listener,
+        |listener| {
+            let (socket, _) = listener.accept()?;
+            socket.set_nonblocking(false)?;
+            let uid = peer_uid(&socket);
+            Ok((socket, Peer::Unix(uid)))
+        },
+        move |socket, peer| {
+            connection::serve(socket, peer, false, &*handler, limits, &connection_shutdown)
+        },
+        waker,
+        limits,
+        shutdown,
+    );
+    let _ = std::fs::remove_file(path);
+    Ok(())
+}
+
+/// User ID of the process on the other end of a Unix domain socket
+#[cfg(any(target_os = "linux", target_os = "android"))]
+fn peer_uid(socket: &std::os::unix::net::UnixStream) -> Option<u32> {
+    use std::os::unix::io::AsRawFd;
+
+    let mut credentials = libc::ucred {
+        pid: 0,
+        uid: 0,
+        gid: 0,
+    };
+    let mut length = std::mem::size_of::<libc::ucred>() as libc::socklen_t;
+    // SAFETY: `credentials` is a `ucred` of `length` bytes, as
+    // `SO_PEERCRED` expects
+    let result = unsafe {
+        libc::getsockopt(
+            socket.as_raw_fd(),
+            libc::SOL_SOCKET,
+            libc::SO_PEERCRED,
+            &mut credentials as *mut libc::ucred as *mut libc::c_void,
+            &mut length,
+        )
+    };
+    (result == 0).then_some(credentials.uid)
+}
+
+/// User ID of the process on the other end of a Unix domain socket
+#[cfg(all(unix, not(any(target_os = "linux", target_os = "android"))))]
+fn peer_uid(socket: &std::os::unix::net::UnixStream) -> Option<u32> {
+    use std::os::unix::io::AsRawFd;
+
+    let (mut uid, mut gid) = (0, 0);
+    // SAFETY: `uid` and `gid` are valid for writes
+    let result = unsafe { libc::getpeereid(socket.as_raw_fd(), &mut uid, &mut gid) };
+    (result == 0).then_some(uid)
+}
+
+#[cfg(not(unix))]
+fn listen_unix<F>(
+    path: &std::path::Path,
+    _handler: Arc<F>,
+    _limits: Limits,
+    _shutdown: &Shutdown,
+) -> Result<(), KError> {
+    Err(KError::Listener {
+        address: path.display().to_string(),
+        reason: "Unix domain sockets are not supported on this platform".to_string(),
+        source: None,
+    })
+}
+
+/// Start a plain HTTP listener on `redirect_port`, that redirects all
+/// requests to the HTTPS listener on `address`
+fn redirect_to_https(
+    address: &str,
+    redirect_port: u16,
+    limits: Limits,
+    shutdown: &Shutdown,
+) -> Result<(), KError> {
+    let https_port = port(address);
+    let host = address.rsplit_once(':').map_or(address, |(host, _)| host);
+    let redirect_address = format!("{host}:{redirect_port}");
+    let listener = bind(&redirect_address)?;
+
+    Log::log(&format!(
+        "redirecting HTTP @ {redirect_address} to HTTPS @ {address}"
+    ))?;
+
+    let handler = Arc::new(move |request: &rouille::Request| redirect(request, https_port));
+    let shutdown = shutdown.clone();
+    thread::spawn(move || listen(listener, handler, limits, &shutdown));
+    Ok(())
+}
+
+/// Permanent redirect to the HTTPS version of the requested url
+fn redirect(request: &rouille::Request, https_port: u16) -> rouille::Response {
+    let host = request.header("Host").unwrap_or("localhost");
+    let host = match host.rsplit_once(':') {
+        // strip the port, unless the colon is part of an IPv6 address
+        Some((host, port)) if !port.contains(']') => host,
+        _ => host,
+    };
+
+    let location = if https_port == 443 {
+        format!("https://{host}{}", request.raw_url())
+    } else {
+        format!("https://{host}:{https_port}{}", request.raw_url())
+    };
+
+    rouille::Response::redirect_301(location)
+}
+
+/// Port of a `host:port` address
+fn port(address: &str) -> u16 {
+    address
+        .rsplit_once(':')
+        .and_then(|(_, port)| port.parse().ok())
+        .unwrap_or(443)
+}
+
+/// Serve HTTPS on `address`, with the certificate of `tls`. The
+/// certificate is swapped when its files change, see [`crate::tls`].
+#[cfg(feature = "tls")]
+fn listen_tls<F>(
+    address: &str,
+    handler: Arc<F>,
+    tls: &TlsKonfig,
+    limits: Limits,
+    shutdown: &Shutdown,
+) -> Result<(), KError>
+where
+    F: Fn(&rouille::Request) -> rouille::Response + Send + Sync + 'static,
+{
+    let certificate = Certificate::load(tls)?;
+    let config = certificate.server_config();
+    let listener = bind(address)?;
+    let waker = waker(address)?;
+
+    let watcher = {
+        let tls = tls.clone();
+        let shutdown = shutdown.clone();
+        thread::spawn(move || certificate.watch(&tls, &shutdown))
+    };
+
+    let connection_shutdown = shutdown.clone();
+    accept(
+        listener,
+        accept_tcp,
+        move |socket, peer| {
+            let Ok(tls_connection) = rustls::ServerConnection::new(config.clone()) else {
+                return;
+            };
+            let stream = rustls::StreamOwned::new(tls_connection, socket);
+            connection::serve(stream, peer, true, &*handler, limits, &connection_shutdown)
+        },
+        waker,
+        limits,
+        shutdown,
+    );
+    let _ = watcher.join();
+    Ok(())
+}
+
+#[cfg(not(feature = "tls"))]
+fn listen_tls<F>(
+    _address: &str,
+    _handler: Arc<F>,
+    _tls: &TlsKonfig,
+    _limits: Limits,
+    _shutdown: &Shutdown,
+) -> Result<(), KError> {
+    Err(KError::Tls {
+        reason: "kong was built without the `tls` feature".to_string(),
+        source: None,
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn limits() -> Limits {
+        Limits {
+            max_connections: 4,
+            shutdown_timeout: Duration::from_secs(5),
+            max_body_size: 1024,
+            max_header_size: 1024,
+            timeout: Duration::from_secs(5),
+        }
+    }
+
+    #[test]
+    fn listener_address() {
+        let tcp = |a: &str| Address::Tcp(a.to_string());
+
+        assert_eq!(
+            Address::new("localhost", Some(80)).unwrap(),
+            tcp("localhost:80")
+        );
+        assert_eq!(
+            Address::new("0.0.0.0", Some(80)).unwrap(),
+            tcp("0.0.0.0:80")
+        );
+        assert_eq!(Address::new("::", Some(80)).unwrap(), tcp("[::]:80"));
+        assert_eq!(Address::new("[::1]", Some(80)).unwrap(), tcp("[::1]:80"));
+        assert_eq!(
+            Address::new("unix:/run/kong.sock", None).unwrap(),
+            Address::Unix(PathBuf::from("/run/kong.sock"))
+        );
+        assert!(Address::new("0.0.0.0", None).is_err());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn unix_domain_socket() {
+        use std::io::{Read, Write};
+        use std::os::unix::net::UnixStream;
+
+        let path = std::env::temp_dir().join("kong-test.sock");
+        let listener_path = path.clone();
+        let shutdown = Shutdown::new();
+        let listener_shutdown = shutdown.clone();
+        let listener = std::thread::spawn(move || {
+            listen_unix(
+                &listener_path,
+                Arc::new(|request: &rouille::Request| {
+                    rouille::Response::text(format!(
+                        "{} {} {:?}",
+                        request.method(),
+                        request.url(),
+                        connection::peer()
+                    ))
+                }),
+                limits(),
+                &listener_shutdown,
+            )
+        });
+
+        let mut stream = (0..20)
+            .find_map(|_| {
+                std::thread::sleep(std::time::Duration::from_millis(100));
+                UnixStream::connect(&path).ok()
+            })
+            .unwrap();
+        stream
+            .write_all(b"GET /hello HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n")
+            .unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).unwrap();
+
+        // clients are identified by the user ID of their process
+        // SAFETY: getuid has no preconditions
+        let uid = unsafe { libc::getuid() };
+        assert!(response.starts_with("HTTP/1.1 200"));
+        assert!(response.ends_with(&format!("GET /hello Some(Unix(Some({uid})))")));
+
+        shutdown.stop();
+        assert!(listener.join().unwrap().is_ok());
+        assert!(!path.exists());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn unix_socket_path_taken() {
+        let path = std::env::temp_dir().join(format!("kong-test-{}.sock", std::process::id()));
+        std::fs::write(&path, b"kong").unwrap();
+
+        // a file that is not a socket is never removed
+        let result = listen_unix(
+            &path,
+            Arc::new(|_: &rouille::Request| rouille::Response::text("hello")),
+            limits(),
+            &Shutdown::new(),
+        );
+        assert!(matches!(result, Err(KError::Listener { .. })));
+        assert_eq!(std::fs::read(&path).unwrap(), b"kong");
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn stop_listener() {
+        use std::io::{Read, Write};
+        use std::net::{TcpListener, TcpStream};
+
+        let port = TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap()
+            .port();
+        let shutdown = Shutdown::new();
+        let listener_shutdown = shutdown.clone();
+        let listener = std::thread::spawn(move || {
+            serve(
+                &Address::Tcp(format!("127.0.0.1:{port}")),
+                |_: &rouille::Request| {
+                    // a slow request, that is still in-flight when the
+                    // listener is stopped
+                    std::thread::sleep(Duration::from_millis(500));
+                    rouille::Response::text("hello")
+                },
+                None,
+                limits(),
+                &listener_shutdown,
+            )
+        });
+
+        let mut stream = (0..20)
+            .find_map(|_| {
+                std::thread::sleep(Duration::from_millis(100));
+                TcpStream::connect(("127.0.0.1", port)).ok()
+            })
+            .unwrap();
+        stream
+            .write_all(b"GET / HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n")
+            .unwrap();
+        std::thread::sleep(Duration::from_millis(200));
+        shutdown.stop();
+
+        // the in-flight request is answered
+        let mut response = String::new();
+        stream.read_to_string(&mut response).unwrap();
+        assert!(response.ends_with("hello"));
+
+        assert!(listener.join().unwrap().is_ok());
+        assert!(TcpStream::connect(("127.0.0.1", port)).is_err());
+    }
+
+    #[test]
+    fn bounded_connections() {
+        use std::io::{Read, Write};
+        use std::net::TcpStream;
+
+        let listener = bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let shutdown = Shutdown::new();
+        let listener_shutdown = shutdown.clone();
+        let listener = std::thread::spawn(move || {
+            let limits = Limits {
+                max_connections: 1,
+                ..limits()
+            };
+            listen(
+                listener,
+                Arc::new(|_: &rouille::Request| rouille::Response::text("hello")),
+                limits,
+                &listener_shutdown,
+            )
+        });
+
+        // an idle connection takes the only place
+        let idle = TcpStream::connect(("127.0.0.1", port)).unwrap();
+        std::thread::sleep(Duration::from_millis(100));
+
+        let mut waiting = TcpStream::connect(("127.0.0.1", port)).unwrap();
+        waiting
+            .write_all(b"GET / HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n")
+            .unwrap();
+        waiting
+            .set_read_timeout(Some(Duration::from_millis(300)))
+            .unwrap();
+        assert!(waiting.read(&mut [0u8]).is_err());
+
+        // the waiting client is served once the idle one leaves
+        drop(idle);
+        waiting.set_read_timeout(None).unwrap();
+        let mut response = String::new();
+        waiting.read_to_string(&mut response).unwrap();
+        assert!(response.ends_with("hello"));
+
+        shutdown.stop();
+        assert!(listener.join().unwrap().is_ok());
+    }
+
+    #[test]
+    fn bounded_drain() {
+        use std::io::Write;
+        use std::net::TcpStream;
+
+        let listener = bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let shutdown = Shutdown::new();
+        let listener_shutdown = shutdown.clone();
+        let listener = std::thread::spawn(move || {
+            let limits = Limits {
+                shutdown_timeout: Duration::from_millis(200),
+                ..limits()
+            };
+            listen(
+                listener,
+                Arc::new(|_: &rouille::Request| {
+                    // a request that does not finish in time
+                    std::thread::sleep(Duration::from_secs(5));
+                    rouille::Response::text("hello")
+                }),
+                limits,
+                &listener_shutdown,
+            )
+        });
+
+        let mut stream = TcpStream::connect(("127.0.0.1", port)).unwrap();
+        stream
+            .write_all(b"GET / HTTP/1.1\r\nHost: localhost\r\n\r\n")
+            .unwrap();
+        std::thread::sleep(Duration::from_millis(200));
+
+        let stopped = Instant::now();
+        shutdown.stop();
+        assert!(listener.join().unwrap().is_ok());
+        assert!(stopped.elapsed() < Duration::from_secs(2));
+    }
+
+    #[test]
+    fn redirect_to_https_url() {
+        let request = rouille::Request::fake_http(
+            "GET",
+            "/hello?name=kong",
+            vec![("Host".to_string(), "example.com:8080".to_string())],
+            Vec::new(),
+        );
+
+        let response = redirect(&request, 443);
+        assert_eq!(response.status_code, 301);
+        assert!(response
+            .headers
+            .iter()
+            .any(|(h, v)| h == "Location" && v == "https://example.com/hello?name=kong"));
+
+        let response = redirect(&request, 8443);
+        assert!(response
+            .headers
+            .iter()
+            .any(|(h, v)| h == "Location" && v == "https://example.com:8443/hello?name=kong"));
+    }
+
+    #[cfg(feature = "tls")]
+    mod tls {
+        use super::super::*;
+        use super::limits;
+        use std::fs;
+        use std::io::{Read, Write};
+        use std::net::TcpStream;
+
+        /// Generate a self signed certificate, returns the DER encoded
+        /// certificate
+        fn self_signed(certificate_path: &str, private_key_path: &str) -> Vec<u8> {
+            let certificate = rcgen::generate_simple_self_signed(vec!["localhost".into()]).unwrap();
+            fs::write(certificate_path, certificate.serialize_pem().unwrap()).unwrap();
+            fs::write(private_key_path, certificate.serialize_private_key_pem()).unwrap();
+            certificate.serialize_der().unwrap()
+        }
+
+        /// Make an HTTPS request trusting only `certificate`, retrying
+        /// while the listener is (re)starting
+        fn get(port: u16, certificate: &[u8]) -> String {
+            let mut roots = rustls::RootCertStore::empty();
+            roots
+                .add(&rustls::Certificate(certificate.to_vec()))
+                .unwrap();
+            let config = Arc::new(
+                rustls::ClientConfig::builder()
+                    .with_safe_defaults()
+                    .with_root_certificates(roots)
+                    .with_no_client_auth(),
+            );
+
+            for _ in 0..20 {
+                let connection =
+                    rustls::ClientConnection::new(config.clone(), "localhost".try_into().unwrap())
+                        .unwrap();
+                if let Ok(socket) = TcpStream::connect(("127.0.0.1", port)) {
+                    let mut stream = rustls::StreamOwned::new(connection, socket);
+                    let request =
+                        b"GET /hello HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n";
+                    let mut response = String::new();
+
+                    if stream.write_all(request).is_ok() {
+                        let _ = stream.read_to_string(&mut response);
+                        if response.starts_with("HTTP/1.1 200") {
+                            return response;
+                        }
+                    }
+                }
+                std::thread::sleep(Duration::from_millis(500));
+            }
+            panic!("HTTPS request failed")
+        }
+
+        fn free_port() -> u16 {
+            std::net::TcpListener::bind("127.0.0.1:0")
+                .unwrap()
+                .local_addr()
+                .unwrap()
+                .port()
+        }
+
+        #[test]
+        fn serve_and_reload_certificate() {
+            let dir = std::env::temp_dir();
+            let tls = TlsKonfig {
+                certificate: dir.join("kong-test-cert.pem").display().to_string(),
+                private_key: dir.join("kong-test-key.pem").display().to_string(),
+                redirect_port: None,
+            };
+            let first = self_signed(&tls.certificate, &tls.private_key);
+
+            let port = free_port();
+            let address = format!("127.0.0.1:{port}");
+            let listener_tls = tls.clone();
+            let shutdown = Shutdown::new();
+            let listener_shutdown = shutdown.clone();
+            let listener = std::thread::spawn(move || {
+                listen_tls(
+                    &address,
+                    Arc::new(|_: &rouille::Request| rouille::Response::text("hello")),
+                    &listener_tls,
+                    limits(),
+                    &listener_shutdown,
+                )
+            });
+            let response = get(port, &first);
+            assert!(response.ends_with("hello"));
+
+            // replace the certificate, the listener swaps it in
+            std::thread::sleep(Duration::from_millis(1100));
+            let second = self_signed(&tls.certificate, &tls.private_key);
+
+            let response = get(port, &second);
+            assert!(response.ends_with("hello"));
+
+            // an invalid certificate is ignored
+            std::thread::sleep(Duration::from_millis(1100));
+            fs::write(&tls.certificate, "not a certificate").unwrap();
+            std::thread::sleep(Duration::from_millis(1500));
+            let response = get(port, &second);
+            assert!(response.ends_with("hello"));
+
+            shutdown.stop();
+            assert!(listener.join().unwrap().is_ok());
+        }
+    }
+}
@@ -1,62 +1,341 @@
 //! 📇 `kong` node logging
+//!
+//! Log entries have a [`Level`], a message and key-value fields:
+//!
+//! ```
+//! use kong::json;
+//! use kong::log::Log;
+//!
+//! Log::info("user created", &[("username", json!("firephoenix"))]).unwrap();
+//! ```
+//!
+//! Entries are written as text lines or as JSON lines, one object per
+//! entry, to the console and/or to the LOG file of the working
+//! directory:
+//!
+//! ```toml
+//! log_level = "debug"
+//! log_format = "json"
+//! ```
+//!
+//! Entries logged while a request is handled have a `request_id` field,
+//! see [`crate::request_id`].
+//!
+//! The logging configuration is read once, when the node starts. Entries
+//! logged before that use the configuration file the program was started
+//! with, if any.
 
 use crate::defaults;
 use crate::konfig::Konfig;
+use crate::rotation::RotatingFile;
 use crate::KError;
-use chrono::Utc;
-use std::fs::OpenOptions;
+use chrono::{SecondsFormat, Utc};
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use std::cell::RefCell;
+use std::fmt;
 use std::io::prelude::*;
+use std::sync::{Mutex, OnceLock};
+
+/// Logger of the process
+static LOGGER: OnceLock<Mutex<Logger>> = OnceLock::new();
+
+thread_local! {
+    /// ID of the request handled by the thread
+    static REQUEST_ID: RefCell<Option<String>> = const { RefCell::new(None) };
+}
+
+/// Request ID of the entries logged by the thread, until it is dropped
+pub(crate) struct RequestScope;
+
+impl RequestScope {
+    /// Log entries of the thread with the request ID `id`
+    pub(crate) fn enter(id: &str) -> Self {
+        REQUEST_ID.with(|request_id| *request_id.borrow_mut() = Some(id.to_string()));
+        RequestScope
+    }
+}
+
+impl Drop for RequestScope {
+    fn drop(&mut self) {
+        REQUEST_ID.with(|request_id| *request_id.borrow_mut() = None);
+    }
+}
+
+/// Request ID of the thread, if it is handling a request
+pub(crate) fn request_id() -> Option<String> {
+    REQUEST_ID.with(|request_id| request_id.borrow().clone())
+}
+
+/// 📇 Severity of a log entry
+#[derive(Deserialize, Serialize, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Debug, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum Level {
+    /// Detailed tracing information
+    Trace,
+    /// Debugging information
+    Debug,
+    /// Normal operation of the node
+    #[default]
+    Info,
+    /// Something unexpected, the node keeps working
+    Warn,
+    /// Something failed
+    Error,
+}
+
+impl fmt::Display for Level {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let level = match self {
+            Level::Trace => "TRACE",
+            Level::Debug => "DEBUG",
+            Level::Info => "INFO",
+            Level::Warn => "WARN",
+            Level::Error => "ERROR",
+        };
+        f.write_str(level)
+    }
+}
+
+/// 📇 Format of log entries
+#[derive(Deserialize, Clone, Copy, PartialEq, Eq, Debug, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum LogFormat {
+    /// `---+ [time] LEVEL: message key=value`
+    #[default]
+    Text,
+    /// One JSON object per line
+    Json,
+}
+
+/// Logging configuration and open LOG file
+struct Logger {
+    level: Level,
+    format: LogFormat,
+    console: bool,
+    file: Option<RotatingFile>,
+}
+
+impl Logger {
+    fn new(config: &Konfig) -> Result<Self, KError> {
+        let file = match config.log_file {
+            Some(true) => {
+                let log_file_path =
+                    std::path::Path::new(config.working_dir()).join(defaults::LOG_FILE);
+                let file = RotatingFile::open(&log_file_path, config.log_rotation.as_ref())
+                    .map_err(|source| KError::LogFile {
+                        path: Some(log_file_path.clone()),
+                        source,
+                    })?;
+                Some(file)
+            }
+            _ => None,
+        };
+
+        Ok(Logger {
+            level: config.log_level.unwrap_or_default(),
+            format: config.log_format.unwrap_or_default(),
+            console: config.console_log.unwrap_or(true),
+            file,
+        })
+    }
+
+    /// Logger of the configuration file the program was started with,
+    /// used until the node is initialized
+    fn from_args() -> Self {
+        Konfig::try_read()
+            .and_then(|config| Logger::new(&config).ok())
+            .unwrap_or(Logger {
+                level: Level::default(),
+                format: LogFormat::default(),
+                console: true,
+                file: None,
+            })
+    }
+
+    fn write(
+        &mut self,
+        level: Level,
+        message: &str,
+        fields: &[(&str, Value)],
+    ) -> Result<(), KError> {
+        if level < self.level {
+            return Ok(());
+        }
+
+        let line = format(self.format, level, message, fields);
+        if self.console {
+            eprintln!("{line}");
+        }
+        if let Some(file) = &mut self.file {
+            file.write_line(&line).map_err(|source| KError::LogFile {
+                path: Some(file.path().to_path_buf()),
+                source,
+            })?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Format a log entry
+fn format(format: LogFormat, level: Level, message: &str, fields: &[(&str, Value)]) -> String {
+    let now = Utc::now();
+
+    match format {
+        LogFormat::Text => {
+            let mut line = format!("---+ [{now}] {level}: {message}");
+            for (key, value) in fields {
+                match value {
+                    Value::String(s) if !s.is_empty() && !s.contains(char::is_whitespace) => {
+                        line.push_str(&format!(" {key}={s}"))
+                    }
+                    value => line.push_str(&format!(" {key}={value}")),
+                }
+            }
+            line
+        }
+        LogFormat::Json => {
+            let mut entry = Map::new();
+            for (key, value) in fields {
+                entry.insert(key.to_string(), value.clone());
+            }
+            entry.insert(
+                "timestamp".to_string(),
+                Value::String(now.to_rfc3339_opts(SecondsFormat::Millis, true)),
+            );
+            entry.insert("level".to_string(), serde_json::json!(level));
+            entry.insert("message".to_string(), Value::String(message.to_string()));
+            Value::Object(entry).to_string()
+        }
+    }
+}
+
+fn logger() -> &'static Mutex<Logger> {
+    LOGGER.get_or_init(|| Mutex::new(Logger::from_args()))
+}
 
 /// 📇 Logging management
 pub struct Log;
 
 impl Log {
-    /// Log data
+    /// Configure logging, the LOG file is created if file logging is
+    /// enabled and it does not exist. [`crate::kroute`] calls it once,
+    /// when the node starts.
+    pub fn init(config: &Konfig) -> Result<(), KError> {
+        let new = Logger::new(config)?;
+        *logger().lock().unwrap_or_else(|e| e.into_inner()) = new;
+        Ok(())
+    }
+
+    /// Log a message at the [`Level::Info`] level
     pub fn log(message: &str) -> Result<(), KError> {
-        let logging = Konfig::read_logging();
+        Log::event(Level::Info, message, &[])
+    }
 
-        if let Some(console_logging) = logging.0 {
-            if console_logging {
-                // Log to console
-                Log::log_to_console(message);
-            }
-        }
+    /// Log an entry with fields
+    pub fn event(level: Level, message: &str, fields: &[(&str, Value)]) -> Result<(), KError> {
+        let request_id = request_id();
+        let mut logger = logger().lock().unwrap_or_else(|e| e.into_inner());
 
-        if let Some(file_logging) = logging.1 {
-            if file_logging {
-                // Log to file
-                Log::log_to_file(message)?;
+        match request_id {
+            Some(id) => {
+                let mut fields = fields.to_vec();
+                fields.push(("request_id", Value::String(id)));
+                logger.write(level, message, &fields)
             }
+            None => logger.write(level, message, fields),
         }
+    }
 
-        Ok(())
+    /// Log a [`Level::Trace`] entry
+    pub fn trace(message: &str, fields: &[(&str, Value)]) -> Result<(), KError> {
+        Log::event(Level::Trace, message, fields)
+    }
+
+    /// Log a [`Level::Debug`] entry
+    pub fn debug(message: &str, fields: &[(&str, Value)]) -> Result<(), KError> {
+        Log::event(Level::Debug, message, fields)
     }
 
-    fn log_to_console(message: &str) {
-        let now = Utc::now();
-        eprintln!("---+ [{now}]: {message}");
+    /// Log a [`Level::Info`] entry
+    pub fn info(message: &str, fields: &[(&str, Value)]) -> Result<(), KError> {
+        Log::event(Level::Info, message, fields)
     }
 
-    fn log_to_file(message: &str) -> Result<(), KError> {
-        let working_dir = Konfig::read_working_dir();
-        let working_dir_path = std::path::Path::new(&working_dir);
-        let log_file = std::path::Path::new(defaults::LOG_FILE);
-        let log_file_path = working_dir_path.join(log_file);
+    /// Log a [`Level::Warn`] entry
+    pub fn warn(message: &str, fields: &[(&str, Value)]) -> Result<(), KError> {
+        Log::event(Level::Warn, message, fields)
+    }
 
-        let file = OpenOptions::new()
-            .write(true)
-            .append(true)
-            .open(log_file_path);
+    /// Log a [`Level::Error`] entry
+    pub fn error(message: &str, fields: &[(&str, Value)]) -> Result<(), KError> {
+        Log::event(Level::Error, message, fields)
+    }
 
-        if let Ok(mut file) = file {
-            let now = Utc::now();
-            if let Err(_e) = writeln!(file, "---+ [{now}]: {message}") {
-                Err(KError::LogFile)
-            } else {
-                Ok(())
-            }
-        } else {
-            Err(KError::LogFile)
+    /// Whether entries of `level` are logged
+    pub fn enabled(level: Level) -> bool {
+        level >= logger().lock().unwrap_or_else(|e| e.into_inner()).level
+    }
+
+    /// Flush logs, making sure they are written to disk
+    pub fn flush() -> Result<(), KError> {
+        std::io::stderr()
+            .flush()
+            .map_err(|source| KError::LogFile { path: None, source })?;
+
+        let logger = logger().lock().unwrap_or_else(|e| e.into_inner());
+        if let Some(file) = &logger.file {
+            file.sync().map_err(|source| KError::LogFile {
+                path: Some(file.path().to_path_buf()),
+                source,
+            })?;
         }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn format_entries() {
+        let fields = [
+            ("method", json!("GET")),
+            ("status", json!(200)),
+            ("agent", json!("kong test")),
+        ];
+
+        let text = format(LogFormat::Text, Level::Warn, "request", &fields);
+        assert!(text.starts_with("---+ ["));
+        assert!(text.ends_with("] WARN: request method=GET status=200 agent=\"kong test\""));
+
+        let entry: Value =
+            serde_json::from_str(&format(LogFormat::Json, Level::Warn, "request", &fields))
+                .unwrap();
+        assert_eq!(entry["level"], "warn");
+        assert_eq!(entry["message"], "request");
+        assert_eq!(entry["method"], "GET");
+        assert_eq!(entry["status"], 200);
+        assert!(entry["timestamp"].is_string());
+    }
+
+    #[test]
+    fn filter_levels() {
+        let mut logger = Logger {
+            level: Level::Warn,
+            format: LogFormat::Text,
+            console: false,
+            file: None,
+        };
+        assert!(Level::Trace < Level::Info && Level::Warn < Level::Error);
+        assert!(logger.write(Level::Debug, "ignored", &[]).is_ok());
+
+        let level: Level = serde_json::from_value(json!("error")).unwrap();
+        assert_eq!(level, Level::Error);
+        assert_eq!(level.to_string(), "ERROR");
     }
 }
@@ -0,0 +1,121 @@
+//! 🗃️ Kong bounded map with least recently used eviction
+
+use std::borrow::Borrow;
+use std::collections::{BTreeMap, HashMap};
+use std::hash::Hash;
+
+/// Map holding at most `capacity` entries, inserting into a full map
+/// evicts the least recently used entry. Lookups, updates and evictions
+/// are `O(log n)`.
+pub(crate) struct LruMap<K, V> {
+    capacity: usize,
+    /// Values and the tick they were last used at
+    entries: HashMap<K, (V, u64)>,
+    /// Keys by the tick they were last used at, oldest first
+    order: BTreeMap<u64, K>,
+    tick: u64,
+}
+
+impl<K: Hash + Eq + Clone, V> LruMap<K, V> {
+    /// Create a map holding at most `capacity` entries
+    pub(crate) fn new(capacity: usize) -> Self {
+        LruMap {
+            capacity: capacity.max(1),
+            entries: HashMap::new(),
+            order: BTreeMap::new(),
+            tick: 0,
+        }
+    }
+
+    /// Number of entries
+    pub(crate) fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    /// Value of `key`, without marking it as used
+    pub(crate) fn peek<Q>(&self, key: &Q) -> Option<&V>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        self.entries.get(key).map(|(value, _)| value)
+    }
+
+    /// Value of `key`, inserted with `default` if there is none, marked
+    /// as the most recently used entry
+    pub(crate) fn get_or_insert_with(&mut self, key: K, default: impl FnOnce() -> V) -> &mut V {
+        self.tick += 1;
+        let tick = self.tick;
+
+        if let Some((_, used)) = self.entries.get_mut(&key) {
+            self.order.remove(used);
+            *used = tick;
+        } else {
+            if self.len() >= self.capacity {
+                self.pop_oldest();
+            }
+            self.entries.insert(key.clone(), (default(), tick));
+        }
+        self.order.insert(tick, key.clone());
+
+        &mut self.entries.get_mut(&key).expect("entry was inserted").0
+    }
+
+    /// Remove the value of `key`
+    pub(crate) fn remove<Q>(&mut self, key: &Q) -> Option<V>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        let (value, used) = self.entries.remove(key)?;
+        self.order.remove(&used);
+        Some(value)
+    }
+
+    /// Least recently used entry
+    pub(crate) fn oldest(&self) -> Option<(&K, &V)> {
+        let (_, key) = self.order.first_key_value()?;
+        self.entries.get(key).map(|(value, _)| (key, value))
+    }
+
+    /// Remove the least recently used entry
+    pub(crate) fn pop_oldest(&mut self) -> Option<(K, V)> {
+        let (_, key) = self.order.pop_first()?;
+        self.entries.remove(&key).map(|(value, _)| (key, value))
+    }
+
+    /// Entries, least recently used first
+    pub(crate) fn iter(&self) -> impl Iterator<Item = (&K, &V)> {
+        self.order
+            .values()
+            .filter_map(|key| self.entries.get(key).map(|(value, _)| (key, value)))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn evict_least_recently_used() {
+        let mut map = LruMap::new(2);
+        *map.get_or_insert_with("a", || 0) += 1;
+        *map.get_or_insert_with("b", || 0) += 1;
+        // "a" is used again, "b" is now the least recently used
+        *map.get_or_insert_with("a", || 0) += 1;
+        map.get_or_insert_with("c", || 0);
+
+        assert_eq!(map.len(), 2);
+        assert_eq!(map.peek("a"), Some(&2));
+        assert_eq!(map.peek("b"), None);
+        assert_eq!(map.oldest(), Some((&"a", &2)));
+        assert_eq!(
+            map.iter().map(|(key, _)| *key).collect::<Vec<_>>(),
+            ["a", "c"]
+        );
+
+        assert_eq!(map.remove("a"), Some(2));
+        assert_eq!(map.pop_oldest(), Some(("c", 0)));
+        assert_eq!(map.len(), 0);
+    }
+}
@@ -0,0 +1,351 @@
+//! 📈 `kong` metrics
+//!
+//! kong records metrics of the requests it handles:
+//!
+//! - `kong_http_requests_total`, requests by method, route and status
+//! - `kong_http_request_duration_seconds`, a histogram of the time spent
+//!   handling requests by method, route and status
+//! - `kong_http_requests_in_flight`, requests received and not yet
+//!   responded to
+//! - `kong_kpassport_validation_failures_total`, invalid kpassports by
+//!   [`krypto::error::KryptoError`] kind
+//! - `kong_rate_limit_rejections_total`, requests over the `global` or
+//!   `route` rate limit
+//! - `kong_static_file_hits_total`, requests served a static file
+//!
+//! The route of a request is the address pattern of its kontroller
+//! (`/users/:id`), or `none` if no kontroller matched, so the number of
+//! series is bounded. Metrics are served in the Prometheus text format
+//! if the endpoint is configured, usually on an admin listener:
+//!
+//! ```toml
+//! [metrics]
+//! path = "/metrics"
+//! listener = "admin"
+//! ```
+//!
+//! The endpoint is public on a listener of its own, which only the
+//! metrics scraper should be able to reach. On the main listener it is
+//! restricted to the node administrators, unless `auth` is set.
+
+use crate::konfig::MetricsKonfig;
+use crate::{AuthPolicy, Kong, Kontrol, Method};
+use krypto::error::KryptoError;
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::sync::atomic::{AtomicI64, AtomicU64, Ordering};
+use std::sync::{Mutex, OnceLock};
+use std::time::Duration;
+
+/// Upper bounds in seconds of the latency histogram buckets
+const BUCKETS: [f64; 11] = [
+    0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0,
+];
+
+/// Route label of requests no kontroller matched
+pub const UNMATCHED_ROUTE: &str = "none";
+
+/// Metrics of the process
+static METRICS: OnceLock<Metrics> = OnceLock::new();
+
+/// 📈 Metrics of the process
+pub fn metrics() -> &'static Metrics {
+    METRICS.get_or_init(Metrics::default)
+}
+
+/// Requests of a method, route and status
+#[derive(Default)]
+struct RequestStats {
+    count: u64,
+    /// Number of requests in each bucket, not cumulative
+    buckets: [u64; BUCKETS.len()],
+    /// Sum of the latencies in seconds
+    sum: f64,
+}
+
+/// 📈 Metrics registry
+#[derive(Default)]
+pub struct Metrics {
+    requests: Mutex<BTreeMap<(String, String, u16), RequestStats>>,
+    in_flight: AtomicI64,
+    kpassport_failures: Mutex<BTreeMap<String, u64>>,
+    rate_limit_rejections: Mutex<BTreeMap<String, u64>>,
+    static_file_hits: AtomicU64,
+}
+
+impl Metrics {
+    /// Record a handled request
+    pub fn request(&self, method: &str, route: &str, status: u16, latency: Duration) {
+        let mut requests = self.requests.lock().unwrap_or_else(|e| e.into_inner());
+        let stats = requests
+            .entry((method.to_string(), route.to_string(), status))
+            .or_default();
+
+        let seconds = latency.as_secs_f64();
+        stats.count += 1;
+        stats.sum += seconds;
+        if let Some(bucket) = BUCKETS.iter().position(|bound| seconds <= *bound) {
+            stats.buckets[bucket] += 1;
+        }
+    }
+
+    /// Record a received request, until the returned guard is dropped
+    pub fn in_flight(&self) -> InFlight<'_> {
+        self.in_flight.fetch_add(1, Ordering::SeqCst);
+        InFlight(self)
+    }
+
+    /// Record an invalid kpassport
+    pub fn kpassport_failure(&self, error: &KryptoError) {
+        let mut failures = self
+            .kpassport_failures
+            .lock()
+            .unwrap_or_else(|e| e.into_inner());
+        *failures.entry(format!("{error:?}")).or_default() += 1;
+    }
+
+    /// Record a request over the `global` or `route` rate limit
+    pub fn rate_limit_rejection(&self, scope: &str) {
+        let mut rejections = self
+            .rate_limit_rejections
+            .lock()
+            .unwrap_or_else(|e| e.into_inner());
+        *rejections.entry(scope.to_string()).or_default() += 1;
+    }
+
+    /// Record a request served a static file
+    pub fn static_file_hit(&self) {
+        self.static_file_hits.fetch_add(1, Ordering::SeqCst);
+    }
+
+    /// Metrics in the Prometheus text format
+    pub fn render(&self) -> String {
+        let mut out = String::new();
+
+        out.push_str("# HELP kong_http_requests_total Requests handled.\n");
+        out.push_str("# TYPE kong_http_requests_total counter\n");
+        let requests = self.requests.lock().unwrap_or_else(|e| e.into_inner());
+        for ((method, route, status), stats) in requests.iter() {
+            let labels = labels(&[
+                ("method", method),
+                ("route", route),
+                ("status", &status.to_string()),
+            ]);
+            let _ = writeln!(out, "kong_http_requests_total{{{labels}}} {}", stats.count);
+        }
+
+        out.push_str("# HELP kong_http_request_duration_seconds Time spent handling requests.\n");
+        out.push_str("# TYPE kong_http_request_duration_seconds histogram\n");
+        for ((method, route, status), stats) in requests.iter() {
+            let labels = labels(&[
+                ("method", method),
+                ("route", route),
+                ("status", &status.to_string()),
+            ]);
+            let mut cumulative = 0;
+            for (bound, count) in BUCKETS.iter().zip(stats.buckets) {
+                cumulative += count;
+                let _ = writeln!(
+                    out,
+                    "kong_http_request_duration_seconds_bucket{{{labels},le=\"{bound}\"}} {cumulative}"
+                );
+            }
+            let _ = writeln!(
+                out,
+                "kong_http_request_duration_seconds_bucket{{{labels},le=\"+Inf\"}} {}",
+                stats.count
+            );
+            let _ = writeln!(
+                out,
+                "kong_http_request_duration_seconds_sum{{{labels}}} {}",
+                stats.sum
+            );
+            let _ = writeln!(
+                out,
+                "kong_http_request_duration_seconds_count{{{labels}}} {}",
+                stats.count
+            );
+        }
+        drop(requests);
+
+        out.push_str(
+            "# HELP kong_http_requests_in_flight Requests received and not yet responded to.\n",
+        );
+        out.push_str("# TYPE kong_http_requests_in_flight gauge\n");
+        let _ = writeln!(
+            out,
+            "kong_http_requests_in_flight {}",
+            self.in_flight.load(Ordering::SeqCst)
+        );
+
+        out.push_str("# HELP kong_kpassport_validation_failures_total Invalid kpassports.\n");
+        out.push_str("# TYPE kong_kpassport_validation_failures_total counter\n");
+        for (kind, count) in self
+            .kpassport_failures
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .iter()
+        {
+            let labels = labels(&[("kind", kind)]);
+            let _ = writeln!(
+                out,
+                "kong_kpassport_validation_failures_total{{{labels}}} {count}"
+            );
+        }
+
+        out.push_str("# HELP kong_rate_limit_rejections_total Requests over a rate limit.\n");
+        out.push_str("# TYPE kong_rate_limit_rejections_total counter\n");
+        for (scope, count) in self
+            .rate_limit_rejections
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .iter()
+        {
+            let labels = labels(&[("scope", scope)]);
+            let _ = writeln!(out, "kong_rate_limit_rejections_total{{{labels}}} {count}");
+        }
+
+        out.push_str("# HELP kong_static_file_hits_total Requests served a static file.\n");
+        out.push_str("# TYPE kong_static_file_hits_total counter\n");
+        let _ = writeln!(
+            out,
+            "kong_static_file_hits_total {}",
+            self.static_file_hits.load(Ordering::SeqCst)
+        );
+
+        out
+    }
+}
+
+/// 📈 Request in flight, see [`Metrics::in_flight`]
+pub struct InFlight<'a>(&'a Metrics);
+
+impl Drop for InFlight<'_> {
+    fn drop(&mut self) {
+        self.0.in_flight.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+/// Prometheus labels, with escaped values
+fn labels(labels: &[(&str, &str)]) -> String {
+    labels
+        .iter()
+        .map(|(name, value)| {
+            let value = value
+                .replace('\\', "\\\\")
+                .replace('"', "\\\"")
+                .replace('\n', "\\n");
+            format!("{name}=\"{value}\"")
+        })
+        .collect::<Vec<String>>()
+        .join(",")
+}
+
+/// Kontroller serving the metrics
+pub(crate) struct MetricsKontroller {
+    config: MetricsKonfig,
+}
+
+impl MetricsKontroller {
+    pub(crate) fn new(config: &MetricsKonfig) -> Self {
+        MetricsKontroller {
+            config: config.clone(),
+        }
+    }
+}
+
+impl Kontrol for MetricsKontroller {
+    fn address(&self) -> String {
+        self.config.path.clone()
+    }
+
+    fn method(&self) -> Method {
+        Method::Get
+    }
+
+    fn listener(&self) -> Option<String> {
+        self.config.listener.clone()
+    }
+
+    fn summary(&self) -> Option<String> {
+        Some("Metrics of the node, in the Prometheus text format".to_string())
+    }
+
+    fn auth(&self) -> AuthPolicy {
+        match (self.config.auth, &self.config.listener) {
+            (Some(auth), _) => auth,
+            (None, Some(_)) => AuthPolicy::Public,
+            (None, None) => AuthPolicy::Admin,
+        }
+    }
+
+    fn kontrol(&self, _kong: &Kong) -> rouille::Response {
+        rouille::Response::from_data("text/plain; version=0.0.4", metrics().render())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn render_metrics() {
+        let metrics = Metrics::default();
+        metrics.request("GET", "/users/:id", 200, Duration::from_millis(30));
+        metrics.request("GET", "/users/:id", 200, Duration::from_secs(20));
+        metrics.kpassport_failure(&KryptoError::InvalidKpassportSignature);
+        metrics.rate_limit_rejection("global");
+        metrics.static_file_hit();
+        let in_flight = metrics.in_flight();
+
+        let text = metrics.render();
+        assert!(text.contains(
+            "kong_http_requests_total{method=\"GET\",route=\"/users/:id\",status=\"200\"} 2\n"
+        ));
+        assert!(text.contains("route=\"/users/:id\",status=\"200\",le=\"0.025\"} 0\n"));
+        assert!(text.contains("route=\"/users/:id\",status=\"200\",le=\"0.05\"} 1\n"));
+        assert!(text.contains("route=\"/users/:id\",status=\"200\",le=\"10\"} 1\n"));
+        assert!(text.contains("route=\"/users/:id\",status=\"200\",le=\"+Inf\"} 2\n"));
+        assert!(text.contains(
+            "kong_kpassport_validation_failures_total{kind=\"InvalidKpassportSignature\"} 1\n"
+        ));
+        assert!(text.contains("kong_rate_limit_rejections_total{scope=\"global\"} 1\n"));
+        assert!(text.contains("kong_static_file_hits_total 1\n"));
+        assert!(text.contains("kong_http_requests_in_flight 1\n"));
+
+        drop(in_flight);
+        assert!(metrics
+            .render()
+            .contains("kong_http_requests_in_flight 0\n"));
+        assert_eq!(labels(&[("a", "x\"y")]), "a=\"x\\\"y\"");
+    }
+
+    #[test]
+    fn endpoint_auth() {
+        use crate::testing::{konfig, TestNode, TestRequest};
+
+        let config = konfig(
+            r#"
+            admins = ["firephoenix"]
+
+            [metrics]
+            path = "/metrics"
+            "#,
+        );
+        let node = TestNode::new(config, vec![]).unwrap();
+
+        node.send(TestRequest::get("/metrics")).assert_status(401);
+        let text = node
+            .send(TestRequest::get("/metrics").kpassport(&node, "firephoenix"))
+            .assert_status(200)
+            .text();
+        assert!(text.contains("method=\"GET\",route=\"/metrics\",status=\"401\""));
+
+        let dedicated = MetricsKontroller::new(&MetricsKonfig {
+            path: "/metrics".to_string(),
+            listener: Some("metrics".to_string()),
+            auth: None,
+        });
+        assert_eq!(dedicated.auth(), AuthPolicy::Public);
+    }
+}
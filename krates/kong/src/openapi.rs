@@ -0,0 +1,408 @@
+//! 📜 `kong` OpenAPI document
+//!
+//! kong generates an [OpenAPI 3.1](https://spec.openapis.org/oas/v3.1.0)
+//! document describing the endpoints of a node, from the address,
+//! method, authorization policy and schemas of its kontrollers:
+//!
+//! ```
+//! use kong::{json, server, JsonValue, Kong, Kontrol, Method};
+//!
+//! struct CreateUser;
+//!
+//! impl Kontrol for CreateUser {
+//!     fn address(&self) -> String {
+//!         "/users".to_string()
+//!     }
+//!     fn method(&self) -> Method {
+//!         Method::Post
+//!     }
+//!     fn summary(&self) -> Option<String> {
+//!         Some("Create a user".to_string())
+//!     }
+//!     fn input_schema(&self) -> Option<JsonValue> {
+//!         Some(json!({
+//!             "type": "object",
+//!             "properties": { "username": { "type": "string" } },
+//!             "required": ["username"]
+//!         }))
+//!     }
+//!     fn kontrol(&self, _kong: &Kong) -> server::Response {
+//!         server::Response::empty_204()
+//!     }
+//! }
+//! ```
+//!
+//! Schemas are JSON Schemas (draft 2020-12), written by hand or produced
+//! by a schema generator. The document is served at the configured
+//! path:
+//!
+//! ```toml
+//! [openapi]
+//! path = "/openapi.json"
+//! version = "1.0.0"
+//! ```
+//!
+//! The document only describes the kontrollers of the listener serving
+//! it. OpenAPI path parameters match a single segment, wildcard
+//! parameters (`*name`), which match the rest of the path, are marked
+//! with the `x-kong-wildcard` extension.
+
+use crate::konfig::{Konfig, OpenApiKonfig};
+use crate::problem::{ErrorFormat, PROBLEM_CONTENT_TYPE};
+use crate::{AuthPolicy, Kong, Kontrol, KontrollerHandle, Method};
+use serde_json::{json, Map, Value};
+use std::sync::{Arc, OnceLock};
+
+/// Name of the kpassport security scheme
+const KPASSPORT_SCHEME: &str = "kpassport";
+
+/// 📜 Generate the OpenAPI document of the kontrollers served by the
+/// listener of the `[openapi]` endpoint
+pub fn document(kontrollers: &[KontrollerHandle], config: &Konfig) -> Value {
+    let version = config
+        .openapi
+        .as_ref()
+        .and_then(|openapi| openapi.version.clone())
+        .unwrap_or_else(|| "0.0.0".to_string());
+    let listener = config
+        .openapi
+        .as_ref()
+        .and_then(|openapi| openapi.listener.clone());
+
+    let mut paths = Map::new();
+    for kontroller in kontrollers {
+        if kontroller.listener() != listener {
+            continue;
+        }
+
+        // OpenAPI only describes the standard methods
+        let method = match kontroller.method() {
+            Method::Extension(_) => continue,
+            method => method.to_string().to_lowercase(),
+        };
+
+        let (path, parameters) = path(&kontroller.address());
+        let item = paths
+            .entry(path)
+            .or_insert_with(|| json!({}))
+            .as_object_mut()
+            .expect("path items are objects");
+        item.insert(method, operation(kontroller.as_ref(), parameters, config));
+    }
+
+    json!({
+        "openapi": "3.1.0",
+        "info": {
+            "title": config.hostname,
+            "version": version,
+        },
+        "paths": paths,
+        "components": {
+            "schemas": {
+                "ErrorResponse": {
+                    "type": "object",
+                    "properties": {
+                        "error_message": { "type": "string" },
+                        "request_id": { "type": "string" }
+                    },
+                    "required": ["error_message"]
+                },
+                "Problem": {
+                    "type": "object",
+                    "properties": {
+                        "type": { "type": "string", "format": "uri-reference" },
+                        "title": { "type": "string" },
+                        "status": { "type": "integer" },
+                        "detail": { "type": "string" },
+                        "instance": { "type": "string", "format": "uri-reference" },
+                        "request_id": { "type": "string" }
+                    },
+                    "required": ["type", "title", "status"]
+                }
+            },
+            "securitySchemes": {
+                KPASSPORT_SCHEME: {
+                    "type": "apiKey",
+                    "in": "cookie",
+                    "name": config.auth_cookie_name,
+                }
+            }
+        }
+    })
+}
+
+/// OpenAPI path of a kontroller address (`/users/:id` is
+/// `/users/{id}`) and its path parameters
+fn path(address: &str) -> (String, Vec<Value>) {
+    let mut parameters = Vec::new();
+    let path = address
+        .split('/')
+        .map(|segment| match segment.strip_prefix([':', '*']) {
+            Some(name) => {
+                let mut parameter = json!({
+                    "name": name,
+                    "in": "path",
+                    "required": true,
+                    "schema": { "type": "string" }
+                });
+                // OpenAPI has no parameters spanning several segments
+                if segment.starts_with('*') {
+                    parameter["description"] = json!("Rest of the path, `/` included");
+                    parameter["x-kong-wildcard"] = json!(true);
+                }
+                parameters.push(parameter);
+                format!("{{{name}}}")
+            }
+            None => segment.to_string(),
+        })
+        .collect::<Vec<String>>()
+        .join("/");
+
+    (path, parameters)
+}
+
+/// OpenAPI operation of a kontroller
+fn operation(kontroller: &dyn Kontrol, parameters: Vec<Value>, config: &Konfig) -> Value {
+    let mut operation = Map::new();
+    if let Some(summary) = kontroller.summary() {
+        operation.insert("summary".to_string(), json!(summary));
+    }
+    if !parameters.is_empty() {
+        operation.insert("parameters".to_string(), json!(parameters));
+    }
+
+    let mut responses = Map::new();
+    let success = match kontroller.output_schema() {
+        Some(schema) => json!({
+            "description": "Success",
+            "content": { "application/json": { "schema": schema } }
+        }),
+        None => json!({ "description": "Success" }),
+    };
+    responses.insert("200".to_string(), success);
+
+    if let Some(schema) = kontroller.input_schema() {
+        operation.insert(
+            "requestBody".to_string(),
+            json!({
+                "required": true,
+                "content": { "application/json": { "schema": schema } }
+            }),
+        );
+        responses.insert("400".to_string(), error("Bad request", config));
+        responses.insert("413".to_string(), error("Payload Too Large", config));
+    }
+
+    match kontroller.auth() {
+        AuthPolicy::Public => {}
+        policy => {
+            operation.insert("security".to_string(), json!([{ KPASSPORT_SCHEME: [] }]));
+            responses.insert("401".to_string(), error("Unauthorized", config));
+            if policy == AuthPolicy::Admin {
+                responses.insert("403".to_string(), error("Forbidden", config));
+            }
+        }
+    }
+
+    let address = kontroller.address();
+    let rate_limited = config.rate_limit.is_some()
+        || config
+            .route(&address)
+            .and_then(|route| route.rate_limit.as_ref())
+            .is_some();
+    if rate_limited {
+        responses.insert("429".to_string(), error("Too Many Requests", config));
+    }
+    responses.insert("500".to_string(), error("Internal Server Error", config));
+
+    operation.insert("responses".to_string(), Value::Object(responses));
+    Value::Object(operation)
+}
+
+/// Response of an [`crate::ErrorResponse`], in the error format of the
+/// node
+fn error(description: &str, config: &Konfig) -> Value {
+    let (content_type, schema) = match config.error_format.unwrap_or_default() {
+        ErrorFormat::Message => ("application/json", "#/components/schemas/ErrorResponse"),
+        ErrorFormat::Problem => (PROBLEM_CONTENT_TYPE, "#/components/schemas/Problem"),
+    };
+
+    json!({
+        "description": description,
+        "content": {
+            content_type: {
+                "schema": { "$ref": schema }
+            }
+        }
+    })
+}
+
+/// Kontroller serving the OpenAPI document
+pub(crate) struct OpenApiKontroller {
+    config: OpenApiKonfig,
+    /// Set by kroute, once all kontrollers are known
+    document: Arc<OnceLock<Value>>,
+}
+
+impl OpenApiKontroller {
+    /// Create the kontroller, the document is set through the returned
+    /// handle
+    pub(crate) fn new(config: &OpenApiKonfig) -> (Self, Arc<OnceLock<Value>>) {
+        let document = Arc::new(OnceLock::new());
+        let kontroller = OpenApiKontroller {
+            config: config.clone(),
+            document: document.clone(),
+        };
+        (kontroller, document)
+    }
+}
+
+impl Kontrol for OpenApiKontroller {
+    fn address(&self) -> String {
+        self.config.path.clone()
+    }
+
+    fn method(&self) -> Method {
+        Method::Get
+    }
+
+    fn listener(&self) -> Option<String> {
+        self.config.listener.clone()
+    }
+
+    fn summary(&self) -> Option<String> {
+        Some("OpenAPI document of the node".to_string())
+    }
+
+    fn kontrol(&self, _kong: &Kong) -> rouille::Response {
+        match self.document.get() {
+            Some(document) => rouille::Response::json(document),
+            None => rouille::Response::json(&json!({})),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::testing::konfig;
+
+    struct UserKontroller;
+
+    impl Kontrol for UserKontroller {
+        fn address(&self) -> String {
+            "/users/:id".to_string()
+        }
+        fn method(&self) -> Method {
+            Method::Put
+        }
+        fn auth(&self) -> AuthPolicy {
+            AuthPolicy::Kpassport
+        }
+        fn input_schema(&self) -> Option<Value> {
+            Some(json!({ "type": "object" }))
+        }
+        fn output_schema(&self) -> Option<Value> {
+            Some(json!({ "type": "string" }))
+        }
+        fn kontrol(&self, _kong: &Kong) -> rouille::Response {
+            rouille::Response::text("kong")
+        }
+    }
+
+    struct FileKontroller {
+        listener: Option<String>,
+    }
+
+    impl Kontrol for FileKontroller {
+        fn address(&self) -> String {
+            "/files/*path".to_string()
+        }
+        fn method(&self) -> Method {
+            Method::Get
+        }
+        fn listener(&self) -> Option<String> {
+            self.listener.clone()
+        }
+        fn kontrol(&self, _kong: &Kong) -> rouille::Response {
+            rouille::Response::text("kong")
+        }
+    }
+
+    #[test]
+    fn generate_document() {
+        let config = konfig(
+            r#"
+            [openapi]
+            path = "/openapi.json"
+            version = "1.2.0"
+            "#,
+        );
+        let (openapi, _) = OpenApiKontroller::new(config.openapi.as_ref().unwrap());
+        let kontrollers: Vec<KontrollerHandle> = vec![
+            Box::new(UserKontroller),
+            Box::new(openapi),
+            Box::new(FileKontroller { listener: None }),
+            Box::new(FileKontroller {
+                listener: Some("admin".to_string()),
+            }),
+        ];
+
+        let document = document(&kontrollers, &config);
+        assert_eq!(document["openapi"], "3.1.0");
+        assert_eq!(document["info"]["version"], "1.2.0");
+        assert_eq!(
+            document["components"]["securitySchemes"]["kpassport"]["name"],
+            "kpassport"
+        );
+
+        let put = &document["paths"]["/users/{id}"]["put"];
+        assert_eq!(put["parameters"][0]["name"], "id");
+        assert_eq!(
+            put["requestBody"]["content"]["application/json"]["schema"]["type"],
+            "object"
+        );
+        assert_eq!(
+            put["responses"]["200"]["content"]["application/json"]["schema"]["type"],
+            "string"
+        );
+        assert_eq!(put["security"][0]["kpassport"], json!([]));
+        assert!(put["responses"]["401"].is_object());
+        assert!(put["responses"]["403"].is_null());
+        assert!(put["responses"]["429"].is_null());
+
+        let get = &document["paths"]["/openapi.json"]["get"];
+        assert_eq!(get["summary"], "OpenAPI document of the node");
+        assert!(get["security"].is_null());
+
+        let files = &document["paths"]["/files/{path}"]["get"];
+        assert_eq!(files["parameters"][0]["name"], "path");
+        assert_eq!(files["parameters"][0]["x-kong-wildcard"], true);
+        assert_eq!(files["parameters"].as_array().unwrap().len(), 1);
+    }
+
+    #[test]
+    fn document_of_listener() {
+        let config = konfig(
+            r#"
+            [openapi]
+            path = "/openapi.json"
+            listener = "admin"
+            "#,
+        );
+        let (openapi, _) = OpenApiKontroller::new(config.openapi.as_ref().unwrap());
+        let kontrollers: Vec<KontrollerHandle> = vec![
+            Box::new(UserKontroller),
+            Box::new(openapi),
+            Box::new(FileKontroller {
+                listener: Some("admin".to_string()),
+            }),
+        ];
+
+        let document = document(&kontrollers, &config);
+        let paths = document["paths"].as_object().unwrap();
+        let mut paths: Vec<&String> = paths.keys().collect();
+        paths.sort();
+        assert_eq!(paths, vec!["/files/{path}", "/openapi.json"]);
+    }
+}
@@ -0,0 +1,292 @@
+//! 🧾 `kong` problem details
+//!
+//! Errors can be reported as [RFC 7807](https://www.rfc-editor.org/rfc/rfc7807)
+//! problem details, `application/problem+json` documents with `type`,
+//! `title`, `status`, `detail` and `instance` members and extension
+//! members. Kontrollers build custom problems with [`Problem`]:
+//!
+//! ```
+//! use kong::json;
+//! use kong::problem::Problem;
+//!
+//! let response = Problem::new(422)
+//!     .type_uri("https://example.com/problems/invalid-username")
+//!     .title("Invalid username")
+//!     .detail("Usernames can only contain letters and digits")
+//!     .extension("username", json!("fire phoenix"))
+//!     .response();
+//! assert_eq!(response.status_code, 422);
+//! ```
+//!
+//! When the node is configured to use problem details, the
+//! [`crate::ErrorResponse`]s it sends are converted to problem details
+//! too:
+//!
+//! ```toml
+//! error_format = "problem"
+//! ```
+
+use crate::error_response::take_error_body;
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+
+/// Media type of problem details
+pub const PROBLEM_CONTENT_TYPE: &str = "application/problem+json";
+
+/// 🧾 Format of the error responses of a node
+#[derive(Deserialize, Clone, Copy, PartialEq, Eq, Debug, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum ErrorFormat {
+    /// `{"error_message": ...}`, see [`crate::ErrorResponse`]
+    #[default]
+    Message,
+    /// RFC 7807 problem details, see [`Problem`]
+    Problem,
+}
+
+/// 🧾 RFC 7807 problem details
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct Problem {
+    /// URI identifying the problem type, `about:blank` if the problem
+    /// has no semantics beyond the HTTP status
+    #[serde(rename = "type")]
+    pub type_uri: String,
+    /// Short summary of the problem type
+    pub title: String,
+    /// HTTP status code
+    pub status: u16,
+    /// Explanation of this occurrence of the problem
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub detail: Option<String>,
+    /// URI identifying this occurrence of the problem
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub instance: Option<String>,
+    /// Extension members
+    #[serde(flatten)]
+    pub extensions: Map<String, Value>,
+}
+
+impl Problem {
+    /// Problem with an HTTP status, its title is the status reason
+    /// phrase and its type `about:blank`
+    pub fn new(status: u16) -> Self {
+        Problem {
+            type_uri: "about:blank".to_string(),
+            title: status_title(status).to_string(),
+            status,
+            detail: None,
+            instance: None,
+            extensions: Map::new(),
+        }
+    }
+
+    /// Set the problem type URI
+    pub fn type_uri(mut self, type_uri: &str) -> Self {
+        self.type_uri = type_uri.to_string();
+        self
+    }
+
+    /// Set the title
+    pub fn title(mut self, title: &str) -> Self {
+        self.title = title.to_string();
+        self
+    }
+
+    /// Set the detail
+    pub fn detail(mut self, detail: &str) -> Self {
+        self.detail = Some(detail.to_string());
+        self
+    }
+
+    /// Set the instance URI
+    pub fn instance(mut self, instance: &str) -> Self {
+        self.instance = Some(instance.to_string());
+        self
+    }
+
+    /// Add an extension member, members named like standard members
+    /// are ignored
+    pub fn extension(mut self, name: &str, value: Value) -> Self {
+        if !matches!(name, "type" | "title" | "status" | "detail" | "instance") {
+            self.extensions.insert(name.to_string(), value);
+        }
+        self
+    }
+
+    /// `application/problem+json` response of the problem, problems
+    /// built while a request is handled have its `request_id`
+    pub fn response(&self) -> rouille::Response {
+        let mut problem = self.clone();
+        if let Some(id) = crate::log::request_id() {
+            problem
+                .extensions
+                .entry("request_id")
+                .or_insert(Value::String(id));
+        }
+        let body = serde_json::to_vec(&problem).expect("problems are serializable");
+        rouille::Response::from_data(PROBLEM_CONTENT_TYPE, body).with_status_code(self.status)
+    }
+}
+
+/// Reason phrase of an HTTP status
+pub fn status_title(status: u16) -> &'static str {
+    match status {
+        400 => "Bad Request",
+        401 => "Unauthorized",
+        402 => "Payment Required",
+        403 => "Forbidden",
+        404 => "Not Found",
+        405 => "Method Not Allowed",
+        406 => "Not Acceptable",
+        408 => "Request Timeout",
+        409 => "Conflict",
+        410 => "Gone",
+        411 => "Length Required",
+        412 => "Precondition Failed",
+        413 => "Payload Too Large",
+        414 => "URI Too Long",
+        415 => "Unsupported Media Type",
+        416 => "Range Not Satisfiable",
+        417 => "Expectation Failed",
+        422 => "Unprocessable Entity",
+        423 => "Locked",
+        428 => "Precondition Required",
+        429 => "Too Many Requests",
+        431 => "Request Header Fields Too Large",
+        451 => "Unavailable For Legal Reasons",
+        500 => "Internal Server Error",
+        501 => "Not Implemented",
+        502 => "Bad Gateway",
+        503 => "Service Unavailable",
+        504 => "Gateway Timeout",
+        _ if status < 500 => "Client Error",
+        _ => "Server Error",
+    }
+}
+
+/// Convert an [`crate::ErrorResponse`] to problem details, other
+/// responses are returned unchanged
+pub(crate) fn convert(mut response: rouille::Response, instance: &str) -> rouille::Response {
+    let error = match take_error_body(&mut response) {
+        Some(error) if error.contains_key("error_message") => error,
+        Some(body) => {
+            response.data = rouille::ResponseBody::from_data(Value::Object(body).to_string());
+            return response;
+        }
+        None => return response,
+    };
+
+    let mut problem = Problem::new(response.status_code).instance(instance);
+    for (name, value) in error {
+        match (name.as_str(), value) {
+            ("error_message", Value::String(message)) => {
+                if message != problem.title {
+                    problem.detail = Some(message);
+                }
+            }
+            (name, value) => problem = problem.extension(name, value),
+        }
+    }
+
+    let problem = problem.response();
+    response.data = problem.data;
+    response
+        .headers
+        .retain(|(name, _)| !name.eq_ignore_ascii_case("Content-Type"));
+    response
+        .headers
+        .push(("Content-Type".into(), PROBLEM_CONTENT_TYPE.into()));
+    response
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::ErrorResponse;
+    use serde_json::json;
+    use std::io::Read;
+
+    fn body(response: rouille::Response) -> Value {
+        let (mut data, _) = response.data.into_reader_and_size();
+        let mut body = String::new();
+        data.read_to_string(&mut body).unwrap();
+        serde_json::from_str(&body).unwrap()
+    }
+
+    #[test]
+    fn build_problems() {
+        let response = Problem::new(422)
+            .type_uri("https://example.com/problems/invalid-username")
+            .detail("Usernames can only contain letters and digits")
+            .instance("/users")
+            .extension("username", json!("fire phoenix"))
+            .extension("status", json!(200))
+            .response();
+
+        assert_eq!(response.status_code, 422);
+        assert!(response
+            .headers
+            .iter()
+            .any(|(n, v)| n == "Content-Type" && v == PROBLEM_CONTENT_TYPE));
+        assert_eq!(
+            body(response),
+            json!({
+                "type": "https://example.com/problems/invalid-username",
+                "title": "Unprocessable Entity",
+                "status": 422,
+                "detail": "Usernames can only contain letters and digits",
+                "instance": "/users",
+                "username": "fire phoenix"
+            })
+        );
+    }
+
+    #[test]
+    fn convert_error_responses() {
+        let response = convert(ErrorResponse::too_many_requests(10), "/login");
+        assert_eq!(response.status_code, 429);
+        assert!(response
+            .headers
+            .iter()
+            .any(|(n, v)| n == "Retry-After" && v == "10"));
+        assert_eq!(
+            body(response),
+            json!({
+                "type": "about:blank",
+                "title": "Too Many Requests",
+                "status": 429,
+                "instance": "/login"
+            })
+        );
+
+        let response = convert(ErrorResponse::not_found(), "/nowhere");
+        assert_eq!(body(response)["detail"], "Could not find resource");
+
+        let response = convert(rouille::Response::json(&json!({ "a": 1 })), "/");
+        assert_eq!(body(response), json!({ "a": 1 }));
+
+        // only error responses of kong are converted
+        let error = json!({ "error_message": "taken" });
+        let response = convert(rouille::Response::json(&error).with_status_code(409), "/");
+        assert_eq!(body(response), error);
+    }
+
+    #[test]
+    fn node_error_format() {
+        use crate::testing::{konfig, TestNode, TestRequest};
+
+        let node = TestNode::new(konfig(r#"error_format = "problem""#), vec![]).unwrap();
+
+        let response = node.send(TestRequest::get("/nowhere?page=2"));
+        response
+            .assert_status(404)
+            .assert_header("Content-Type", PROBLEM_CONTENT_TYPE);
+        let problem = response.json();
+        assert_eq!(problem["title"], "Not Found");
+        assert_eq!(problem["instance"], "/nowhere");
+        assert_eq!(
+            problem["request_id"],
+            response.header("X-Request-Id").unwrap()
+        );
+    }
+}
@@ -0,0 +1,371 @@
+//! ❓ `kong` query string parameters
+//!
+//! The query string of a request is parsed into [`crate::Kong::query`]
+//! before the kontroller is called. Parameters can be read one by one,
+//! repeated keys (`?tag=a&tag=b`, or `?tag[]=a&tag[]=b`) are kept:
+//!
+//! ```
+//! use kong::query::Query;
+//!
+//! let query = Query::parse("page=2&sort=name&tag=a&tag=b");
+//! assert_eq!(query.get("page"), Some("2"));
+//! assert_eq!(query.get_all("tag"), vec!["a", "b"]);
+//! ```
+//!
+//! or extracted into a typed struct, in which case parameters that do
+//! not parse are reported as a [`ValidationError`]:
+//!
+//! ```
+//! use kong::query::Query;
+//! use serde::Deserialize;
+//!
+//! #[derive(Deserialize)]
+//! struct ListParameters {
+//!     page: Option<u32>,
+//!     sort: String,
+//!     #[serde(default)]
+//!     tag: Vec<String>,
+//! }
+//!
+//! let query = Query::parse("page=2&sort=name&tag=a&tag=b");
+//! let parameters: ListParameters = query.extract().unwrap();
+//! assert_eq!(parameters.page, Some(2));
+//! assert_eq!(parameters.tag, vec!["a", "b"]);
+//!
+//! assert!(Query::parse("page=two&sort=name").extract::<ListParameters>().is_err());
+//! ```
+
+use crate::validate::ValidationError;
+use serde::de::{self, value::Error, DeserializeOwned, IntoDeserializer, Visitor};
+use serde::forward_to_deserialize_any;
+
+/// ❓ Query string parameters of a request
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Query {
+    /// Decoded key value pairs, in the order they appear in the query
+    pairs: Vec<(String, String)>,
+}
+
+impl Query {
+    /// Parse a query string (without the leading `?`)
+    pub fn parse(query: &str) -> Self {
+        let pairs = query
+            .split('&')
+            .filter(|pair| !pair.is_empty())
+            .map(|pair| {
+                let (key, value) = pair.split_once('=').unwrap_or((pair, ""));
+                (decode(key), decode(value))
+            })
+            .collect();
+
+        Query { pairs }
+    }
+
+    /// Parse the query string of a request
+    pub(crate) fn from_request(request: &rouille::Request) -> Self {
+        Query::parse(request.raw_query_string())
+    }
+
+    /// First value of a parameter
+    pub fn get(&self, key: &str) -> Option<&str> {
+        self.pairs
+            .iter()
+            .find(|(k, _)| k == key)
+            .map(|(_, v)| v.as_str())
+    }
+
+    /// All values of a repeated parameter
+    pub fn get_all(&self, key: &str) -> Vec<&str> {
+        self.pairs
+            .iter()
+            .filter(|(k, _)| k == key)
+            .map(|(_, v)| v.as_str())
+            .collect()
+    }
+
+    /// Whether the query string has no parameters
+    pub fn is_empty(&self) -> bool {
+        self.pairs.is_empty()
+    }
+
+    /// Key value pairs, in the order they appear in the query string
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.pairs.iter().map(|(k, v)| (k.as_str(), v.as_str()))
+    }
+
+    /// Extract the parameters into a typed struct, repeated parameters
+    /// can be extracted into sequences (`Vec<T>`)
+    pub fn extract<T: DeserializeOwned>(&self) -> Result<T, ValidationError> {
+        // group values by key, `key[]` is the same parameter as `key`
+        let mut parameters: Vec<(&str, Vec<&str>)> = Vec::new();
+        for (key, value) in &self.pairs {
+            let key = key.strip_suffix("[]").unwrap_or(key);
+            match parameters.iter_mut().find(|(k, _)| *k == key) {
+                Some((_, values)) => values.push(value),
+                None => parameters.push((key, vec![value])),
+            }
+        }
+
+        let parameters = parameters
+            .into_iter()
+            .map(|(key, values)| (key, Values { key, values }));
+        T::deserialize(de::value::MapDeserializer::new(parameters))
+            .map_err(|e| ValidationError::Query(e.to_string()))
+    }
+}
+
+/// Decode a percent-encoded query string component, `+` is a space.
+/// Invalid escape sequences are kept as they are.
+fn decode(component: &str) -> String {
+    let bytes = component.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+
+    while i < bytes.len() {
+        match bytes[i] {
+            b'+' => decoded.push(b' '),
+            b'%' => match (hex(bytes.get(i + 1)), hex(bytes.get(i + 2))) {
+                (Some(high), Some(low)) => {
+                    decoded.push(high * 16 + low);
+                    i += 2;
+                }
+                _ => decoded.push(b'%'),
+            },
+            byte => decoded.push(byte),
+        }
+        i += 1;
+    }
+
+    String::from_utf8_lossy(&decoded).into_owned()
+}
+
+/// Value of a hexadecimal digit
+fn hex(digit: Option<&u8>) -> Option<u8> {
+    digit
+        .and_then(|d| (*d as char).to_digit(16))
+        .map(|d| d as u8)
+}
+
+/// Deserialize the single value of a parameter
+macro_rules! forward_single_value {
+    ($($method:ident)*) => {$(
+        fn $method<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+            self.value().$method(visitor)
+        }
+    )*};
+}
+
+/// Deserialize a value by parsing it
+macro_rules! parse_value {
+    ($($method:ident => $visit:ident: $expected:literal,)*) => {$(
+        fn $method<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+            match self.value.parse() {
+                Ok(value) => visitor.$visit(value),
+                Err(_) => Err(self.invalid($expected)),
+            }
+        }
+    )*};
+}
+
+/// Values of a query parameter
+struct Values<'a> {
+    key: &'a str,
+    values: Vec<&'a str>,
+}
+
+impl<'a> Values<'a> {
+    /// Single value of the parameter, the last one wins
+    fn value(&self) -> Value<'a> {
+        Value {
+            key: self.key,
+            value: self.values.last().copied().unwrap_or_default(),
+        }
+    }
+}
+
+impl<'de> IntoDeserializer<'de, Error> for Values<'de> {
+    type Deserializer = Self;
+
+    fn into_deserializer(self) -> Self {
+        self
+    }
+}
+
+impl<'de> de::Deserializer<'de> for Values<'de> {
+    type Error = Error;
+
+    fn deserialize_any<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        if self.values.len() > 1 {
+            self.deserialize_seq(visitor)
+        } else {
+            self.value().deserialize_any(visitor)
+        }
+    }
+
+    fn deserialize_seq<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        let key = self.key;
+        let values = self.values.into_iter().map(|value| Value { key, value });
+        visitor.visit_seq(de::value::SeqDeserializer::new(values))
+    }
+
+    fn deserialize_option<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        visitor.visit_some(self)
+    }
+
+    fn deserialize_enum<V: Visitor<'de>>(
+        self,
+        name: &'static str,
+        variants: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, Error> {
+        self.value().deserialize_enum(name, variants, visitor)
+    }
+
+    forward_single_value! {
+        deserialize_bool deserialize_i8 deserialize_i16 deserialize_i32 deserialize_i64
+        deserialize_u8 deserialize_u16 deserialize_u32 deserialize_u64 deserialize_f32
+        deserialize_f64 deserialize_char deserialize_str deserialize_string
+        deserialize_identifier
+    }
+
+    forward_to_deserialize_any! {
+        bytes byte_buf unit unit_struct newtype_struct tuple tuple_struct map struct
+        ignored_any
+    }
+}
+
+/// Single value of a query parameter
+struct Value<'a> {
+    key: &'a str,
+    value: &'a str,
+}
+
+impl<'de> IntoDeserializer<'de, Error> for Value<'de> {
+    type Deserializer = Self;
+
+    fn into_deserializer(self) -> Self {
+        self
+    }
+}
+
+impl<'de> Value<'de> {
+    fn invalid(&self, expected: &str) -> Error {
+        de::Error::custom(format!(
+            "invalid value `{}` for `{}`, expected {expected}",
+            self.value, self.key
+        ))
+    }
+}
+
+impl<'de> de::Deserializer<'de> for Value<'de> {
+    type Error = Error;
+
+    fn deserialize_any<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        visitor.visit_borrowed_str(self.value)
+    }
+
+    fn deserialize_option<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        visitor.visit_some(self)
+    }
+
+    fn deserialize_enum<V: Visitor<'de>>(
+        self,
+        name: &'static str,
+        variants: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, Error> {
+        de::value::BorrowedStrDeserializer::<Error>::new(self.value)
+            .deserialize_enum(name, variants, visitor)
+            .map_err(|_| self.invalid(&format!("one of {}", variants.join(", "))))
+    }
+
+    parse_value! {
+        deserialize_bool => visit_bool: "true or false",
+        deserialize_i8 => visit_i8: "an integer",
+        deserialize_i16 => visit_i16: "an integer",
+        deserialize_i32 => visit_i32: "an integer",
+        deserialize_i64 => visit_i64: "an integer",
+        deserialize_u8 => visit_u8: "a positive integer",
+        deserialize_u16 => visit_u16: "a positive integer",
+        deserialize_u32 => visit_u32: "a positive integer",
+        deserialize_u64 => visit_u64: "a positive integer",
+        deserialize_f32 => visit_f32: "a number",
+        deserialize_f64 => visit_f64: "a number",
+        deserialize_char => visit_char: "a single character",
+    }
+
+    forward_to_deserialize_any! {
+        str string bytes byte_buf unit unit_struct newtype_struct seq tuple tuple_struct
+        map struct identifier ignored_any
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use serde::Deserialize;
+
+    #[derive(Deserialize, Debug, PartialEq)]
+    #[serde(rename_all = "lowercase")]
+    enum Sort {
+        Name,
+        Date,
+    }
+
+    #[derive(Deserialize, Debug)]
+    struct ListParameters {
+        page: u32,
+        sort: Option<Sort>,
+        #[serde(default)]
+        tag: Vec<String>,
+        q: Option<String>,
+    }
+
+    #[test]
+    fn parse_query_string() {
+        let query = Query::parse("q=kong+node%21&tag=a&tag=b&empty&bad=%zz");
+
+        assert_eq!(query.get("q"), Some("kong node!"));
+        assert_eq!(query.get_all("tag"), vec!["a", "b"]);
+        assert_eq!(query.get("empty"), Some(""));
+        assert_eq!(query.get("bad"), Some("%zz"));
+        assert_eq!(query.get("missing"), None);
+        assert!(Query::parse("").is_empty());
+    }
+
+    #[test]
+    fn extract_parameters() {
+        let query = Query::parse("page=2&sort=date&tag[]=a&tag[]=b");
+        let parameters: ListParameters = query.extract().unwrap();
+
+        assert_eq!(parameters.page, 2);
+        assert_eq!(parameters.sort, Some(Sort::Date));
+        assert_eq!(parameters.tag, vec!["a", "b"]);
+        assert_eq!(parameters.q, None);
+
+        // a single value is a sequence of one
+        let parameters: ListParameters = Query::parse("page=1&tag=a").extract().unwrap();
+        assert_eq!(parameters.tag, vec!["a"]);
+    }
+
+    #[test]
+    fn extract_validation_errors() {
+        let error = Query::parse("page=two")
+            .extract::<ListParameters>()
+            .unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "Invalid query parameter: invalid value `two` for `page`, expected a positive integer"
+        );
+
+        let error = Query::parse("sort=size")
+            .extract::<ListParameters>()
+            .unwrap_err();
+        assert!(error.to_string().contains("one of name, date"));
+
+        let error = Query::parse("sort=name")
+            .extract::<ListParameters>()
+            .unwrap_err();
+        assert!(error.to_string().contains("missing field `page`"));
+    }
+}
@@ -0,0 +1,230 @@
+//! 🎡 `kong` request rate limiting
+//!
+//! Requests are rate limited with __token buckets__. Every client
+//! (identified by its IP address, its kpassport username or by the
+//! route it requests) gets a bucket holding `requests` tokens, every
+//! request takes one token out of the bucket and the bucket is refilled
+//! at a rate of `requests` tokens per `period`. A request that finds
+//! the bucket empty is rejected with `429 Too Many Requests`.
+//!
+//! Limits are configured globally with the `[rate_limit]` table and
+//! per route with `[routes."/address".rate_limit]`:
+//!
+//! ```toml
+//! [rate_limit]
+//! requests = 120
+//! period = 60
+//! key = "ip"
+//!
+//! [routes."/login".rate_limit]
+//! requests = 5
+//! period = 60
+//! key = "ip"
+//! ```
+//!
+//! Buckets that have been refilled completely hold no information and
+//! are dropped. At most `MAX_BUCKETS` buckets are kept, when there are
+//! more the least recently used bucket is dropped, which gives its
+//! client a full bucket again.
+
+use crate::konfig::RateLimitKonfig;
+use crate::lru::LruMap;
+use std::time::{Duration, Instant};
+
+/// Maximum number of buckets, the least recently used are dropped first
+const MAX_BUCKETS: usize = 10_000;
+
+/// Outcome of a rate limit check
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct RateLimitStatus {
+    /// Whether the request is allowed
+    pub allowed: bool,
+    /// Number of requests allowed per period
+    pub limit: u32,
+    /// Number of requests left before the limit is reached
+    pub remaining: u32,
+    /// Seconds until the bucket is full again (or, if the request was
+    /// rejected, until the next request is allowed)
+    pub reset: u64,
+}
+
+impl RateLimitStatus {
+    /// Add `RateLimit-*` headers to a response
+    pub fn headers(&self, response: rouille::Response) -> rouille::Response {
+        response
+            .with_additional_header("RateLimit-Limit", self.limit.to_string())
+            .with_additional_header("RateLimit-Remaining", self.remaining.to_string())
+            .with_additional_header("RateLimit-Reset", self.reset.to_string())
+    }
+}
+
+/// Token bucket
+struct Bucket {
+    /// Tokens left in the bucket
+    tokens: f64,
+    /// Last time the bucket was refilled
+    refilled: Instant,
+    /// Time it takes to refill the empty bucket, the period of its limit
+    period: Duration,
+}
+
+impl Bucket {
+    /// Whether the bucket is full again at `now`
+    fn is_full(&self, now: Instant) -> bool {
+        now.saturating_duration_since(self.refilled) >= self.period
+    }
+}
+
+/// 🎡 Token bucket rate limiter
+pub struct RateLimiter {
+    /// Buckets, least recently used first
+    buckets: LruMap<String, Bucket>,
+}
+
+impl Default for RateLimiter {
+    fn default() -> Self {
+        RateLimiter {
+            buckets: LruMap::new(MAX_BUCKETS),
+        }
+    }
+}
+
+impl RateLimiter {
+    /// Create a rate limiter without buckets
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// Take a token out of the bucket identified by `key`
+    pub fn check(&mut self, key: &str, limit: &RateLimitKonfig, now: Instant) -> RateLimitStatus {
+        let capacity = f64::from(limit.requests.max(1));
+        let rate = capacity / limit.period.max(1) as f64; // tokens per second
+
+        self.prune(now);
+
+        let period = Duration::from_secs(limit.period.max(1));
+        let bucket = self.buckets.get_or_insert_with(key.to_string(), || Bucket {
+            tokens: capacity,
+            refilled: now,
+            period,
+        });
+        bucket.period = period;
+
+        let elapsed = now.saturating_duration_since(bucket.refilled).as_secs_f64();
+        bucket.tokens = (bucket.tokens + elapsed * rate).min(capacity);
+        bucket.refilled = now;
+
+        let allowed = bucket.tokens >= 1.0;
+        if allowed {
+            bucket.tokens -= 1.0;
+        }
+
+        let reset = if allowed {
+            (capacity - bucket.tokens) / rate
+        } else {
+            (1.0 - bucket.tokens) / rate
+        };
+
+        RateLimitStatus {
+            allowed,
+            limit: limit.requests,
+            remaining: bucket.tokens.floor() as u32,
+            reset: reset.ceil() as u64,
+        }
+    }
+
+    /// Drop the least recently used buckets while they have been
+    /// refilled completely, according to their own period
+    fn prune(&mut self, now: Instant) {
+        while let Some((_, bucket)) = self.buckets.oldest() {
+            if !bucket.is_full(now) {
+                break;
+            }
+            self.buckets.pop_oldest();
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn limit() -> RateLimitKonfig {
+        RateLimitKonfig {
+            requests: 3,
+            period: 30,
+            key: None,
+        }
+    }
+
+    #[test]
+    fn bucket_empties_and_refills() {
+        let mut limiter = RateLimiter::new();
+        let limit = limit();
+        let now = Instant::now();
+
+        for remaining in [2, 1, 0] {
+            let status = limiter.check("10.0.0.1", &limit, now);
+            assert!(status.allowed);
+            assert_eq!(status.remaining, remaining);
+        }
+
+        let status = limiter.check("10.0.0.1", &limit, now);
+        assert!(!status.allowed);
+        assert_eq!(status.reset, 10);
+
+        // other clients have their own bucket
+        assert!(limiter.check("10.0.0.2", &limit, now).allowed);
+
+        // one token is added every 10 seconds
+        let later = now + Duration::from_secs(10);
+        assert!(limiter.check("10.0.0.1", &limit, later).allowed);
+        assert!(!limiter.check("10.0.0.1", &limit, later).allowed);
+    }
+
+    #[test]
+    fn buckets_are_pruned_by_their_own_period() {
+        let mut limiter = RateLimiter::new();
+        let hourly = RateLimitKonfig {
+            requests: 1,
+            period: 3600,
+            key: None,
+        };
+        let now = Instant::now();
+
+        assert!(limiter.check("global:10.0.0.1", &hourly, now).allowed);
+        // a shorter limit does not drop the bucket of the hourly limit
+        let later = now + Duration::from_secs(60);
+        limiter.check("/login:10.0.0.2", &limit(), later);
+        assert!(!limiter.check("global:10.0.0.1", &hourly, later).allowed);
+
+        // full buckets are dropped
+        let much_later = later + Duration::from_secs(3600);
+        limiter.check("/login:10.0.0.3", &limit(), much_later);
+        assert_eq!(limiter.buckets.len(), 1);
+    }
+
+    #[test]
+    fn buckets_are_capped() {
+        let mut limiter = RateLimiter::new();
+        let limit = limit();
+        let now = Instant::now();
+
+        for client in 0..MAX_BUCKETS + 10 {
+            limiter.check(&format!("10.0.{client}"), &limit, now);
+        }
+        assert_eq!(limiter.buckets.len(), MAX_BUCKETS);
+        assert!(limiter.buckets.peek("10.0.0").is_none());
+    }
+
+    #[test]
+    fn bucket_does_not_overflow() {
+        let mut limiter = RateLimiter::new();
+        let limit = limit();
+        let now = Instant::now();
+
+        limiter.check("10.0.0.1", &limit, now);
+        let status = limiter.check("10.0.0.1", &limit, now + Duration::from_secs(3600));
+        assert_eq!(status.remaining, 2);
+    }
+}
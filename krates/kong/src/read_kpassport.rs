@@ -14,20 +14,12 @@ pub(crate) fn get_kpassport(
     if let Some((_, cookie_value)) =
         rouille::input::cookies(request).find(|&(n, _)| n == auth_cookie_name)
     {
-        if let Ok(kpassport) = krypto::kpassport::Kpassport::from_str(cookie_value) {
-            // validate kpassport
-            if kpassport.validate(kpassport_signing_key).is_ok() {
-                Ok(kpassport)
-            } else {
-                // could not validate kpassport
-                Err(KryptoError::InvalidKpassport)
-            }
-        } else {
-            // could not read cookie token
-            Err(KryptoError::InvalidKpassport)
-        }
+        let kpassport = krypto::kpassport::Kpassport::from_str(cookie_value)?;
+        // validate kpassport
+        kpassport.validate(kpassport_signing_key)?;
+        Ok(kpassport)
     } else {
         // Cookie not found
-        Err(KryptoError::InvalidKpassport)
+        Err(KryptoError::MissingAuthenticationCredentials)
     }
 }
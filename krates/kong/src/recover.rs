@@ -0,0 +1,141 @@
+//! 🩹 Kong panic isolation
+//!
+//! A kontroller that panics fails its own request only: the panic is
+//! caught, logged with the request ID, the panic message, its location
+//! and a backtrace, and answered with a `500 Internal Server Error`. The
+//! node keeps serving the next requests.
+
+use crate::log::{self, Log};
+use crate::ErrorResponse;
+use serde_json::json;
+use std::backtrace::Backtrace;
+use std::cell::RefCell;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::Once;
+
+/// Panic hook installation
+static HOOK: Once = Once::new();
+
+thread_local! {
+    /// Panic of the request handled by the thread
+    static PANIC: RefCell<Option<Panic>> = const { RefCell::new(None) };
+}
+
+/// Panic of a request, recorded by the panic hook
+struct Panic {
+    message: String,
+    location: String,
+    backtrace: String,
+}
+
+/// Install the panic hook recording the panics of requests, other
+/// panics are reported by the previous hook
+fn install_hook() {
+    HOOK.call_once(|| {
+        let previous = panic::take_hook();
+        panic::set_hook(Box::new(move |info| {
+            if log::request_id().is_none() {
+                return previous(info);
+            }
+
+            let message = if let Some(message) = info.payload().downcast_ref::<&str>() {
+                message.to_string()
+            } else if let Some(message) = info.payload().downcast_ref::<String>() {
+                message.clone()
+            } else {
+                "Box<dyn Any>".to_string()
+            };
+            let location = info
+                .location()
+                .map(|location| location.to_string())
+                .unwrap_or_default();
+            let panic = Panic {
+                message,
+                location,
+                backtrace: Backtrace::force_capture().to_string(),
+            };
+            PANIC.with(|current| *current.borrow_mut() = Some(panic));
+        }));
+    });
+}
+
+/// Respond to a request with `respond`, or with an internal server error
+/// if it panics
+pub(crate) fn catch_panic(respond: impl FnOnce() -> rouille::Response) -> rouille::Response {
+    install_hook();
+
+    match panic::catch_unwind(AssertUnwindSafe(respond)) {
+        Ok(response) => response,
+        Err(_) => {
+            let panic = PANIC.with(|current| current.borrow_mut().take());
+            let fields = match &panic {
+                Some(panic) => vec![
+                    ("panic", json!(panic.message)),
+                    ("location", json!(panic.location)),
+                    ("backtrace", json!(panic.backtrace)),
+                ],
+                None => vec![],
+            };
+            let _ = Log::error("request handler panicked", &fields);
+            ErrorResponse::internal()
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::testing::{TestNode, TestRequest};
+    use crate::{Kong, Kontrol, Method};
+
+    struct Explode;
+
+    impl Kontrol for Explode {
+        fn address(&self) -> String {
+            "/explode".to_string()
+        }
+        fn method(&self) -> Method {
+            Method::Get
+        }
+        fn kontrol(&self, _kong: &Kong) -> rouille::Response {
+            panic!("kaboom")
+        }
+    }
+
+    struct Hello;
+
+    impl Kontrol for Hello {
+        fn address(&self) -> String {
+            "/hello".to_string()
+        }
+        fn method(&self) -> Method {
+            Method::Get
+        }
+        fn kontrol(&self, _kong: &Kong) -> rouille::Response {
+            rouille::Response::text("hello")
+        }
+    }
+
+    #[test]
+    fn isolate_panics() {
+        let node = TestNode::minimal(vec![Box::new(Explode), Box::new(Hello)]).unwrap();
+
+        let response = node.send(TestRequest::get("/explode"));
+        response.assert_status(500);
+        assert_eq!(
+            response.json()["request_id"],
+            response.header("X-Request-Id").unwrap()
+        );
+        // the recorded panic was logged
+        assert!(PANIC.with(|current| current.borrow().is_none()));
+
+        // the node keeps serving
+        assert_eq!(
+            node.send(TestRequest::get("/hello"))
+                .assert_status(200)
+                .text(),
+            "hello"
+        );
+        node.send(TestRequest::get("/explode")).assert_status(500);
+    }
+}
@@ -0,0 +1,126 @@
+//! 🏷️ `kong` request IDs
+//!
+//! Every request handled by a node gets an ID: the `X-Request-Id` header
+//! of the request if it is a valid ID (for example one assigned by a
+//! load balancer), a newly generated ID otherwise. The ID is:
+//!
+//! - available to kontrollers as [`crate::Kong::request_id`],
+//! - a field of every log entry emitted while the request is handled,
+//!   and of its access log entry,
+//! - returned in the `X-Request-Id` response header and in the body of
+//!   [`crate::ErrorResponse`]s and [`crate::problem::Problem`]s built
+//!   while the request is handled. Other response bodies are never
+//!   changed.
+
+use crate::error_response::ERROR_RESPONSE_HEADER;
+use std::hash::{BuildHasher, RandomState};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Request and response header of the request ID
+pub const REQUEST_ID_HEADER: &str = "X-Request-Id";
+
+/// Maximum length of an accepted request ID
+const MAX_LENGTH: usize = 128;
+
+/// Number of generated IDs
+static GENERATED: AtomicU64 = AtomicU64::new(0);
+
+/// Generate a new request ID, 32 hexadecimal characters
+pub fn generate() -> String {
+    let count = GENERATED.fetch_add(1, Ordering::SeqCst);
+    let nanos = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|time| time.as_nanos())
+        .unwrap_or_default();
+
+    // randomly keyed hashes, unique per process and count
+    let high = RandomState::new().hash_one((count, nanos));
+    let low = RandomState::new().hash_one((nanos, count));
+    format!("{high:016x}{low:016x}")
+}
+
+/// Whether an ID sent by a client is accepted, IDs are written to logs
+/// so only short IDs of visible characters are
+pub fn is_valid(id: &str) -> bool {
+    !id.is_empty()
+        && id.len() <= MAX_LENGTH
+        && id.chars().all(|c| {
+            c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.' | ':' | '+' | '/' | '=')
+        })
+}
+
+/// ID of a request
+pub(crate) fn from_request(request: &rouille::Request) -> String {
+    match request.header(REQUEST_ID_HEADER) {
+        Some(id) if is_valid(id) => id.to_string(),
+        _ => generate(),
+    }
+}
+
+/// Add the request ID header to a response, error responses already
+/// have the ID in their body
+pub(crate) fn tag(mut response: rouille::Response, id: &str) -> rouille::Response {
+    response.headers.retain(|(name, _)| {
+        !name.eq_ignore_ascii_case(REQUEST_ID_HEADER)
+            && !name.eq_ignore_ascii_case(ERROR_RESPONSE_HEADER)
+    });
+    response.with_additional_header(REQUEST_ID_HEADER, id.to_string())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::log::RequestScope;
+    use crate::ErrorResponse;
+    use serde_json::{json, Value};
+    use std::io::Read;
+
+    #[test]
+    fn generate_and_accept_ids() {
+        let id = generate();
+        assert_eq!(id.len(), 32);
+        assert_ne!(id, generate());
+
+        assert!(is_valid("f81d4fae-7dec-11d0-a765-00a0c91e6bf6"));
+        assert!(!is_valid(""));
+        assert!(!is_valid("two words"));
+        assert!(!is_valid("line\nbreak"));
+        assert!(!is_valid(&"a".repeat(MAX_LENGTH + 1)));
+    }
+
+    fn body(response: rouille::Response) -> String {
+        let (mut data, _) = response.data.into_reader_and_size();
+        let mut body = String::new();
+        data.read_to_string(&mut body).unwrap();
+        body
+    }
+
+    #[test]
+    fn tag_error_responses() {
+        let scope = RequestScope::enter("abc");
+        let response = tag(ErrorResponse::not_found(), "abc");
+        drop(scope);
+
+        let header = |name: &str| {
+            response
+                .headers
+                .iter()
+                .find(|(n, _)| n == name)
+                .map(|(_, v)| v.to_string())
+        };
+        assert_eq!(header(REQUEST_ID_HEADER).as_deref(), Some("abc"));
+        assert_eq!(header(ERROR_RESPONSE_HEADER), None);
+        let error: Value = serde_json::from_str(&body(response)).unwrap();
+        assert_eq!(error["request_id"], "abc");
+        assert_eq!(error["error_message"], "Could not find resource");
+
+        let response = tag(rouille::Response::text("kong"), "abc");
+        assert_eq!(body(response), "kong");
+
+        // JSON errors of kontrollers are theirs
+        let error = json!({ "error": "taken" });
+        let response = tag(rouille::Response::json(&error).with_status_code(409), "abc");
+        assert_eq!(body(response), error.to_string());
+    }
+}
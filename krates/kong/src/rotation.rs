@@ -0,0 +1,336 @@
+//! 🔄 `kong` log file rotation
+//!
+//! Nodes run for months, so the `LOG` and access log files are rotated:
+//! when a file grows over `max_size` bytes, or on the first write of a
+//! new (UTC) day if `daily` is enabled, the file is renamed to `LOG.1`,
+//! the previous `LOG.1` to `LOG.2` and so on. Only the `keep` most
+//! recent rotated files are retained, and they are gzip compressed
+//! (`LOG.1.gz`) if `compress` is enabled. Compression runs on a
+//! background thread, so writes do not wait for it:
+//!
+//! ```toml
+//! [log_rotation]
+//! max_size = 10485760
+//! daily = true
+//! keep = 14
+//! compress = true
+//! ```
+//!
+//! When the files are rotated by an external tool like `logrotate`
+//! instead, send the node a `SIGHUP` after moving them: log files are
+//! reopened on their next write.
+
+use crate::defaults;
+use crate::konfig::LogRotationKonfig;
+use crate::KError;
+use chrono::{DateTime, NaiveDate, Utc};
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, prelude::*};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, OnceLock};
+use std::thread::{self, JoinHandle};
+
+/// Set by `SIGHUP`
+static HANGUP: OnceLock<Arc<AtomicBool>> = OnceLock::new();
+
+/// Incremented every time log files have to be reopened
+static GENERATION: AtomicU64 = AtomicU64::new(0);
+
+/// Reopen log files when the process receives `SIGHUP`
+pub fn reopen_on_sighup() -> Result<(), KError> {
+    if HANGUP.get().is_some() {
+        return Ok(());
+    }
+
+    let hangup = HANGUP.get_or_init(Default::default);
+    signal_hook::flag::register(signal_hook::consts::SIGHUP, hangup.clone())
+        .map_err(KError::Signal)?;
+    Ok(())
+}
+
+/// Reopen log files on their next write
+pub fn reopen() {
+    GENERATION.fetch_add(1, Ordering::SeqCst);
+}
+
+/// Current generation of log files
+fn generation() -> u64 {
+    if let Some(hangup) = HANGUP.get() {
+        if hangup.swap(false, Ordering::SeqCst) {
+            reopen();
+        }
+    }
+    GENERATION.load(Ordering::SeqCst)
+}
+
+/// 🔄 Log file that is rotated according to a policy
+pub struct RotatingFile {
+    path: PathBuf,
+    policy: Option<LogRotationKonfig>,
+    file: File,
+    /// Size of the file in bytes
+    size: u64,
+    /// Day the file was last written to
+    day: NaiveDate,
+    /// Generation of log files the file was opened in
+    generation: u64,
+    /// Compression of the last rotated file
+    compressing: Option<JoinHandle<()>>,
+}
+
+impl RotatingFile {
+    /// Open a log file in append mode, the file is created if it does
+    /// not exist. It is never rotated if there is no `policy`.
+    pub fn open(path: &Path, policy: Option<&LogRotationKonfig>) -> io::Result<Self> {
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        let metadata = file.metadata()?;
+        let day = match metadata.modified() {
+            Ok(modified) => DateTime::<Utc>::from(modified).date_naive(),
+            Err(_) => Utc::now().date_naive(),
+        };
+
+        Ok(RotatingFile {
+            path: path.to_path_buf(),
+            policy: policy.cloned(),
+            file,
+            size: metadata.len(),
+            day,
+            generation: generation(),
+            compressing: None,
+        })
+    }
+
+    /// Write a line, rotating or reopening the file first if needed
+    pub fn write_line(&mut self, line: &str) -> io::Result<()> {
+        let now = Utc::now();
+
+        if self.generation != generation() {
+            self.reopen()?;
+        }
+        if self.should_rotate(line.len() as u64 + 1, now) {
+            self.rotate()?;
+        }
+
+        writeln!(self.file, "{line}")?;
+        self.size += line.len() as u64 + 1;
+        self.day = now.date_naive();
+        Ok(())
+    }
+
+    /// Whether the file has to be rotated before writing `len` bytes
+    fn should_rotate(&self, len: u64, now: DateTime<Utc>) -> bool {
+        let Some(policy) = &self.policy else {
+            return false;
+        };
+        if self.size == 0 {
+            return false;
+        }
+
+        let too_large = policy
+            .max_size
+            .is_some_and(|max_size| self.size + len > max_size);
+        let new_day = policy.daily.unwrap_or(false) && now.date_naive() != self.day;
+        too_large || new_day
+    }
+
+    /// Rotate the file now
+    pub fn rotate(&mut self) -> io::Result<()> {
+        let keep = self
+            .policy
+            .as_ref()
+            .and_then(|policy| policy.keep)
+            .unwrap_or(defaults::LOG_KEEP);
+        let compress = self
+            .policy
+            .as_ref()
+            .and_then(|policy| policy.compress)
+            .unwrap_or(false);
+
+        self.file.sync_all()?;
+
+        // the rotated files can only be shifted once the last one is
+        // compressed
+        self.wait_for_compression();
+
+        // drop the oldest file, and shift the others
+        remove_rotated(&self.path, keep)?;
+        for n in (1..keep).rev() {
+            for extension in ["", ".gz"] {
+                let from = rotated(&self.path, n, extension);
+                if from.exists() {
+                    fs::rename(&from, rotated(&self.path, n + 1, extension))?;
+                }
+            }
+        }
+
+        if keep == 0 {
+            fs::remove_file(&self.path)?;
+        } else {
+            let rotated = rotated(&self.path, 1, "");
+            fs::rename(&self.path, &rotated)?;
+            if compress {
+                self.compressing = Some(thread::spawn(move || compress_rotated(&rotated)));
+            }
+        }
+
+        self.reopen()
+    }
+
+    /// Open the file at its path again, a rotated file may still be
+    /// compressed
+    fn reopen(&mut self) -> io::Result<()> {
+        let compressing = self.compressing.take();
+        *self = RotatingFile::open(&self.path, self.policy.as_ref())?;
+        self.compressing = compressing;
+        Ok(())
+    }
+
+    /// Wait until the last rotated file is compressed
+    fn wait_for_compression(&mut self) {
+        if let Some(compressing) = self.compressing.take() {
+            let _ = compressing.join();
+        }
+    }
+
+    /// Path of the file
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Make sure the written lines are on disk
+    pub fn sync(&self) -> io::Result<()> {
+        self.file.sync_all()
+    }
+}
+
+/// Path of the `n`th rotated file
+fn rotated(path: &Path, n: usize, extension: &str) -> PathBuf {
+    let mut rotated = path.as_os_str().to_owned();
+    rotated.push(format!(".{n}{extension}"));
+    PathBuf::from(rotated)
+}
+
+/// Remove the `n`th rotated file, compressed or not
+fn remove_rotated(path: &Path, n: usize) -> io::Result<()> {
+    for extension in ["", ".gz"] {
+        match fs::remove_file(rotated(path, n, extension)) {
+            Err(e) if e.kind() != io::ErrorKind::NotFound => return Err(e),
+            _ => {}
+        }
+    }
+    Ok(())
+}
+
+/// Replace a rotated file by its compressed version (`LOG.1.gz`), the
+/// file is kept as it is if it can not be compressed
+fn compress_rotated(path: &Path) {
+    let compressed = rotated_gz(path);
+    let result = gzip(path, &compressed).and_then(|()| fs::remove_file(path));
+    if let Err(error) = result {
+        let _ = fs::remove_file(&compressed);
+        // the LOG file may be the one rotated, so the error is not logged
+        eprintln!("Could not compress {}: {error}", path.display());
+    }
+}
+
+/// Path of the compressed version of a rotated file
+fn rotated_gz(path: &Path) -> PathBuf {
+    let mut compressed = path.as_os_str().to_owned();
+    compressed.push(".gz");
+    PathBuf::from(compressed)
+}
+
+/// Compress `from` into `to`
+fn gzip(from: &Path, to: &Path) -> io::Result<()> {
+    let mut input = File::open(from)?;
+    let mut encoder = GzEncoder::new(File::create(to)?, Compression::default());
+    io::copy(&mut input, &mut encoder)?;
+    encoder.finish()?.sync_all()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use flate2::read::GzDecoder;
+
+    fn dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("kong-rotation-{name}-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    fn rotate_by_size() {
+        let dir = dir("size");
+        let path = dir.join("LOG");
+        let policy = LogRotationKonfig {
+            max_size: Some(10),
+            daily: None,
+            keep: Some(2),
+            compress: None,
+        };
+
+        let mut log = RotatingFile::open(&path, Some(&policy)).unwrap();
+        for line in ["first", "second", "third", "fourth"] {
+            log.write_line(line).unwrap();
+        }
+
+        assert_eq!(fs::read_to_string(&path).unwrap(), "fourth\n");
+        assert_eq!(fs::read_to_string(dir.join("LOG.1")).unwrap(), "third\n");
+        assert_eq!(fs::read_to_string(dir.join("LOG.2")).unwrap(), "second\n");
+        assert!(!dir.join("LOG.3").exists());
+    }
+
+    #[test]
+    fn rotate_daily_and_compress() {
+        let dir = dir("daily");
+        let path = dir.join("LOG");
+        let policy = LogRotationKonfig {
+            max_size: None,
+            daily: Some(true),
+            keep: None,
+            compress: Some(true),
+        };
+
+        let mut log = RotatingFile::open(&path, Some(&policy)).unwrap();
+        log.write_line("yesterday").unwrap();
+        log.day = log.day.pred_opt().unwrap();
+        log.write_line("today").unwrap();
+        log.wait_for_compression();
+
+        assert_eq!(fs::read_to_string(&path).unwrap(), "today\n");
+        let mut rotated = String::new();
+        GzDecoder::new(File::open(dir.join("LOG.1.gz")).unwrap())
+            .read_to_string(&mut rotated)
+            .unwrap();
+        assert_eq!(rotated, "yesterday\n");
+        assert!(!dir.join("LOG.1").exists());
+
+        // the compressed file is shifted on the next rotation
+        log.day = log.day.pred_opt().unwrap();
+        log.write_line("tomorrow").unwrap();
+        log.wait_for_compression();
+        assert!(dir.join("LOG.1.gz").exists());
+        assert!(dir.join("LOG.2.gz").exists());
+        assert!(!dir.join("LOG.1").exists());
+    }
+
+    #[test]
+    fn reopen_moved_file() {
+        let dir = dir("reopen");
+        let path = dir.join("LOG");
+
+        let mut log = RotatingFile::open(&path, None).unwrap();
+        log.write_line("before").unwrap();
+        fs::rename(&path, dir.join("LOG.old")).unwrap();
+        reopen();
+        log.write_line("after").unwrap();
+
+        assert_eq!(fs::read_to_string(dir.join("LOG.old")).unwrap(), "before\n");
+        assert_eq!(fs::read_to_string(&path).unwrap(), "after\n");
+    }
+}
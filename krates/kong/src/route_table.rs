@@ -0,0 +1,167 @@
+//! 🧭 `kong` route table
+//!
+//! The route table lists the endpoints a node serves, with their
+//! method, address pattern, listener, authorization policy and rate
+//! limit. kroute logs it when the node starts, and serves it as JSON to
+//! the node [`crate::Konfig::admins`] if the endpoint is configured:
+//!
+//! ```toml
+//! admins = ["firephoenix"]
+//!
+//! [route_table]
+//! path = "/routes"
+//! listener = "admin"
+//! ```
+
+use crate::konfig::{Konfig, RateLimitKonfig, RouteTableKonfig};
+use crate::{AuthPolicy, Kong, Kontrol, KontrollerHandle, Method};
+use serde::Serialize;
+use std::fmt;
+use std::sync::{Arc, OnceLock};
+
+/// 🧭 Endpoint served by a node
+#[derive(Serialize, Clone, Debug)]
+pub struct RouteEntry {
+    /// HTTP method
+    pub method: Method,
+    /// Address pattern, with `:name` parameters and `*name` wildcards
+    pub address: String,
+    /// Name of the listener serving the endpoint, `None` for the main
+    /// listener
+    pub listener: Option<String>,
+    /// Authorization policy
+    pub auth: AuthPolicy,
+    /// Rate limit of the route, in addition to the global rate limit
+    pub rate_limit: Option<RateLimitKonfig>,
+}
+
+impl RouteEntry {
+    /// Describe the endpoint of a kontroller
+    pub fn new(kontroller: &dyn Kontrol, config: &Konfig) -> Self {
+        let address = kontroller.address();
+        let rate_limit = config
+            .route(&address)
+            .and_then(|route| route.rate_limit.clone());
+
+        RouteEntry {
+            method: kontroller.method(),
+            address,
+            listener: kontroller.listener(),
+            auth: kontroller.auth(),
+            rate_limit,
+        }
+    }
+}
+
+/// 🧭 Endpoints served by a node
+#[derive(Serialize, Clone, Debug, Default)]
+pub struct RouteTable {
+    /// Endpoints, in the order their kontrollers were registered
+    pub routes: Vec<RouteEntry>,
+}
+
+impl RouteTable {
+    /// Describe the endpoints of the kontrollers
+    pub fn new(kontrollers: &[KontrollerHandle], config: &Konfig) -> Self {
+        RouteTable {
+            routes: kontrollers
+                .iter()
+                .map(|kontroller| RouteEntry::new(kontroller.as_ref(), config))
+                .collect(),
+        }
+    }
+}
+
+impl fmt::Display for RouteEntry {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} {} [{:?}]", self.method, self.address, self.auth)?;
+        if let Some(limit) = &self.rate_limit {
+            write!(f, " {}/{}s", limit.requests, limit.period)?;
+        }
+        if let Some(listener) = &self.listener {
+            write!(f, " @{listener}")?;
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Display for RouteTable {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let routes: Vec<String> = self.routes.iter().map(|r| r.to_string()).collect();
+        write!(f, "{}", routes.join("\n"))
+    }
+}
+
+/// Kontroller serving the route table
+pub(crate) struct RouteTableKontroller {
+    config: RouteTableKonfig,
+    /// Set by kroute, once all kontrollers are known
+    table: Arc<OnceLock<RouteTable>>,
+}
+
+impl RouteTableKontroller {
+    /// Create the kontroller, the route table is set through the
+    /// returned handle
+    pub(crate) fn new(config: &RouteTableKonfig) -> (Self, Arc<OnceLock<RouteTable>>) {
+        let table = Arc::new(OnceLock::new());
+        let kontroller = RouteTableKontroller {
+            config: config.clone(),
+            table: table.clone(),
+        };
+        (kontroller, table)
+    }
+}
+
+impl Kontrol for RouteTableKontroller {
+    fn address(&self) -> String {
+        self.config.path.clone()
+    }
+
+    fn method(&self) -> Method {
+        Method::Get
+    }
+
+    fn listener(&self) -> Option<String> {
+        self.config.listener.clone()
+    }
+
+    fn auth(&self) -> AuthPolicy {
+        AuthPolicy::Admin
+    }
+
+    fn kontrol(&self, _kong: &Kong) -> rouille::Response {
+        match self.table.get() {
+            Some(table) => rouille::Response::json(table),
+            None => rouille::Response::json(&RouteTable::default()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn describe_routes() {
+        let config = crate::testing::konfig(
+            r#"
+            [routes."/routes".rate_limit]
+            requests = 10
+            period = 60
+            "#,
+        );
+        let (kontroller, _) = RouteTableKontroller::new(&RouteTableKonfig {
+            path: "/routes".to_string(),
+            listener: Some("admin".to_string()),
+        });
+        let kontrollers: Vec<KontrollerHandle> = vec![Box::new(kontroller)];
+
+        let table = RouteTable::new(&kontrollers, &config);
+        assert_eq!(table.to_string(), "GET /routes [Admin] 10/60s @admin");
+
+        let json = serde_json::to_value(&table).unwrap();
+        assert_eq!(json["routes"][0]["method"], "GET");
+        assert_eq!(json["routes"][0]["auth"], "admin");
+        assert_eq!(json["routes"][0]["rate_limit"]["requests"], 10);
+    }
+}
@@ -0,0 +1,460 @@
+//! 🧭 `kong` route table
+//!
+//! Kontrollers are grouped by address, so that a request for a known
+//! address with a method no kontroller handles is answered with
+//! `405 Method Not Allowed` rather than `404 Not Found`. `OPTIONS`
+//! requests are answered automatically and `HEAD` requests are served
+//! by the `GET` kontroller of the address, unless a kontroller handles
+//! these methods itself.
+
+use crate::{konfig::CorsKonfig, KError, Kontrol, Method};
+use route_recognizer::{Params, Router};
+
+/// Kontoller Handle
+pub type KontrollerHandle = Box<dyn Kontrol + std::marker::Sync + std::marker::Send + 'static>;
+
+/// Kontrollers of a single address
+struct Endpoint {
+    address: String,
+    kontrollers: Vec<KontrollerHandle>,
+}
+
+impl Endpoint {
+    /// Kontroller handling `method`
+    fn kontroller(&self, method: &Method) -> Option<&KontrollerHandle> {
+        self.kontrollers.iter().find(|k| k.method() == *method)
+    }
+
+    /// CORS policy of the kontrollers
+    fn cors(&self) -> Option<&CorsKonfig> {
+        self.kontrollers.iter().find_map(|k| k.cors())
+    }
+
+    /// Value of the `Allow` header, the methods of the kontrollers
+    /// followed by the methods kroute handles automatically
+    fn allow(&self) -> String {
+        let mut methods: Vec<Method> = self.kontrollers.iter().map(|k| k.method()).collect();
+
+        if methods.contains(&Method::Get) && !methods.contains(&Method::Head) {
+            methods.push(Method::Head);
+        }
+        if !methods.contains(&Method::Options) {
+            methods.push(Method::Options);
+        }
+
+        methods
+            .iter()
+            .map(|m| m.to_string())
+            .collect::<Vec<String>>()
+            .join(", ")
+    }
+
+    /// Route of a request with a method no kontroller handles
+    fn not_allowed(&self) -> Route<'_> {
+        Route::NotAllowed {
+            address: self.address.clone(),
+            allow: self.allow(),
+            cors: self.cors(),
+        }
+    }
+}
+
+/// Outcome of routing a request
+pub(crate) enum Route<'a> {
+    /// The kontroller handling the request
+    Kontroller(&'a KontrollerHandle, Params),
+    /// `HEAD` request served by the `GET` kontroller, the response body
+    /// has to be stripped
+    Head(&'a KontrollerHandle, Params),
+    /// `OPTIONS` request, answered with the `Allow` header value (or
+    /// as a CORS preflight request) for the kontroller `address`
+    Options {
+        /// Address of the kontrollers
+        address: String,
+        /// `Allow` header value
+        allow: String,
+        /// CORS policy of the kontrollers
+        cors: Option<&'a CorsKonfig>,
+    },
+    /// No kontroller of the address `address` handles the method, the
+    /// `Allow` header value lists the methods that are handled
+    NotAllowed {
+        /// Address of the kontrollers
+        address: String,
+        /// `Allow` header value
+        allow: String,
+        /// CORS policy of the kontrollers
+        cors: Option<&'a CorsKonfig>,
+    },
+    /// No kontroller handles the address
+    NotFound,
+}
+
+/// 🧭 Route table of a listener
+#[derive(Default)]
+pub(crate) struct Routes {
+    router: Router<Endpoint>,
+}
+
+impl Routes {
+    /// Create the route table of the provided kontrollers. Invalid
+    /// addresses, duplicate kontrollers and addresses that match the
+    /// same requests are reported as [`KError::InvalidRoutes`].
+    pub(crate) fn new(kontrollers: Vec<KontrollerHandle>) -> Result<Self, KError> {
+        let mut problems = Vec::new();
+
+        // group kontrollers by address, keeping their order
+        let mut endpoints: Vec<(String, Endpoint)> = Vec::new();
+        for kontroller in kontrollers {
+            let address = kontroller.address();
+            let method = kontroller.method();
+
+            if let Err(problem) = validate_address(&address) {
+                problems.push(format!("{method} {address}: {problem}"));
+                continue;
+            }
+
+            match endpoints.iter_mut().find(|(a, _)| *a == address) {
+                Some((_, endpoint)) => {
+                    if endpoint.kontroller(&method).is_some() {
+                        problems.push(format!("{method} {address}: duplicate kontroller"));
+                    }
+                    endpoint.kontrollers.push(kontroller)
+                }
+                None => {
+                    // a request must not match more than one address
+                    let shadowed = endpoints.iter().find(|(a, _)| overlap(a, &address));
+                    if let Some((other, _)) = shadowed {
+                        problems.push(format!("{method} {address}: conflicts with {other}"));
+                        continue;
+                    }
+
+                    endpoints.push((
+                        address.clone(),
+                        Endpoint {
+                            address,
+                            kontrollers: vec![kontroller],
+                        },
+                    ))
+                }
+            }
+        }
+
+        if !problems.is_empty() {
+            return Err(KError::InvalidRoutes(problems));
+        }
+
+        let mut router = Router::new();
+        for (address, endpoint) in endpoints {
+            router.add(&address, endpoint);
+        }
+
+        Ok(Routes { router })
+    }
+
+    /// Route a request
+    pub(crate) fn recognize(&self, method: &str, url: &str) -> Route<'_> {
+        let matched = match self.router.recognize(url) {
+            Ok(matched) => matched,
+            Err(_) => return Route::NotFound,
+        };
+        let endpoint = matched.handler();
+        let params = matched.params().clone();
+
+        // no kontroller handles a method that was never parsed
+        let method = match Method::lookup(method) {
+            Some(method) => method,
+            None => return endpoint.not_allowed(),
+        };
+
+        if let Some(kontroller) = endpoint.kontroller(&method) {
+            return Route::Kontroller(kontroller, params);
+        }
+
+        match method {
+            Method::Head => match endpoint.kontroller(&Method::Get) {
+                Some(kontroller) => Route::Head(kontroller, params),
+                None => endpoint.not_allowed(),
+            },
+            Method::Options => Route::Options {
+                address: endpoint.address.clone(),
+                allow: endpoint.allow(),
+                cors: endpoint.cors(),
+            },
+            _ => endpoint.not_allowed(),
+        }
+    }
+}
+
+/// Check the syntax of a kontroller address: it starts with `/`, has
+/// no empty segments or query string, and parameters (`:name`) and
+/// wildcards (`*name`) are whole segments with a unique name, wildcards
+/// only as the last segment.
+fn validate_address(address: &str) -> Result<(), String> {
+    let path = address
+        .strip_prefix('/')
+        .ok_or("address must start with `/`")?;
+    if path.contains('?') || path.contains('#') {
+        return Err("address can not contain a query string or fragment".to_string());
+    }
+
+    let segments: Vec<&str> = path.split('/').collect();
+    let mut names = Vec::new();
+    for (i, segment) in segments.iter().enumerate() {
+        let last = i == segments.len() - 1;
+
+        match segment.chars().next() {
+            // a trailing slash is allowed
+            None if last => {}
+            None => return Err("address contains an empty segment".to_string()),
+            Some(kind @ (':' | '*')) => {
+                let name = &segment[1..];
+                if name.is_empty() || !name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_') {
+                    return Err(format!("invalid parameter name `{segment}`"));
+                }
+                if names.contains(&name) {
+                    return Err(format!("duplicate parameter name `{name}`"));
+                }
+                if kind == '*' && !last {
+                    return Err(format!("wildcard `{segment}` must be the last segment"));
+                }
+                names.push(name);
+            }
+            Some(_) if segment.contains([':', '*']) => {
+                return Err(format!("parameter in the middle of segment `{segment}`"));
+            }
+            Some(_) => {}
+        }
+    }
+
+    Ok(())
+}
+
+/// Whether two addresses match some of the same requests: parameters
+/// (`:name`) match any segment, a literal one included, and wildcards
+/// (`*name`) any number of remaining segments
+fn overlap(a: &str, b: &str) -> bool {
+    let mut a = a.split('/');
+    let mut b = b.split('/');
+
+    loop {
+        match (a.next(), b.next()) {
+            (None, None) => return true,
+            (None, Some(_)) | (Some(_), None) => return false,
+            (Some(x), Some(y)) => {
+                if x.starts_with('*') || y.starts_with('*') {
+                    // the other address needs a non-empty segment left
+                    return !x.is_empty() && !y.is_empty();
+                }
+                let param = x.starts_with(':') || y.starts_with(':');
+                if x != y && !(param && !x.is_empty() && !y.is_empty()) {
+                    return false;
+                }
+            }
+        }
+    }
+}
+
+/// Strip the body of a response to a `HEAD` request, the
+/// `Content-Length` of the `GET` response is kept
+pub(crate) fn strip_body(mut response: rouille::Response) -> rouille::Response {
+    let (_, size) = response.data.into_reader_and_size();
+    response.data = match size {
+        Some(size) => rouille::ResponseBody::from_reader_and_size(std::io::empty(), size),
+        None => rouille::ResponseBody::empty(),
+    };
+    response
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::Kong;
+    use std::io::Read;
+
+    struct TestKontroller {
+        address: String,
+        method: Method,
+    }
+
+    impl Kontrol for TestKontroller {
+        fn address(&self) -> String {
+            self.address.clone()
+        }
+        fn method(&self) -> Method {
+            self.method
+        }
+        fn kontrol(&self, _kong: &Kong) -> rouille::Response {
+            rouille::Response::text("kong")
+        }
+    }
+
+    fn kontroller(address: &str, method: Method) -> KontrollerHandle {
+        Box::new(TestKontroller {
+            address: address.to_string(),
+            method,
+        })
+    }
+
+    fn routes() -> Routes {
+        Routes::new(vec![
+            kontroller("/users/:id", Method::Get),
+            kontroller("/users/:id", Method::Put),
+            kontroller("/login", Method::Post),
+            kontroller("/users", Method::Patch),
+            kontroller("/users", "QUERY".parse().unwrap()),
+        ])
+        .unwrap()
+    }
+
+    fn problems(kontrollers: Vec<KontrollerHandle>) -> Vec<String> {
+        match Routes::new(kontrollers) {
+            Err(KError::InvalidRoutes(problems)) => problems,
+            _ => panic!("routes should be invalid"),
+        }
+    }
+
+    #[test]
+    fn duplicate_and_conflicting_routes() {
+        let problems = problems(vec![
+            kontroller("/users/:id", Method::Get),
+            kontroller("/users/:id", Method::Get),
+            kontroller("/users/:name", Method::Delete),
+            kontroller("/users/me", Method::Get),
+            kontroller("/files/*path", Method::Get),
+            kontroller("/files/:name", Method::Put),
+            kontroller("/files/archive/:year", Method::Get),
+            kontroller("/users/:id/posts", Method::Get),
+            kontroller("/users/", Method::Get),
+        ]);
+
+        assert_eq!(
+            problems,
+            vec![
+                "GET /users/:id: duplicate kontroller",
+                "DELETE /users/:name: conflicts with /users/:id",
+                "GET /users/me: conflicts with /users/:id",
+                "PUT /files/:name: conflicts with /files/*path",
+                "GET /files/archive/:year: conflicts with /files/*path",
+            ]
+        );
+    }
+
+    #[test]
+    fn overlapping_addresses() {
+        assert!(overlap("/a/:x", "/a/b"));
+        assert!(overlap("/a/b", "/a/:x"));
+        assert!(overlap("/a/:x/c", "/a/:y/c"));
+        assert!(overlap("/files/*path", "/files/:name"));
+        assert!(overlap("/files/:name/raw", "/files/*path"));
+        assert!(overlap("/*any", "/users"));
+
+        assert!(!overlap("/a/:x", "/b/:x"));
+        assert!(!overlap("/a/:x", "/a/:x/c"));
+        assert!(!overlap("/a/:x", "/a/"));
+        assert!(!overlap("/files/*path", "/files/"));
+        assert!(!overlap("/files/*path", "/files"));
+        assert!(!overlap("/users", "/users/"));
+    }
+
+    #[test]
+    fn invalid_addresses() {
+        let problems = problems(vec![
+            kontroller("users", Method::Get),
+            kontroller("/users//:id", Method::Get),
+            kontroller("/users/:", Method::Get),
+            kontroller("/users/:user-id", Method::Get),
+            kontroller("/users/id:id", Method::Get),
+            kontroller("/:id/posts/:id", Method::Get),
+            kontroller("/files/*path/raw", Method::Get),
+            kontroller("/search?q", Method::Get),
+        ]);
+
+        assert_eq!(
+            problems,
+            vec![
+                "GET users: address must start with `/`",
+                "GET /users//:id: address contains an empty segment",
+                "GET /users/:: invalid parameter name `:`",
+                "GET /users/:user-id: invalid parameter name `:user-id`",
+                "GET /users/id:id: parameter in the middle of segment `id:id`",
+                "GET /:id/posts/:id: duplicate parameter name `id`",
+                "GET /files/*path/raw: wildcard `*path` must be the last segment",
+                "GET /search?q: address can not contain a query string or fragment",
+            ]
+        );
+    }
+
+    #[test]
+    fn kontroller_by_method() {
+        let routes = routes();
+
+        match routes.recognize("PUT", "/users/42") {
+            Route::Kontroller(kontroller, params) => {
+                assert!(kontroller.method() == Method::Put);
+                assert_eq!(params.find("id"), Some("42"));
+            }
+            _ => panic!("PUT /users/42 should be routed to a kontroller"),
+        }
+        match routes.recognize("QUERY", "/users") {
+            Route::Kontroller(kontroller, _) => {
+                assert_eq!(kontroller.method().to_string(), "QUERY")
+            }
+            _ => panic!("QUERY /users should be routed to a kontroller"),
+        }
+        assert!(matches!(
+            routes.recognize("GET", "/nowhere"),
+            Route::NotFound
+        ));
+    }
+
+    #[test]
+    fn method_not_allowed() {
+        let routes = routes();
+
+        match routes.recognize("DELETE", "/users/42") {
+            Route::NotAllowed { allow, .. } => assert_eq!(allow, "GET, PUT, HEAD, OPTIONS"),
+            _ => panic!("DELETE /users/42 should not be allowed"),
+        }
+        match routes.recognize("HEAD", "/login") {
+            Route::NotAllowed { allow, .. } => assert_eq!(allow, "POST, OPTIONS"),
+            _ => panic!("HEAD /login should not be allowed"),
+        }
+        match routes.recognize("GET", "/users") {
+            Route::NotAllowed { allow, .. } => assert_eq!(allow, "PATCH, QUERY, OPTIONS"),
+            _ => panic!("GET /users should not be allowed"),
+        }
+        assert!(matches!(
+            routes.recognize("BREW", "/login"),
+            Route::NotAllowed { .. }
+        ));
+    }
+
+    #[test]
+    fn automatic_options_and_head() {
+        let routes = routes();
+
+        match routes.recognize("OPTIONS", "/login") {
+            Route::Options { address, allow, .. } => {
+                assert_eq!(address, "/login");
+                assert_eq!(allow, "POST, OPTIONS");
+            }
+            _ => panic!("OPTIONS /login should be answered automatically"),
+        }
+        match routes.recognize("HEAD", "/users/42") {
+            Route::Head(kontroller, _) => assert!(kontroller.method() == Method::Get),
+            _ => panic!("HEAD /users/42 should be served by the GET kontroller"),
+        }
+    }
+
+    #[test]
+    fn head_response_has_no_body() {
+        let response = strip_body(rouille::Response::text("kong"));
+        let (mut data, size) = response.data.into_reader_and_size();
+
+        let mut body = String::new();
+        data.read_to_string(&mut body).unwrap();
+        assert_eq!(body, "");
+        assert_eq!(size, Some(4));
+    }
+}
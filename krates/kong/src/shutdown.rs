@@ -0,0 +1,151 @@
+//! 🛑 `kong` node shutdown
+//!
+//! A kong node stops when its [`Shutdown`] is stopped, either by a
+//! `SIGTERM`/`SIGINT` signal or programmatically:
+//!
+//! ```no_run
+//! use kong::{kroute_until, Shutdown};
+//!
+//! let shutdown = Shutdown::new();
+//! let stop = shutdown.clone();
+//!
+//! std::thread::spawn(move || {
+//!     std::thread::sleep(std::time::Duration::from_secs(60));
+//!     stop.stop();
+//! });
+//!
+//! kroute_until(vec![], shutdown).expect("kong node failed");
+//! ```
+//!
+//! When the node stops, its listeners stop accepting connections,
+//! requests that are in-flight are given until the `shutdown_timeout`
+//! to finish and the log is flushed. If every request finished, the
+//! kontrollers are dropped (so resources they own, like database
+//! connections, are closed). Requests that did not finish keep running
+//! on their threads, holding the kontrollers and the node: the process
+//! must exit once [`crate::kroute_until`] returns.
+//!
+//! A second `SIGTERM`/`SIGINT` exits the process right away, without
+//! waiting for in-flight requests.
+
+use crate::KError;
+use std::io;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+
+/// 🛑 Shutdown signal of a kong node
+#[derive(Clone, Default)]
+pub struct Shutdown {
+    stopped: Arc<AtomicBool>,
+    /// Woken when the node is stopped programmatically
+    wakers: Arc<Mutex<Vec<Waker>>>,
+}
+
+impl Shutdown {
+    /// Create a shutdown signal that is only stopped programmatically
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// Create a shutdown signal that is stopped when the process
+    /// receives `SIGTERM` or `SIGINT`, the process exits with status
+    /// `1` when it receives one of them again. Signals only set the
+    /// flag, [`crate::kroute_until`] calls [`Shutdown::stop`] once it
+    /// sees it, to wake the listeners.
+    pub fn on_signals() -> Result<Self, KError> {
+        let shutdown = Shutdown::new();
+
+        for signal in [signal_hook::consts::SIGTERM, signal_hook::consts::SIGINT] {
+            // registered first, so it only sees the flag set by an
+            // earlier signal
+            signal_hook::flag::register_conditional_shutdown(signal, 1, shutdown.stopped.clone())
+                .map_err(KError::Signal)?;
+            signal_hook::flag::register(signal, shutdown.stopped.clone())
+                .map_err(KError::Signal)?;
+        }
+
+        Ok(shutdown)
+    }
+
+    /// Stop the node
+    pub fn stop(&self) {
+        self.stopped.store(true, Ordering::SeqCst);
+        for waker in self.wakers.lock().unwrap_or_else(|e| e.into_inner()).iter() {
+            waker.wake();
+        }
+    }
+
+    /// Whether the node has been stopped
+    pub fn is_stopped(&self) -> bool {
+        self.stopped.load(Ordering::SeqCst)
+    }
+
+    /// Wake `waker` when the node is stopped
+    pub(crate) fn wake_on_stop(&self, waker: Waker) {
+        if self.is_stopped() {
+            waker.wake();
+        }
+        self.wakers
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .push(waker);
+    }
+}
+
+/// Wakes a listener that waits for connections, see
+/// [`crate::listener`]. On unix systems both ends of a socket pair are
+/// kept, the listener waits for the reading end to be readable.
+#[derive(Clone)]
+pub(crate) struct Waker {
+    #[cfg(unix)]
+    pair: Arc<(
+        std::os::unix::net::UnixStream,
+        std::os::unix::net::UnixStream,
+    )>,
+}
+
+#[cfg(unix)]
+impl Waker {
+    /// Create a waker
+    pub(crate) fn new() -> io::Result<Waker> {
+        let (reader, writer) = std::os::unix::net::UnixStream::pair()?;
+        reader.set_nonblocking(true)?;
+        writer.set_nonblocking(true)?;
+        Ok(Waker {
+            pair: Arc::new((reader, writer)),
+        })
+    }
+
+    /// Wake the listener, wake-ups that are not handled yet are
+    /// merged
+    pub(crate) fn wake(&self) {
+        use std::io::Write;
+        // a full socket buffer already holds a wake-up
+        let _ = (&self.pair.1).write(&[1]);
+    }
+
+    /// Forget the wake-ups received so far
+    pub(crate) fn clear(&self) {
+        use std::io::Read;
+        let mut buffer = [0u8; 64];
+        while matches!((&self.pair.0).read(&mut buffer), Ok(n) if n > 0) {}
+    }
+
+    /// File descriptor that is readable once woken
+    pub(crate) fn fd(&self) -> std::os::unix::io::RawFd {
+        use std::os::unix::io::AsRawFd;
+        self.pair.0.as_raw_fd()
+    }
+}
+
+/// Listeners of other platforms check for wake-ups at intervals
+#[cfg(not(unix))]
+impl Waker {
+    pub(crate) fn new() -> io::Result<Waker> {
+        Ok(Waker {})
+    }
+
+    pub(crate) fn wake(&self) {}
+
+    pub(crate) fn clear(&self) {}
+}
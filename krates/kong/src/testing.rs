@@ -0,0 +1,459 @@
+//! 🧪 `kong` kontroller testing
+//!
+//! A [`TestNode`] is a kong node built from an in-memory configuration
+//! and kontrollers. Requests are dispatched through the same pipeline
+//! as in a running node (header and body limits, rate limiting, static
+//! files, routing, CORS, authorization, input validation), without
+//! opening any socket:
+//!
+//! ```
+//! use kong::testing::{TestNode, TestRequest};
+//! use kong::{json, server, AuthPolicy, Kong, Kontrol, Method};
+//!
+//! struct Whoami;
+//!
+//! impl Kontrol for Whoami {
+//!     fn address(&self) -> String {
+//!         "/whoami".to_string()
+//!     }
+//!     fn method(&self) -> Method {
+//!         Method::Get
+//!     }
+//!     fn auth(&self) -> AuthPolicy {
+//!         AuthPolicy::Kpassport
+//!     }
+//!     fn kontrol(&self, kong: &Kong) -> server::Response {
+//!         let username = &kong.kpassport.as_ref().unwrap().content.username;
+//!         server::Response::json(&json!({ "username": username }))
+//!     }
+//! }
+//!
+//! let node = TestNode::minimal(vec![Box::new(Whoami)]).unwrap();
+//!
+//! node.send(TestRequest::get("/whoami")).assert_status(401);
+//! node.send(TestRequest::get("/whoami").kpassport(&node, "firephoenix"))
+//!     .assert_status(200)
+//!     .assert_json(&json!({ "username": "firephoenix" }));
+//! ```
+//!
+//! Settings a test is about are added to the [`MINIMAL_CONFIG`] with
+//! [`konfig`], for example `TestNode::new(konfig("[health]"), vec![])`.
+//! Unless the configuration sets a `working_directory`, every test
+//! node gets its own temporary working directory.
+
+use crate::health::{self, HealthCheck};
+use crate::konfig::Konfig;
+use crate::kroute::{builtin_kontrollers, handle};
+use crate::rate_limit::RateLimiter;
+use crate::router::Routes;
+use crate::{KError, Kong, KontrollerHandle};
+use krypto::kpassport::Kpassport;
+use serde::Serialize;
+use std::io::Read;
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+
+/// Settings every node needs, the configuration of tests adds its own
+/// settings after them
+pub const MINIMAL_CONFIG: &str = r#"
+port = 7878
+auth_cookie_name = "kpassport"
+hostname = "kong.test"
+secret_key = "secret"
+"#;
+
+/// Configuration of the [`MINIMAL_CONFIG`] followed by the TOML
+/// `settings`, panics if they are invalid
+pub fn konfig(settings: &str) -> Konfig {
+    toml::from_str(&format!("{MINIMAL_CONFIG}{settings}")).expect("invalid test configuration")
+}
+
+/// Number of test nodes created by the process, used to give every
+/// node its own working directory
+static NODES: AtomicUsize = AtomicUsize::new(0);
+
+/// 🧪 In-process kong node
+pub struct TestNode {
+    routes: Routes,
+    config: Konfig,
+    checks: Vec<Arc<dyn HealthCheck>>,
+    kong: Mutex<Kong>,
+    rate_limiter: Mutex<RateLimiter>,
+}
+
+impl TestNode {
+    /// Create a test node, kontrollers of all listeners are served
+    pub fn new(mut config: Konfig, mut kontrollers: Vec<KontrollerHandle>) -> Result<Self, KError> {
+        if config.working_directory.is_none() {
+            let node = NODES.fetch_add(1, Ordering::SeqCst);
+            let working_dir =
+                std::env::temp_dir().join(format!("kong-test-{}-{node}/", std::process::id()));
+            let _ = std::fs::remove_dir_all(&working_dir);
+            config.working_directory = Some(working_dir.to_string_lossy().into_owned());
+        }
+
+        config.validate()?;
+        builtin_kontrollers(&mut kontrollers, &config);
+        let checks = health::node_checks(&kontrollers, &config);
+        let routes = Routes::new(kontrollers)?;
+
+        Ok(TestNode {
+            routes,
+            config: config.clone(),
+            checks,
+            kong: Mutex::new(Kong::new(config)),
+            rate_limiter: Mutex::new(RateLimiter::new()),
+        })
+    }
+
+    /// Create a test node with the [`MINIMAL_CONFIG`]
+    pub fn minimal(kontrollers: Vec<KontrollerHandle>) -> Result<Self, KError> {
+        TestNode::new(konfig(""), kontrollers)
+    }
+
+    /// Create a test node from a TOML configuration
+    pub fn from_toml(config: &str, kontrollers: Vec<KontrollerHandle>) -> Result<Self, KError> {
+        let config = toml::from_str(config).map_err(|source| KError::config_parse(None, source))?;
+        TestNode::new(config, kontrollers)
+    }
+
+    /// Dispatch a request through the node
+    pub fn send(&self, mut request: TestRequest) -> TestResponse {
+        // bodies are announced by their length, as connections do
+        if !request.body.is_empty()
+            && !request
+                .headers
+                .iter()
+                .any(|(name, _)| name.eq_ignore_ascii_case("Content-Length"))
+        {
+            let length = request.body.len().to_string();
+            request.headers.push(("Content-Length".to_string(), length));
+        }
+        let request = rouille::Request::fake_http_from(
+            request.remote_addr,
+            request.method,
+            request.url,
+            request.headers,
+            request.body,
+        );
+        let response = handle(
+            &request,
+            &self.routes,
+            &self.config,
+            &self.checks,
+            &self.kong,
+            &self.rate_limiter,
+        );
+
+        let (mut data, _) = response.data.into_reader_and_size();
+        let mut body = Vec::new();
+        let _ = data.read_to_end(&mut body);
+
+        TestResponse {
+            status: response.status_code,
+            headers: response
+                .headers
+                .into_iter()
+                .map(|(name, value)| (name.into_owned(), value.into_owned()))
+                .collect(),
+            body,
+        }
+    }
+
+    /// Signed kpassport of `username`, issued by this node.
+    ///
+    /// Panics if `username` can not be used in a kpassport.
+    pub fn kpassport(&self, username: &str) -> String {
+        let kong = self.kong.lock().unwrap_or_else(|e| e.into_inner());
+        let mut kpassport = Kpassport::new_unsigned(username, &kong.config.hostname)
+            .expect("invalid kpassport username or hostname");
+        kpassport
+            .sign(&kong.config.secret_key)
+            .expect("could not sign kpassport");
+        kpassport.export().expect("could not export kpassport")
+    }
+
+    /// Name of the cookie holding the kpassport
+    pub fn auth_cookie_name(&self) -> String {
+        let kong = self.kong.lock().unwrap_or_else(|e| e.into_inner());
+        kong.config.auth_cookie_name.clone()
+    }
+}
+
+/// 🧪 Synthetic request
+pub struct TestRequest {
+    method: String,
+    url: String,
+    headers: Vec<(String, String)>,
+    body: Vec<u8>,
+    remote_addr: SocketAddr,
+}
+
+impl TestRequest {
+    /// Request with any method
+    pub fn new(method: &str, url: &str) -> Self {
+        TestRequest {
+            method: method.to_string(),
+            url: url.to_string(),
+            headers: Vec::new(),
+            body: Vec::new(),
+            remote_addr: SocketAddr::from(([127, 0, 0, 1], 40000)),
+        }
+    }
+
+    /// GET request
+    pub fn get(url: &str) -> Self {
+        TestRequest::new("GET", url)
+    }
+
+    /// POST request
+    pub fn post(url: &str) -> Self {
+        TestRequest::new("POST", url)
+    }
+
+    /// PUT request
+    pub fn put(url: &str) -> Self {
+        TestRequest::new("PUT", url)
+    }
+
+    /// PATCH request
+    pub fn patch(url: &str) -> Self {
+        TestRequest::new("PATCH", url)
+    }
+
+    /// DELETE request
+    pub fn delete(url: &str) -> Self {
+        TestRequest::new("DELETE", url)
+    }
+
+    /// Add a header
+    pub fn header(mut self, name: &str, value: &str) -> Self {
+        self.headers.push((name.to_string(), value.to_string()));
+        self
+    }
+
+    /// Set the raw request body
+    pub fn body(mut self, body: &[u8]) -> Self {
+        self.body = body.to_vec();
+        self
+    }
+
+    /// Set a JSON request body
+    pub fn json<T: Serialize>(self, body: &T) -> Self {
+        let body = serde_json::to_vec(body).expect("could not serialize JSON body");
+        self.header("Content-Type", "application/json").body(&body)
+    }
+
+    /// Add a cookie
+    pub fn cookie(mut self, name: &str, value: &str) -> Self {
+        let cookie = format!("{name}={value}");
+        match self
+            .headers
+            .iter_mut()
+            .find(|(n, _)| n.eq_ignore_ascii_case("Cookie"))
+        {
+            Some((_, cookies)) => *cookies = format!("{cookies}; {cookie}"),
+            None => self.headers.push(("Cookie".to_string(), cookie)),
+        }
+        self
+    }
+
+    /// Authenticate the request with a kpassport of `username`, issued
+    /// by `node`
+    pub fn kpassport(self, node: &TestNode, username: &str) -> Self {
+        self.cookie(&node.auth_cookie_name(), &node.kpassport(username))
+    }
+
+    /// Set the address of the client
+    pub fn remote_addr(mut self, remote_addr: SocketAddr) -> Self {
+        self.remote_addr = remote_addr;
+        self
+    }
+}
+
+/// 🧪 Response of a [`TestNode`]
+#[derive(Debug)]
+pub struct TestResponse {
+    /// HTTP status code
+    pub status: u16,
+    /// Response headers
+    pub headers: Vec<(String, String)>,
+    /// Response body
+    pub body: Vec<u8>,
+}
+
+impl TestResponse {
+    /// Value of a header
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(n, _)| n.eq_ignore_ascii_case(name))
+            .map(|(_, v)| v.as_str())
+    }
+
+    /// Body as text
+    pub fn text(&self) -> String {
+        String::from_utf8_lossy(&self.body).into_owned()
+    }
+
+    /// Body as JSON, panics if the body is not JSON
+    pub fn json(&self) -> serde_json::Value {
+        serde_json::from_slice(&self.body)
+            .unwrap_or_else(|_| panic!("response body is not JSON: {}", self.text()))
+    }
+
+    /// Assert the status code
+    #[track_caller]
+    pub fn assert_status(&self, status: u16) -> &Self {
+        assert_eq!(
+            self.status,
+            status,
+            "unexpected status, body: {}",
+            self.text()
+        );
+        self
+    }
+
+    /// Assert the value of a header
+    #[track_caller]
+    pub fn assert_header(&self, name: &str, value: &str) -> &Self {
+        assert_eq!(self.header(name), Some(value), "unexpected `{name}` header");
+        self
+    }
+
+    /// Assert the JSON body
+    #[track_caller]
+    pub fn assert_json(&self, json: &serde_json::Value) -> &Self {
+        assert_eq!(&self.json(), json, "unexpected JSON body");
+        self
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{json, AuthPolicy, Kontrol, Method};
+
+    struct EchoKontroller;
+
+    impl Kontrol for EchoKontroller {
+        fn address(&self) -> String {
+            "/echo/:name".to_string()
+        }
+        fn method(&self) -> Method {
+            Method::Post
+        }
+        fn auth(&self) -> AuthPolicy {
+            AuthPolicy::Kpassport
+        }
+        fn get_input(&self, request: &rouille::Request) -> Option<serde_json::Value> {
+            rouille::input::json_input(request).ok()
+        }
+        fn validate(
+            &self,
+            input: Option<serde_json::Value>,
+        ) -> Result<Option<serde_json::Value>, ()> {
+            input.map(Some).ok_or(())
+        }
+        fn kontrol(&self, kong: &Kong) -> rouille::Response {
+            let name = kong.url_parameters.as_ref().unwrap().find("name").unwrap();
+            rouille::Response::json(&json!({
+                "name": name,
+                "input": kong.input,
+                "page": kong.query.as_ref().unwrap().get("page"),
+            }))
+        }
+    }
+
+    fn node() -> TestNode {
+        TestNode::minimal(vec![Box::new(EchoKontroller)]).unwrap()
+    }
+
+    #[test]
+    fn full_pipeline() {
+        let node = node();
+
+        node.send(TestRequest::post("/echo/kong").json(&json!({ "a": 1 })))
+            .assert_status(401);
+        node.send(TestRequest::get("/echo/kong"))
+            .assert_status(405)
+            .assert_header("Allow", "POST, OPTIONS");
+        node.send(TestRequest::get("/nowhere")).assert_status(404);
+
+        node.send(
+            TestRequest::post("/echo/kong?page=2")
+                .kpassport(&node, "firephoenix")
+                .cookie("theme", "dark")
+                .json(&json!({ "a": 1 })),
+        )
+        .assert_status(200)
+        .assert_json(&json!({ "name": "kong", "input": { "a": 1 }, "page": "2" }));
+
+        // invalid input
+        let response = node.send(TestRequest::post("/echo/kong").kpassport(&node, "firephoenix"));
+        response.assert_status(400);
+        let id = response.header("X-Request-Id").unwrap();
+        assert_eq!(response.json()["request_id"], id);
+
+        // request ID of the client
+        node.send(TestRequest::get("/nowhere").header("X-Request-Id", "client-id-1"))
+            .assert_header("X-Request-Id", "client-id-1");
+    }
+
+    #[test]
+    fn body_size_limit() {
+        let config = r#"
+            max_body_size = 64
+
+            [routes."/echo/:name"]
+            max_body_size = 8
+            "#;
+        let node = TestNode::new(konfig(config), vec![Box::new(EchoKontroller)]).unwrap();
+
+        node.send(
+            TestRequest::post("/echo/kong")
+                .kpassport(&node, "firephoenix")
+                .json(&json!({ "a": 1 })),
+        )
+        .assert_status(200);
+        node.send(
+            TestRequest::post("/echo/kong")
+                .kpassport(&node, "firephoenix")
+                .json(&json!({ "a": "too large" })),
+        )
+        .assert_status(413);
+    }
+
+    #[test]
+    fn cors_on_errors() {
+        let config = r#"
+            [rate_limit]
+            requests = 2
+            period = 60
+
+            [cors]
+            origins = ["https://app.kong.test"]
+            credentials = true
+            "#;
+        let node = TestNode::new(konfig(config), vec![Box::new(EchoKontroller)]).unwrap();
+        let origin = "https://app.kong.test";
+
+        node.send(TestRequest::get("/nowhere").header("Origin", origin))
+            .assert_status(404)
+            .assert_header("Access-Control-Allow-Origin", origin);
+        node.send(TestRequest::get("/echo/kong").header("Origin", origin))
+            .assert_status(405)
+            .assert_header("Access-Control-Allow-Origin", origin)
+            .assert_header("Access-Control-Allow-Credentials", "true");
+        node.send(TestRequest::get("/nowhere").header("Origin", origin))
+            .assert_status(429)
+            .assert_header("Access-Control-Allow-Origin", origin);
+
+        let wildcard = config.replace("https://app.kong.test", "*");
+        assert!(matches!(
+            TestNode::new(konfig(&wildcard), vec![]),
+            Err(KError::InvalidConfig { .. })
+        ));
+    }
+}
@@ -0,0 +1,409 @@
+//! 🧯 `kong` login brute-force protection
+//!
+//! Password verification with `scrypt` is deliberately expensive, so
+//! a login kontroller that can be called without limits is both a
+//! brute-force and a denial of service target. `LoginThrottle` keeps
+//! count of failed login attempts per __username__ and per __client
+//! IP address__:
+//!
+//! - After every failure the next attempt is delayed, the delay
+//!   doubles with every failure (exponential backoff) up to a maximum.
+//! - After too many failures the account (or IP address) is locked
+//!   for the lockout period.
+//! - Counters are persisted in the working directory, so restarting
+//!   the node does not reset them.
+//!
+//! ```no_run
+//! use kong::{ErrorResponse, Kong};
+//!
+//! fn login(kong: &Kong, username: &str, password_ok: bool) -> kong::server::Response {
+//!     let ip = kong.remote_addr.map(|addr| addr.ip());
+//!
+//!     if let Some(retry_after) = kong.login_throttle.retry_after(username, ip) {
+//!         return ErrorResponse::too_many_requests(retry_after);
+//!     }
+//!
+//!     if password_ok {
+//!         kong.login_throttle.success(username, ip);
+//!         kong::server::Response::text("welcome")
+//!     } else {
+//!         kong.login_throttle.failure(username, ip);
+//!         ErrorResponse::unauthorized()
+//!     }
+//! }
+//! ```
+
+use crate::log::Log;
+use crate::lru::LruMap;
+use crate::{defaults, konfig::LoginThrottleKonfig};
+use chrono::{DateTime, Duration, Utc};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::path::{Path, PathBuf};
+use std::sync::{Mutex, MutexGuard};
+use std::time::Instant;
+
+/// Failed login attempts of a single username or IP address
+#[derive(Serialize, Deserialize, Clone, Debug)]
+struct Attempts {
+    /// Number of consecutive failures
+    failures: u32,
+    /// Time of the last failure
+    last_failure: DateTime<Utc>,
+}
+
+/// Counters and their persistence state
+struct Counters {
+    /// Failed attempts keyed by `user:{username}` and `ip:{address}`,
+    /// least recently failed first
+    attempts: LruMap<String, Attempts>,
+    /// Whether the counters changed since they were last written
+    dirty: bool,
+    /// Last time the counters were written
+    persisted: Instant,
+}
+
+/// 🧯 Login brute-force protection
+pub struct LoginThrottle {
+    /// File where the counters are persisted
+    path: PathBuf,
+    /// Failures allowed per username before it is locked
+    max_failures: u32,
+    /// Failures allowed per IP address before it is locked
+    ip_max_failures: u32,
+    /// Delay after the first failure, in seconds
+    base_delay: u64,
+    /// Upper bound of the backoff delay, in seconds
+    max_delay: u64,
+    /// How long a locked username or IP address stays locked, in seconds
+    lockout: u64,
+    counters: Mutex<Counters>,
+}
+
+impl LoginThrottle {
+    /// Create a login throttle that persists its counters in the
+    /// provided file, counters already stored in the file are loaded.
+    /// At most `max_tracked` usernames and IP addresses are tracked,
+    /// when there are more the least recently failed are forgotten.
+    pub fn new(path: &Path, config: &LoginThrottleKonfig) -> Self {
+        let mut attempts = LruMap::new(config.max_tracked.unwrap_or(defaults::LOGIN_MAX_TRACKED));
+        let mut loaded: Vec<(String, Attempts)> = load(path).into_iter().collect();
+        loaded.sort_by_key(|(_, a)| a.last_failure);
+        for (key, a) in loaded {
+            attempts.get_or_insert_with(key, || a);
+        }
+
+        LoginThrottle {
+            path: path.to_path_buf(),
+            max_failures: config.max_failures.unwrap_or(defaults::LOGIN_MAX_FAILURES),
+            ip_max_failures: config
+                .ip_max_failures
+                .unwrap_or(defaults::LOGIN_IP_MAX_FAILURES),
+            base_delay: config.base_delay.unwrap_or(defaults::LOGIN_BASE_DELAY),
+            max_delay: config.max_delay.unwrap_or(defaults::LOGIN_MAX_DELAY),
+            lockout: config.lockout.unwrap_or(defaults::LOGIN_LOCKOUT),
+            counters: Mutex::new(Counters {
+                attempts,
+                dirty: false,
+                persisted: Instant::now(),
+            }),
+        }
+    }
+
+    /// Number of seconds the client has to wait before it is allowed
+    /// to attempt to login again, `None` if the attempt is allowed.
+    pub fn retry_after(&self, username: &str, ip: Option<IpAddr>) -> Option<u64> {
+        let now = Utc::now();
+        let mut counters = self.lock();
+        self.forget_expired(&mut counters, now);
+
+        let user_wait = counters
+            .attempts
+            .peek(&user_key(username))
+            .and_then(|a| self.wait(a, self.max_failures, now));
+        let ip_wait = ip
+            .and_then(|ip| counters.attempts.peek(&ip_key(ip)))
+            .and_then(|a| self.wait(a, self.ip_max_failures, now));
+
+        self.persist_later(&mut counters);
+        user_wait.max(ip_wait)
+    }
+
+    /// Record a failed login attempt
+    pub fn failure(&self, username: &str, ip: Option<IpAddr>) {
+        let now = Utc::now();
+        let mut counters = self.lock();
+
+        let mut keys = vec![user_key(username)];
+        if let Some(ip) = ip {
+            keys.push(ip_key(ip));
+        }
+
+        for key in keys {
+            let entry = counters.attempts.get_or_insert_with(key, || Attempts {
+                failures: 0,
+                last_failure: now,
+            });
+            entry.failures = entry.failures.saturating_add(1);
+            entry.last_failure = now;
+        }
+
+        counters.dirty = true;
+        self.persist_later(&mut counters);
+    }
+
+    /// Record a successful login, this resets the username's counter.
+    /// The IP address counter is kept, so that an attacker that owns
+    /// one account cannot use it to reset the counter of the IP address.
+    pub fn success(&self, username: &str, _ip: Option<IpAddr>) {
+        let mut counters = self.lock();
+
+        if counters.attempts.remove(&user_key(username)).is_some() {
+            counters.dirty = true;
+            self.persist_later(&mut counters);
+        }
+    }
+
+    /// Write the counters to the throttle file now, if they changed.
+    /// Counters are otherwise written at most every few seconds, and
+    /// when the throttle is dropped.
+    pub fn flush(&self) {
+        let mut counters = self.lock();
+        if counters.dirty {
+            self.persist(&mut counters);
+        }
+    }
+
+    /// Seconds left before the next attempt is allowed
+    fn wait(&self, attempts: &Attempts, max_failures: u32, now: DateTime<Utc>) -> Option<u64> {
+        let delay = if attempts.failures >= max_failures {
+            self.lockout
+        } else {
+            // 1, 2, 4, 8 ... times the base delay
+            let exponent = attempts.failures.saturating_sub(1).min(32);
+            self.base_delay
+                .saturating_mul(1u64 << exponent)
+                .min(self.max_delay)
+        };
+
+        let allowed_at = attempts.last_failure + seconds(delay);
+        let remaining = (allowed_at - now).num_seconds();
+
+        if remaining > 0 {
+            Some(remaining as u64)
+        } else {
+            None
+        }
+    }
+
+    /// Forget failures that are older than the lockout period, this is
+    /// also what unlocks a locked username or IP address. Counters are
+    /// ordered by their last failure, so only expired ones are visited.
+    fn forget_expired(&self, counters: &mut Counters, now: DateTime<Utc>) {
+        let lockout = seconds(self.lockout);
+        while let Some((_, a)) = counters.attempts.oldest() {
+            if a.last_failure + lockout > now {
+                break;
+            }
+            counters.attempts.pop_oldest();
+            counters.dirty = true;
+        }
+    }
+
+    /// Write the counters if they changed and were not written recently
+    fn persist_later(&self, counters: &mut Counters) {
+        let interval = std::time::Duration::from_secs(defaults::LOGIN_PERSIST_INTERVAL);
+        if counters.dirty && counters.persisted.elapsed() >= interval {
+            self.persist(counters);
+        }
+    }
+
+    /// Write counters to the throttle file. They are written to a
+    /// temporary file first, that is renamed over the throttle file, so
+    /// an interrupted write never leaves a truncated file behind.
+    fn persist(&self, counters: &mut Counters) {
+        let attempts: HashMap<&String, &Attempts> = counters.attempts.iter().collect();
+        let temporary = self.path.with_extension("tmp");
+        let written = serde_json::to_string(&attempts)
+            .map_err(std::io::Error::from)
+            .and_then(|json| std::fs::write(&temporary, json))
+            .and_then(|_| std::fs::rename(&temporary, &self.path));
+
+        // XXX: A failure to persist the counters should not prevent
+        // users from logging in, the in memory counters still apply.
+        if let Err(error) = written {
+            let _ = Log::warn(
+                "could not write login throttle counters",
+                &[
+                    ("path", json!(self.path.display().to_string())),
+                    ("error", json!(error.to_string())),
+                ],
+            );
+        }
+        counters.dirty = false;
+        counters.persisted = Instant::now();
+    }
+
+    fn lock(&self) -> MutexGuard<'_, Counters> {
+        self.counters
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+impl Drop for LoginThrottle {
+    fn drop(&mut self) {
+        self.flush();
+    }
+}
+
+/// Counters stored in the throttle file, a missing file holds none.
+/// An unreadable or corrupted file is logged, and the node starts with
+/// empty counters rather than not starting at all.
+fn load(path: &Path) -> HashMap<String, Attempts> {
+    let loaded = std::fs::read_to_string(path)
+        .map_err(|error| error.to_string())
+        .and_then(|json| serde_json::from_str(&json).map_err(|error| error.to_string()));
+
+    match loaded {
+        Ok(attempts) => attempts,
+        Err(_) if !path.exists() => HashMap::new(),
+        Err(error) => {
+            let _ = Log::warn(
+                "could not load login throttle counters, starting with none",
+                &[
+                    ("path", json!(path.display().to_string())),
+                    ("error", json!(error)),
+                ],
+            );
+            HashMap::new()
+        }
+    }
+}
+
+fn user_key(username: &str) -> String {
+    format!("user:{username}")
+}
+
+fn ip_key(ip: IpAddr) -> String {
+    format!("ip:{ip}")
+}
+
+fn seconds(secs: u64) -> Duration {
+    Duration::seconds(i64::try_from(secs).unwrap_or(i64::MAX / 1000))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn throttle(name: &str, config: &LoginThrottleKonfig) -> LoginThrottle {
+        let path = std::env::temp_dir().join(format!("kong-throttle-{name}"));
+        let _ = std::fs::remove_file(&path);
+        LoginThrottle::new(&path, config)
+    }
+
+    fn config() -> LoginThrottleKonfig {
+        LoginThrottleKonfig {
+            max_failures: Some(3),
+            ip_max_failures: Some(5),
+            base_delay: Some(10),
+            max_delay: Some(25),
+            lockout: Some(600),
+            max_tracked: Some(4),
+        }
+    }
+
+    #[test]
+    fn exponential_backoff() {
+        let throttle = throttle("backoff", &config());
+        let ip: Option<IpAddr> = Some("10.0.0.1".parse().unwrap());
+
+        assert_eq!(throttle.retry_after("natty_dread", ip), None);
+
+        throttle.failure("natty_dread", ip);
+        let wait = throttle.retry_after("natty_dread", ip).unwrap();
+        assert!(wait > 8 && wait <= 10);
+
+        throttle.failure("natty_dread", ip);
+        let wait = throttle.retry_after("natty_dread", ip).unwrap();
+        assert!(wait > 18 && wait <= 20);
+
+        // other usernames from other IP addresses are not affected
+        assert_eq!(throttle.retry_after("firephoenix", None), None);
+    }
+
+    #[test]
+    fn lockout_and_success() {
+        let throttle = throttle("lockout", &config());
+
+        for _ in 0..3 {
+            throttle.failure("natty_dread", None);
+        }
+        let wait = throttle.retry_after("natty_dread", None).unwrap();
+        assert!(wait > 590 && wait <= 600);
+
+        throttle.success("natty_dread", None);
+        assert_eq!(throttle.retry_after("natty_dread", None), None);
+    }
+
+    #[test]
+    fn ip_address_is_throttled_across_usernames() {
+        let throttle = throttle("ip", &config());
+        let ip: Option<IpAddr> = Some("10.0.0.2".parse().unwrap());
+
+        for username in ["a", "b", "c", "d", "e"] {
+            throttle.failure(username, ip);
+        }
+
+        let wait = throttle.retry_after("f", ip).unwrap();
+        assert!(wait > 590 && wait <= 600);
+        assert_eq!(throttle.retry_after("f", None), None);
+    }
+
+    #[test]
+    fn counters_survive_restarts() {
+        let path = std::env::temp_dir().join("kong-throttle-restart");
+        let _ = std::fs::remove_file(&path);
+
+        let throttle = LoginThrottle::new(&path, &config());
+        throttle.failure("natty_dread", None);
+        drop(throttle);
+
+        let throttle = LoginThrottle::new(&path, &config());
+        assert!(throttle.retry_after("natty_dread", None).is_some());
+    }
+
+    #[test]
+    fn tracked_keys_are_capped() {
+        let throttle = throttle("cap", &config());
+
+        throttle.failure("natty_dread", None);
+        for username in ["a", "b", "c", "d"] {
+            throttle.failure(username, None);
+        }
+
+        // the least recently failed username was forgotten
+        assert_eq!(throttle.lock().attempts.len(), 4);
+        assert_eq!(throttle.retry_after("natty_dread", None), None);
+        assert!(throttle.retry_after("d", None).is_some());
+    }
+
+    #[test]
+    fn corrupted_file_is_replaced() {
+        let path = std::env::temp_dir().join("kong-throttle-corrupted");
+        std::fs::write(&path, "{\"user:natty_dread\": {\"fail").unwrap();
+
+        let throttle = LoginThrottle::new(&path, &config());
+        assert_eq!(throttle.retry_after("natty_dread", None), None);
+        throttle.failure("natty_dread", None);
+        throttle.flush();
+
+        assert!(!path.with_extension("tmp").exists());
+        let json = std::fs::read_to_string(&path).unwrap();
+        let attempts: HashMap<String, Attempts> = serde_json::from_str(&json).unwrap();
+        assert_eq!(attempts["user:natty_dread"].failures, 1);
+    }
+}
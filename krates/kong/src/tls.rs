@@ -0,0 +1,162 @@
+//! 🔐 `kong` TLS termination
+//!
+//! HTTPS listeners resolve the certificate of every handshake through
+//! a [`Certificate`]. Its certificate and private key files are watched
+//! and, once they change, the new certificate is swapped in: new
+//! connections are served with it, open connections keep the one they
+//! were established with and the listener is never restarted. A new
+//! certificate that can not be loaded is ignored.
+
+use crate::connection::Socket;
+use crate::konfig::TlsKonfig;
+use crate::log::Log;
+use crate::{KError, Shutdown};
+use rustls::server::{ClientHello, ResolvesServerCert};
+use rustls::sign::{self, CertifiedKey};
+use rustls::{ServerConfig, ServerConnection, StreamOwned};
+use std::fs;
+use std::io::{self, BufReader, Write};
+use std::net::TcpStream;
+use std::sync::{Arc, RwLock};
+use std::time::{Duration, Instant, SystemTime};
+
+/// How often the certificate files are checked for changes
+const RELOAD_INTERVAL: Duration = Duration::from_secs(1);
+
+/// How often the watcher checks whether the node is shutting down
+const POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// 🔐 Certificate of an HTTPS listener
+pub(crate) struct Certificate {
+    current: RwLock<Arc<CertifiedKey>>,
+}
+
+impl Certificate {
+    /// Load the certificate chain and private key of `tls`
+    pub(crate) fn load(tls: &TlsKonfig) -> Result<Arc<Certificate>, KError> {
+        Ok(Arc::new(Certificate {
+            current: RwLock::new(Arc::new(certified_key(tls)?)),
+        }))
+    }
+
+    /// TLS configuration of the connections served with the certificate
+    pub(crate) fn server_config(self: &Arc<Self>) -> Arc<ServerConfig> {
+        Arc::new(
+            ServerConfig::builder()
+                .with_safe_defaults()
+                .with_no_client_auth()
+                .with_cert_resolver(self.clone()),
+        )
+    }
+
+    /// Swap in the certificate of `tls` whenever its files change,
+    /// until `shutdown` is stopped
+    pub(crate) fn watch(&self, tls: &TlsKonfig, shutdown: &Shutdown) {
+        let mut last_modified = modified(tls);
+        let mut checked = Instant::now();
+
+        while !shutdown.is_stopped() {
+            std::thread::sleep(POLL_INTERVAL);
+            if checked.elapsed() < RELOAD_INTERVAL {
+                continue;
+            }
+            checked = Instant::now();
+
+            let current = modified(tls);
+            if current == last_modified {
+                continue;
+            }
+            last_modified = current;
+
+            match certified_key(tls) {
+                Ok(key) => {
+                    *self.current.write().unwrap_or_else(|e| e.into_inner()) = Arc::new(key);
+                    let _ = Log::log("TLS certificate changed, reloaded");
+                }
+                Err(error) => {
+                    let _ = Log::warn(
+                        "Invalid TLS certificate, keeping the current certificate",
+                        &[("error", serde_json::json!(error.to_string()))],
+                    );
+                }
+            }
+        }
+    }
+}
+
+impl ResolvesServerCert for Certificate {
+    fn resolve(&self, _client_hello: ClientHello<'_>) -> Option<Arc<CertifiedKey>> {
+        Some(
+            self.current
+                .read()
+                .unwrap_or_else(|e| e.into_inner())
+                .clone(),
+        )
+    }
+}
+
+/// TLS connection served by an HTTPS listener
+impl Socket for StreamOwned<ServerConnection, TcpStream> {
+    fn set_timeout(&self, timeout: Duration) -> io::Result<()> {
+        self.sock.set_timeout(timeout)
+    }
+
+    fn close(&mut self) {
+        self.conn.send_close_notify();
+        let _ = self.flush();
+    }
+}
+
+/// Last modification time of the certificate and private key files
+fn modified(tls: &TlsKonfig) -> (Option<SystemTime>, Option<SystemTime>) {
+    let modified = |path: &str| fs::metadata(path).and_then(|m| m.modified()).ok();
+    (modified(&tls.certificate), modified(&tls.private_key))
+}
+
+/// Read the PEM encoded certificate chain and private key (PKCS#8 or
+/// RSA) of `tls`
+fn certified_key(tls: &TlsKonfig) -> Result<CertifiedKey, KError> {
+    let read = |path: &str| {
+        let file = fs::File::open(path).map_err(|source| KError::Tls {
+            reason: format!("could not read {path}"),
+            source: Some(source),
+        })?;
+        rustls_pemfile::read_all(&mut BufReader::new(file)).map_err(|source| KError::Tls {
+            reason: format!("could not parse {path}"),
+            source: Some(source),
+        })
+    };
+
+    let certificates: Vec<rustls::Certificate> = read(&tls.certificate)?
+        .into_iter()
+        .filter_map(|item| match item {
+            rustls_pemfile::Item::X509Certificate(der) => Some(rustls::Certificate(der)),
+            _ => None,
+        })
+        .collect();
+    if certificates.is_empty() {
+        return Err(KError::Tls {
+            reason: format!("no certificate in {}", tls.certificate),
+            source: None,
+        });
+    }
+
+    let private_key = read(&tls.private_key)?
+        .into_iter()
+        .find_map(|item| match item {
+            rustls_pemfile::Item::PKCS8Key(der) | rustls_pemfile::Item::RSAKey(der) => {
+                Some(rustls::PrivateKey(der))
+            }
+            _ => None,
+        })
+        .ok_or_else(|| KError::Tls {
+            reason: format!("no private key in {}", tls.private_key),
+            source: None,
+        })?;
+    let signing_key = sign::any_supported_type(&private_key).map_err(|_| KError::Tls {
+        reason: format!("unsupported private key in {}", tls.private_key),
+        source: None,
+    })?;
+
+    Ok(CertifiedKey::new(certificates, signing_key))
+}
@@ -21,18 +21,17 @@ impl Validate {
             return false;
         }
 
-        let chars: Vec<char> = username.chars().collect();
         let mut underscore_count = 0;
 
-        for i in 0..chars.len() {
+        for (i, c) in username.chars().enumerate() {
             // Username cannot start with a underscore (_)
-            if i == 0 && chars[i] == '_' {
+            if i == 0 && c == '_' {
                 return false;
             }
 
             // Username can only contain letters, numbers, and one underscore
-            if chars[i] != '_' {
-                if !chars[i].is_ascii_alphanumeric() {
+            if c != '_' {
+                if !c.is_ascii_alphanumeric() {
                     return false;
                 }
             } else {
@@ -85,6 +84,8 @@ pub enum ValidationError {
     Email,
     /// Password validation error
     Password,
+    /// Query string parameter validation error
+    Query(String),
 }
 
 impl std::error::Error for ValidationError {}
@@ -96,6 +97,7 @@ impl fmt::Display for ValidationError {
             ValidationError::Username => write!(f, "Invalid username"),
             ValidationError::Email => write!(f, "Invalid email"),
             ValidationError::Password => write!(f, "Invalid password"),
+            ValidationError::Query(e) => write!(f, "Invalid query parameter: {e}"),
         }
     }
 }
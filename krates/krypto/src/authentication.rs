@@ -4,14 +4,14 @@
 //! - [x] Usernames are __alphanumeric__ (letters A-Z, numbers 0-9) with the exception of __underscores__.
 //! - [x] Password should be at least 10 characters long
 //! - [x] The user's password is __hashed__ with `scrypt` and the hash
-//! is stored in the database.
+//!   is stored in the database.
 //! - [ ] The username may be claimed by a suspended or deactivated
-//! account. Suspended and deactivated usernames are not immediately
-//! available for use.
+//!   account. Suspended and deactivated usernames are not immediately
+//!   available for use.
 //! - [ ] After the user has been authenticated, they are handed a
-//! __passport__ that should send with requests to private resources.
-//!- [ ] `kong` allows  a reserve list of usernames that
-//!can never be used by end-users (e.g __admin__)
+//!   __passport__ that should send with requests to private resources.
+//! - [ ] `kong` allows  a reserve list of usernames that
+//!   can never be used by end-users (e.g __admin__)
 //!
 //! #### Attaching to HTTP requests
 //! Clients that request to access protected routes, need to provide a
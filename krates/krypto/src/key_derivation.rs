@@ -1,6 +1,7 @@
 //! Management of cryptographic keys
 
 use chrono::prelude::*;
+use std::fmt;
 
 /// The context of a key derivation
 pub(crate) struct Context<'a> {
@@ -10,10 +11,10 @@ pub(crate) struct Context<'a> {
     pub(crate) timestamp: DateTime<Utc>,
 }
 
-impl<'a> Context<'a> {
+impl fmt::Display for Context<'_> {
     /// Convert context to string
-    pub(crate) fn to_string(&self) -> String {
-        format!("{} {} kpassport-token", self.host, self.timestamp)
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} {} kpassport-token", self.host, self.timestamp)
     }
 }
 
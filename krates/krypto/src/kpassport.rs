@@ -10,22 +10,22 @@
 //!           15B      45B      33B        32B
 //! ```
 //! - __USERNAME__: The username of the entity the `kpassport` issued to.
-//! The maximum length is 15bytes because `kong` account username have a
-//! maximum length of 15 characters.
+//!   The maximum length is 15bytes because `kong` account username have a
+//!   maximum length of 15 characters.
 //! - __HOST__: The issuer of the `kpassport` can be a, the maximum length
-//! 45bytes because that is the maximum IPv6 string length.  But any
-//! string identifier can be used not just IP addresses as long as it
-//! fits into 45bytes
+//!   45bytes because that is the maximum IPv6 string length.  But any
+//!   string identifier can be used not just IP addresses as long as it
+//!   fits into 45bytes
 //! - The __USERNAME__ and __HOST__ are seperated by the `@` characters (1byte)
 //! - __TIMESTAMP__: The time the `kpassport` was issued, it is 3bytes long
 //! - __SIGNATURE__: `blake3::keyed_hash()` of the `host`, `username` and `timestamp`,
-//! it is 32bytes long.
+//!   it is 32bytes long.
 //!
 //! #### Why use blake3
 //!
 //! - Fast
 //! - Pure __Rust__ implementation written by the creators of blake3
-//! (`kong` is also written in Rust).
+//!   (`kong` is also written in Rust).
 //!
 //! #### HTTPS
 //!
@@ -182,13 +182,10 @@ impl Content {
 
     /// Get the index of a `kpassport` username and host seperator
     fn get_seperator_index(kpassport_bytes: Vec<u8>) -> Result<usize, KryptoError> {
-        for i in 0..kpassport_bytes.len() {
-            if kpassport_bytes[i] == b"@"[0] {
-                return Ok(i);
-            }
-        }
-
-        Err(KryptoError::MissingUsernameHostSeperator)
+        kpassport_bytes
+            .iter()
+            .position(|byte| *byte == b"@"[0])
+            .ok_or(KryptoError::MissingUsernameHostSeperator)
     }
 }
 
@@ -277,6 +274,7 @@ impl Kpassport {
     }
 
     /// Derive a `kpassport` from a base64 encoded string
+    #[allow(clippy::should_implement_trait)]
     pub fn from_str(kpassport_str: &str) -> Result<Kpassport, KryptoError> {
         let kpassport_bytes = Kpassport::as_bytes(kpassport_str)?;
         let content_bytes = Kpassport::get_content_bytes(&kpassport_bytes)?;
@@ -463,7 +461,7 @@ mod test {
 
         match kpassport {
             Ok(kp) => {
-                if let Some(sig) = kp.signature {
+                if kp.signature.is_some() {
                     panic!("kpassport should not be signed");
                 }
             }
@@ -510,7 +508,7 @@ mod test {
             panic!("Should error because wrong key was provided");
         }
 
-        let mut kpassport = Kpassport::new_unsigned("My App", "my_username").unwrap();
+        let kpassport = Kpassport::new_unsigned("My App", "my_username").unwrap();
         let validation = kpassport.validate(key);
 
         if validation.is_ok() {